@@ -0,0 +1,281 @@
+//! The game's assets: textures, sprite sheets, fonts, and sounds. Each is
+//! listed by id in the manifest, and only loaded once it's first asked for,
+//! after which it's kept for anything else that asks for it.
+
+use failure::{err_msg, Error, ResultExt};
+use fnv::FnvHashMap as Map;
+use image;
+use rusttype::Font;
+use settings::{find_upwards, Settings};
+use sprite::{SpriteSheet, Sprites};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+use toml;
+
+
+/// A shared reference to a loaded asset.
+pub type Handle<T> = Rc<T>;
+
+/// Turns an image file into a texture.
+pub type TextureLoader<T> = Box<dyn FnMut(&Path) -> Result<T, String>>;
+
+/// Every asset in the manifest, with textures of type `T`.
+pub struct Assets<T> {
+    /// Directory of the manifest, which the paths in it are relative to.
+    dir:          PathBuf,
+    manifest:     Manifest,
+    load_texture: TextureLoader<T>,
+    /// Assets that have been loaded so far, by id.
+    textures:     Map<String, Handle<T>>,
+    sheets:       Map<String, Handle<SpriteSheet<T>>>,
+    fonts:        Map<String, Handle<Vec<u8>>>,
+    sounds:       Map<String, Handle<Vec<u8>>>,
+}
+
+/// Paths of every asset, by kind and then by id.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    textures: BTreeMap<String, PathBuf>,
+    #[serde(default)]
+    sheets:   BTreeMap<String, PathBuf>,
+    #[serde(default)]
+    fonts:    BTreeMap<String, PathBuf>,
+    #[serde(default)]
+    sounds:   BTreeMap<String, PathBuf>,
+}
+
+#[derive(Debug, Fail)]
+pub enum AssetError {
+    #[fail(display = "The asset manifest has no {} with the id {:?}",
+           kind, id)]
+    UnknownId {
+        kind: &'static str,
+        id:   String,
+    },
+    #[fail(display = "The {} {:?} is missing: there's no file at {:?}",
+           kind, id, path)]
+    MissingFile {
+        kind: &'static str,
+        id:   String,
+        path: PathBuf,
+    },
+    #[fail(display = "Couldn't load the texture {:?} from {:?}: {}",
+           id, path, reason)]
+    BadTexture {
+        id:     String,
+        path:   PathBuf,
+        reason: String,
+    },
+    #[fail(display = "The font {:?} isn't a font that can be drawn with", id)]
+    BadFont {
+        id: String,
+    },
+}
+
+
+impl<T> Assets<T> {
+    /// Reads the manifest, which is looked for as with `find_upwards`, but
+    /// doesn't load any assets yet.
+    pub fn open(manifest:     &Path,
+                load_texture: TextureLoader<T>) -> Result<Self, Error>
+    {
+        let path = find_upwards(manifest)
+            .context("Couldn't find the asset manifest")?;
+        let manifest = toml::from_str(&read_file(&path)?)
+            .with_context(|_| format!("Couldn't load {}", path.display()))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("")).to_owned();
+
+        Ok(Assets {
+            dir,
+            manifest,
+            load_texture,
+            textures: Map::default(),
+            sheets:   Map::default(),
+            fonts:    Map::default(),
+            sounds:   Map::default(),
+        })
+    }
+
+    pub fn texture(&mut self, id: &str) -> Result<Handle<T>, Error> {
+        if let Some(texture) = self.textures.get(id) {
+            return Ok(texture.clone());
+        }
+
+        let path = find(&self.dir, "texture", &self.manifest.textures, id)?;
+        let texture = (self.load_texture)(&path).map_err(|reason| {
+            AssetError::BadTexture { id: id.to_owned(), path, reason }
+        })?;
+        let texture = Rc::new(texture);
+        self.textures.insert(id.to_owned(), texture.clone());
+
+        Ok(texture)
+    }
+
+    pub fn sheet(&mut self,
+                 id: &str) -> Result<Handle<SpriteSheet<T>>, Error>
+    {
+        if let Some(sheet) = self.sheets.get(id) {
+            return Ok(sheet.clone());
+        }
+
+        let paths = &self.manifest.sheets;
+        let path = find(&self.dir, "sprite sheet", paths, id)?;
+        let sheet = SpriteSheet::load(&path, |texture| self.texture(texture))?;
+        let sheet = Rc::new(sheet);
+        self.sheets.insert(id.to_owned(), sheet.clone());
+
+        Ok(sheet)
+    }
+
+    /// The contents of a font file.
+    pub fn font(&mut self, id: &str) -> Result<Handle<Vec<u8>>, Error> {
+        let (dir, paths) = (&self.dir, &self.manifest.fonts);
+
+        cached_bytes(&mut self.fonts, id, || find(dir, "font", paths, id))
+    }
+
+    /// The contents of a sound file.
+    pub fn sound(&mut self, id: &str) -> Result<Handle<Vec<u8>>, Error> {
+        let (dir, paths) = (&self.dir, &self.manifest.sounds);
+
+        cached_bytes(&mut self.sounds, id, || find(dir, "sound", paths, id))
+    }
+
+    /// Loads every asset in the manifest, returning how many there are, and
+    /// what went wrong with those that couldn't be loaded.
+    pub fn load_all(&mut self) -> (usize, Vec<Error>) {
+        let ids = |paths: &BTreeMap<String, PathBuf>| -> Vec<String> {
+            paths.keys().cloned().collect()
+        };
+        let textures = ids(&self.manifest.textures);
+        let sheets = ids(&self.manifest.sheets);
+        let fonts = ids(&self.manifest.fonts);
+        let sounds = ids(&self.manifest.sounds);
+        let count = textures.len() + sheets.len() + fonts.len() + sounds.len();
+
+        let mut problems = Vec::new();
+        for id in textures {
+            problems.extend(self.texture(&id).err());
+        }
+        for id in sheets {
+            problems.extend(self.sheet(&id).err());
+        }
+        for id in fonts {
+            problems.extend(self.font(&id).err());
+        }
+        for id in sounds {
+            problems.extend(self.sound(&id).err());
+        }
+
+        (count, problems)
+    }
+}
+
+
+/// Loads every asset in the manifest without a window, along with the
+/// sprites, printing whatever goes wrong. Images are decoded and fonts are
+/// parsed, but neither is turned into textures, since that takes a window.
+pub fn check(settings: &Settings) -> Result<(), Error> {
+    let decode: TextureLoader<()> = Box::new(|path| {
+        image::open(path).map(|_| ()).map_err(|e| e.to_string())
+    });
+    let mut assets = Assets::open(&settings.assets.manifest, decode)?;
+
+    let (count, mut problems) = assets.load_all();
+    let fonts: Vec<_> = assets.manifest.fonts.keys().cloned().collect();
+    for id in fonts {
+        // Fonts that couldn't be read at all are already in `problems`.
+        if let Ok(bytes) = assets.font(&id) {
+            if Font::from_bytes(&bytes[..]).is_err() {
+                problems.push(AssetError::BadFont { id }.into());
+            }
+        }
+    }
+    if problems.is_empty() {
+        if let Err(e) = Sprites::load(&settings.sprites.path, &mut assets) {
+            problems.push(e.context("Couldn't load the sprites").into());
+        }
+    }
+
+    for e in problems.iter() {
+        let chain: Vec<_> = e.iter_chain().map(|c| c.to_string()).collect();
+        eprintln!("{}.", chain.join(": "));
+    }
+    if !problems.is_empty() {
+        return Err(err_msg(format!(
+            "{} of the {} assets couldn't be loaded",
+            problems.len(),
+            count,
+        )));
+    }
+    println!("All {} assets loaded.", count);
+
+    Ok(())
+}
+
+/// The contents of a text file, with the path in the error if it can't be
+/// read.
+pub fn read_file(path: &Path) -> Result<String, Error> {
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .with_context(|_| format!("Couldn't read {}", path.display()))?;
+
+    Ok(contents)
+}
+
+fn read_bytes(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut contents = Vec::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut contents))
+        .with_context(|_| format!("Couldn't read {}", path.display()))?;
+
+    Ok(contents)
+}
+
+/// Where the file for the asset of the given kind and id is, as long as it's
+/// there. `dir` is the manifest's directory.
+fn find(dir:   &Path,
+        kind:  &'static str,
+        paths: &BTreeMap<String, PathBuf>,
+        id:    &str) -> Result<PathBuf, AssetError>
+{
+    let path = paths.get(id).ok_or_else(|| AssetError::UnknownId {
+        kind,
+        id: id.to_owned(),
+    })?;
+    let path = dir.join(path);
+    if !path.is_file() {
+        return Err(AssetError::MissingFile {
+            kind,
+            id: id.to_owned(),
+            path,
+        });
+    }
+
+    Ok(path)
+}
+
+/// The contents of the file for the asset with the given id, from `cache`
+/// if it's already been loaded, or else from wherever `find` finds it.
+fn cached_bytes<F>(cache: &mut Map<String, Handle<Vec<u8>>>,
+                   id:    &str,
+                   find:  F) -> Result<Handle<Vec<u8>>, Error>
+    where F: FnOnce() -> Result<PathBuf, AssetError>
+{
+    if let Some(bytes) = cache.get(id) {
+        return Ok(bytes.clone());
+    }
+
+    let bytes = Rc::new(read_bytes(&find()?)?);
+    cache.insert(id.to_owned(), bytes.clone());
+
+    Ok(bytes)
+}
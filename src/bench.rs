@@ -0,0 +1,175 @@
+//! Timing of how long frames take to draw as maps grow, without a window.
+
+use camera::Camera;
+use drawable::Drawable;
+use failure::Error;
+use geometry::CubePoint;
+use graphics::{draw_state::DrawState, Context, Graphics, ImageSize, Viewport};
+use map_data::{simulated_map_data, TileStyle};
+use npc::Npc;
+use player::Player;
+use scene::Scene;
+use settings::Settings;
+use std::{f64::consts::FRAC_PI_3, time::Instant};
+use temporal::Temporal;
+
+
+/// Side lengths of the maps that are timed, in hexes.
+const SIZES: &[usize] = &[64, 128, 256, 512, 1_000];
+
+/// Frames drawn of each map, in each mode.
+const FRAMES: u32 = 120;
+
+/// The camera turns every so many frames, so that culling is timed at more
+/// than one angle.
+const FRAMES_PER_TURN: u32 = 20;
+
+const SEED: u64 = 0;
+
+
+/// A backend that draws nothing, but that still has everything broken down
+/// into triangles, as a real one would. Keeps count of how many batches of
+/// triangles it's handed, each of which would be at least one draw call.
+struct NullGraphics {
+    calls: usize,
+}
+
+struct NullTexture;
+
+
+/// Times drawing on each of `SIZES`, flat both tile by tile and in batches,
+/// and elevated, which is never batched, and prints the average frame time
+/// and number of draw calls for each. Each map is timed both at the starting
+/// zoom level and zoomed all the way out, where there are many more tiles on
+/// screen.
+pub fn run(settings: &Settings) -> Result<(), Error> {
+    // With fog of war, hardly any of each map would be drawn.
+    let mut settings = settings.clone();
+    settings.gameplay.fog_of_war = false;
+
+    let (width, height) = (settings.graphics.width, settings.graphics.height);
+    let ctx = Context::new_viewport(Viewport {
+        rect:        [0, 0, width as i32, height as i32],
+        draw_size:   [width, height],
+        window_size: [f64::from(width), f64::from(height)],
+    });
+
+    println!(
+        "Only flat maps are batched; elevated maps, as drawn by default, are \
+         always drawn tile by tile.",
+    );
+    println!(
+        "{:>6}  {:>9}  {:>5}  {:>19}  {:>19}  {:>19}",
+        "side", "tiles", "zoom", "flat", "flat, batched", "elevated",
+    );
+    let zooms = [settings.graphics.zoom, settings.graphics.min_zoom];
+    for &side in SIZES {
+        for &zoom in zooms.iter() {
+            settings.graphics.zoom = zoom;
+            let mut scene = centered_scene(side, &settings)?;
+            let mut time_with = |elevated, batch_tiles| {
+                settings.graphics.elevated = elevated;
+                settings.graphics.batch_tiles = batch_tiles;
+                scene.apply_settings(&settings);
+
+                time_frames(&mut scene, &ctx)
+            };
+
+            let per_tile = time_with(false, false);
+            let batched = time_with(false, true);
+            let elevated = time_with(true, true);
+
+            println!(
+                "{:>6}  {:>9}  {:>5.2}  {}  {}  {}",
+                side,
+                side * side,
+                zoom,
+                per_tile,
+                batched,
+                elevated,
+            );
+        }
+    }
+
+    Ok(())
+}
+
+
+/// A scene on a generated map with the given side length, with the player
+/// and camera in the middle of it.
+fn centered_scene(side: usize, settings: &Settings) -> Result<Scene, Error> {
+    let gameplay = &settings.gameplay;
+    let map = simulated_map_data(side, SEED, TileStyle::new(settings))?;
+
+    let q = (side / 2) as i32;
+    let center = CubePoint::from_q_r(q, q - q / 2).cast();
+    let player = Player::new(gameplay.player_anim_time, center, settings);
+    let camera = Camera::new(gameplay.camera_anim_time, center, settings);
+    let npcs = map
+        .npcs()
+        .iter()
+        .enumerate()
+        .map(|(i, spawn)| Npc::new(gameplay.npc_anim_time, i, spawn, settings))
+        .collect();
+
+    Ok(Scene::new(camera, map, player, npcs, settings))
+}
+
+/// Average milliseconds and draw calls that drawing the scene takes, ready
+/// for printing.
+fn time_frames(scene: &mut Scene, ctx: &Context) -> String {
+    let dt = 1.0 / 60.0;
+    let mut total = 0.0;
+    let mut g = NullGraphics { calls: 0 };
+
+    for frame in 0..FRAMES {
+        if frame % FRAMES_PER_TURN == 0 {
+            scene.camera.grid_pos_mut().inc_target_angle(FRAC_PI_3);
+        }
+        scene.camera.step(dt);
+
+        let started = Instant::now();
+        scene.draw(&scene.camera, ctx, &mut g);
+        let took = started.elapsed();
+        total += took.as_secs() as f64 + f64::from(took.subsec_nanos()) * 1e-9;
+    }
+
+    format!(
+        "{:>7.3} ms {:>6} calls",
+        1_000.0 * total / f64::from(FRAMES),
+        g.calls / FRAMES as usize,
+    )
+}
+
+
+impl ImageSize for NullTexture {
+    fn get_size(&self) -> (u32, u32) {
+        (0, 0)
+    }
+}
+
+impl Graphics for NullGraphics {
+    type Texture = NullTexture;
+
+    fn clear_color(&mut self, _: [f32; 4]) {}
+
+    fn clear_stencil(&mut self, _: u8) {}
+
+    fn tri_list<F>(&mut self, _: &DrawState, _: &[f32; 4], mut f: F)
+        where F: FnMut(&mut dyn FnMut(&[[f32; 2]]))
+    {
+        self.calls += 1;
+        f(&mut |_| ());
+    }
+
+    fn tri_list_uv<F>(&mut self,
+                      _:     &DrawState,
+                      _:     &[f32; 4],
+                      _:     &Self::Texture,
+                      mut f: F)
+        where F: FnMut(&mut dyn FnMut(&[[f32; 2]], &[[f32; 2]]))
+    {
+        self.calls += 1;
+        f(&mut |_, _| ());
+    }
+}
@@ -1,31 +1,269 @@
-use geometry::{Angle, cube_dir, CubePoint};
+use camera_effects::{CameraEffects, Keyframe, View};
+use easing::Easing;
+use geometry::{Angle, cube_dir, cube_to_real, real_to_cube, CubePoint, Dir};
+use graphics::{math::add, Context};
+use matrix::{m, rot, scale_uni, trans, Matrix};
 use positioned::Positioned;
+use settings::Settings;
 use std::f64::consts::FRAC_PI_3;
 use temporal::Temporal;
 use transitioned_grid_pos::TransitionedGridPos;
+use tween::{Animator, Timeline};
+
+
+/// How many hexes tall the window is at a zoom level of `1`.
+const BASE_HEX_SCALED_HEIGHT: f64 = 12.0;
+
+/// Seconds that a change of zoom level takes.
+const ZOOM_TIME: f64 = 0.25;
+
+/// Seconds that the view takes to get back to the camera from looking at
+/// something else, once the camera moves or turns.
+const LOOK_BACK_TIME: f64 = 0.4;
 
 
 pub struct Camera {
-    pos: TransitionedGridPos,
+    pos:         TransitionedGridPos,
+    /// Size, in pixels, of the area being drawn to.
+    viewport:    [f64; 2],
+    zoom:        f64,
+    /// Zoom level that is being transitioned to.
+    target_zoom: f64,
+    min_zoom:    f64,
+    max_zoom:    f64,
+    zoom_step:   f64,
+    zoom_anim:   Option<Animator<f64>>,
+    /// Whether tiles are drawn as raised columns, rather than flat.
+    elevated:    bool,
+    /// How far up the screen each level of tile height raises something, as
+    /// a fraction of the size of a hex.
+    tile_height: f64,
+    effects:     CameraEffects,
+    /// What the camera sees, with every effect applied, as of the last step.
+    view:        View,
 }
 
 
 impl Camera {
-    pub fn new(anim_time: f64, start_pos: CubePoint<f64>) -> Self {
+    pub fn new(anim_time: f64,
+               start_pos: CubePoint<f64>,
+               settings:  &Settings) -> Self
+    {
+        let easing = settings.animation.camera;
+        let mut pos = TransitionedGridPos::new(anim_time, start_pos);
+        pos.set_easing(easing.translation, easing.rotation);
+
+        let graphics = &settings.graphics;
+        let view = View { pos: start_pos, angle: pos.angle() };
+
         Camera {
-            pos: TransitionedGridPos::new(anim_time, start_pos),
+            pos,
+            viewport:    [
+                f64::from(graphics.width),
+                f64::from(graphics.height),
+            ],
+            zoom:        graphics.zoom,
+            target_zoom: graphics.zoom,
+            min_zoom:    graphics.min_zoom,
+            max_zoom:    graphics.max_zoom,
+            zoom_step:   graphics.zoom_step,
+            zoom_anim:   None,
+            elevated:    graphics.elevated,
+            tile_height: graphics.tile_height,
+            effects:     CameraEffects::new(start_pos, settings),
+            view,
+        }
+    }
+
+    /// Picks up any changes to the settings that affect the camera. The
+    /// current zoom level is kept, as long as it's still within the limits.
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        let easing = settings.animation.camera;
+        self.pos.set_easing(easing.translation, easing.rotation);
+        self.pos.set_anim_time(settings.gameplay.camera_anim_time);
+
+        let graphics = &settings.graphics;
+        self.min_zoom = graphics.min_zoom;
+        self.max_zoom = graphics.max_zoom;
+        self.zoom_step = graphics.zoom_step;
+        self.elevated = graphics.elevated;
+        self.tile_height = graphics.tile_height;
+        self.effects.apply_settings(settings);
+
+        let target_zoom = self.target_zoom;
+        if target_zoom < self.min_zoom || target_zoom > self.max_zoom {
+            self.zoom_to(target_zoom);
+        }
+    }
+
+    pub fn viewport(&self) -> [f64; 2] {
+        self.viewport
+    }
+
+    /// Should be called whenever the size of the area being drawn to
+    /// changes, e.g. when the window is resized.
+    pub fn set_viewport(&mut self, viewport: [f64; 2]) {
+        self.viewport = viewport;
+    }
+
+    pub fn zoom(&self) -> f64 {
+        self.zoom
+    }
+
+    /// Smoothly zooms to the given level, within the configured limits.
+    pub fn zoom_to(&mut self, target_zoom: f64) {
+        self.target_zoom = target_zoom.max(self.min_zoom).min(self.max_zoom);
+        self.zoom_anim = Some(Animator::new(Timeline::tween(
+            |z: &mut f64| z,
+            self.zoom,
+            self.target_zoom,
+            ZOOM_TIME,
+            Easing::CubicOut,
+        )));
+    }
+
+    /// Smoothly zooms in (for `factor > 1`) or out (for `factor < 1`)
+    /// relative to the zoom level currently being transitioned to.
+    pub fn zoom_by(&mut self, factor: f64) {
+        let target_zoom = self.target_zoom * factor;
+        self.zoom_to(target_zoom);
+    }
+
+    /// Zooms in or out by one step.
+    pub fn zoom_step(&mut self, zoom_in: bool) {
+        let factor = if zoom_in {
+            self.zoom_step
+        } else {
+            1.0 / self.zoom_step
+        };
+
+        self.zoom_by(factor);
+    }
+
+    pub fn grid_pos(&self) -> &TransitionedGridPos {
+        &self.pos
+    }
+
+    pub fn grid_pos_mut(&mut self) -> &mut TransitionedGridPos {
+        &mut self.pos
+    }
+
+    /// The hex direction nearest to the way that the camera is turning to,
+    /// which is "up" on the screen as far as movement goes.
+    pub fn facing(&self) -> Dir {
+        Dir::from_angle(self.pos.target_angle())
+    }
+
+    /// Turns the camera by `radians` straight away, anticlockwise for
+    /// positive angles, e.g. while it's being dragged around.
+    pub fn rotate(&mut self, radians: f64) {
+        self.pos.rotate_now(radians);
+    }
+
+    /// Smoothly turns the camera back to the nearest hex-aligned angle.
+    pub fn snap_angle(&mut self) {
+        self.pos.set_target_angle(self.facing().angle());
+    }
+
+    /// Shakes the camera harder, e.g. when something is hit.
+    pub fn add_trauma(&mut self, amount: f64) {
+        self.effects.add_trauma(amount);
+    }
+
+    /// Pans and turns the view through the given keyframes, starting from
+    /// wherever it is now, and then eases it back to the camera.
+    pub fn play_cutscene(&mut self, keyframes: &[Keyframe]) {
+        self.effects.play_cutscene(self.view, keyframes);
+    }
+
+    pub fn is_in_cutscene(&self) -> bool {
+        self.effects.is_in_cutscene()
+    }
+
+    /// Eases the view over to `pos` in `duration` seconds, and keeps it
+    /// there until the camera next moves or turns.
+    pub fn look_at(&mut self, pos: CubePoint<f64>, duration: f64) {
+        self.effects.look_at(pos, duration);
+    }
+
+    /// Size, in pixels, of a single unit of the hex grid.
+    pub fn scale_factor(&self) -> f64 {
+        let hex_scaled_height = BASE_HEX_SCALED_HEIGHT / self.zoom;
+
+        self.viewport[1] / hex_scaled_height
+    }
+
+    pub fn is_elevated(&self) -> bool {
+        self.elevated
+    }
+
+    /// Screen-space displacement of something at the given tile height from
+    /// where it would be at a height of `0`. Without elevation, nothing is
+    /// displaced.
+    pub fn lift(&self, height: i32) -> [f64; 2] {
+        if !self.elevated {
+            return [0.0, 0.0];
         }
+
+        [0.0, -f64::from(height) * self.tile_height * self.scale_factor()]
+    }
+
+    /// Screen-space displacement of the given absolute position, as seen by
+    /// this camera.
+    pub fn screen_pos(&self, abs_pos: CubePoint<f64>) -> [f64; 2] {
+        let cam_rotation = rot(self.angle().radians());
+        let pos_minus_cam = abs_pos - *self.pos();
+
+        add(
+            cam_rotation
+                .vec_mul(cube_to_real(pos_minus_cam, self.scale_factor())),
+            [self.viewport[0] / 2.0, self.viewport[1] / 2.0],
+        )
+    }
+
+    /// The absolute position, at a tile height of `0`, that appears at the
+    /// given point on the screen. The inverse of `screen_pos`.
+    pub fn ground_pos(&self, screen_pos: [f64; 2]) -> CubePoint<f64> {
+        let from_center = [
+            screen_pos[0] - self.viewport[0] / 2.0,
+            screen_pos[1] - self.viewport[1] / 2.0,
+        ];
+        let unrotated = rot(-self.angle().radians()).vec_mul(from_center);
+
+        real_to_cube(unrotated, self.scale_factor()) + *self.pos()
+    }
+
+    /// Transformation from hex grid units, as given by `cube_to_real` with a
+    /// size of `1`, onto the screen. Does the same as `screen_pos`, for
+    /// when lots of points need placing at once.
+    pub fn ground_transform(&self) -> Matrix {
+        let cam_real = cube_to_real(*self.pos(), 1.0);
+
+        trans([-cam_real[0], -cam_real[1]]) *
+        scale_uni(self.scale_factor()) *
+        rot(self.angle().radians()) *
+        trans([self.viewport[0] / 2.0, self.viewport[1] / 2.0])
+    }
+
+    /// Transformation that places an entity with the given absolute position
+    /// and orientation onto the screen.
+    pub fn entity_transform(&self,
+                            abs_pos:   CubePoint<f64>,
+                            abs_angle: Angle,
+                            ctx:       &Context) -> Matrix
+    {
+        let ang_minus_cam = abs_angle - self.angle();
+
+        rot(-ang_minus_cam.radians()) *
+        trans(self.screen_pos(abs_pos)) *
+        m(ctx.transform)
     }
 }
 
 impl Positioned for Camera {
     fn unit_move(&mut self, forwards: bool) {
-        let target_angle = self.pos.target_angle();
-        let turns = (target_angle.radians() / FRAC_PI_3)
-            .round() as usize % 6;
-
         let target_pos = *self.pos.target_pos();
-        let target_dir = cube_dir(turns);
+        let target_dir = cube_dir(self.facing());
         let new_target_pos = if forwards {
             target_pos + target_dir
         } else {
@@ -33,6 +271,7 @@ impl Positioned for Camera {
         };
 
         self.pos.set_target_pos(new_target_pos);
+        self.effects.look_back(LOOK_BACK_TIME);
     }
 
     fn turn(&mut self, anticlockwise: bool) {
@@ -41,19 +280,32 @@ impl Positioned for Camera {
         } else {
             self.pos.dec_target_angle(FRAC_PI_3);
         }
+        self.effects.look_back(LOOK_BACK_TIME);
     }
 
     fn pos(&self) -> &CubePoint<f64> {
-        self.pos.pos()
+        &self.view.pos
     }
 
     fn angle(&self) -> Angle {
-        self.pos.angle()
+        self.view.angle
     }
 }
 
 impl Temporal for Camera {
     fn step(&mut self, dt: f64) {
         self.pos.step(dt);
+
+        let base = View { pos: *self.pos.pos(), angle: self.pos.angle() };
+        self.effects.step(base, dt);
+        self.view = self.effects.view(base);
+
+        if let Some(ref mut zoom_anim) = self.zoom_anim {
+            zoom_anim.step(dt);
+            zoom_anim.apply(&mut self.zoom);
+        }
+        if self.zoom_anim.as_ref().map_or(false, Animator::is_finished) {
+            self.zoom_anim = None;
+        }
     }
 }
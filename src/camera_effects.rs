@@ -0,0 +1,478 @@
+//! Effects layered on top of where the camera's grid position puts it: a
+//! critically damped follow, scripted cutscenes, transitions to look at some
+//! point and back, and trauma-based shake. Each is applied on top of the
+//! ones before it, in that order, and what each one does is worked out by a
+//! pure function of time.
+
+use easing::Easing;
+use geometry::{cube_to_real, lerp, real_to_cube, Angle, CubePoint};
+use graphics::math::Vec2d;
+use settings::Settings;
+use temporal::Temporal;
+use tween::{Animator, Timeline, Tweenable};
+
+
+/// Seconds that the view takes to get back to the camera once a cutscene
+/// has finished.
+const CUTSCENE_RETURN_TIME: f64 = 0.6;
+
+/// Where the camera is looking from, and which way it's turned.
+#[derive(Clone, Copy, Debug)]
+pub struct View {
+    pub pos:   CubePoint<f64>,
+    pub angle: Angle,
+}
+
+/// A point along a cutscene's path.
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    /// Seconds that it takes to get here from the previous keyframe.
+    pub time:   f64,
+    pub view:   View,
+    /// How the camera gets here from the previous keyframe.
+    pub easing: Easing,
+}
+
+pub struct CameraEffects {
+    follow:   Follow,
+    cutscene: Option<Animator<View>>,
+    focus:    Focus,
+    shake:    Shake,
+}
+
+/// Eases the view towards where the camera is, like a critically damped
+/// spring, which catches up as quickly as it can without overshooting.
+#[derive(Clone, Copy, Debug)]
+struct Follow {
+    /// Angular frequency of the spring. Higher catches up quicker, and `0`
+    /// keeps up exactly.
+    frequency: f64,
+    /// In hex grid units, as given by `cube_to_real` with a size of `1`.
+    pos:       Vec2d,
+    vel:       Vec2d,
+}
+
+/// Draws the view towards somewhere else, after a cutscene or while looking
+/// at something.
+struct Focus {
+    pos:    CubePoint<f64>,
+    /// Which way to turn, or `None` to stay turned the way the camera is.
+    angle:  Option<Angle>,
+    /// How far the view is drawn towards the focus, from `0` to `1`.
+    weight: f64,
+    anim:   Option<Animator<f64>>,
+}
+
+/// Shakes the view by an amount that goes with the square of the trauma
+/// that's been taken, which wears off over time.
+#[derive(Clone, Copy, Debug)]
+struct Shake {
+    style:       ShakeStyle,
+    /// From `0` to `1`, as of when trauma was last added.
+    trauma:      f64,
+    /// Seconds since trauma was last added.
+    since_added: f64,
+    /// Seconds that the shake has been going for, which the noise that it's
+    /// made from is sampled at.
+    time:        f64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShakeStyle {
+    /// Furthest, in hexes, that the view is moved with full trauma.
+    pub offset:    f64,
+    /// Furthest, in radians, that the view is turned with full trauma.
+    pub angle:     f64,
+    /// Roughly how many times a second the shake changes direction.
+    pub frequency: f64,
+    /// Trauma that wears off each second.
+    pub decay:     f64,
+}
+
+
+impl CameraEffects {
+    /// No effects, for a camera starting at `pos`.
+    pub fn new(pos: CubePoint<f64>, settings: &Settings) -> Self {
+        CameraEffects {
+            follow:   Follow {
+                frequency: settings.camera.follow_frequency,
+                pos:       cube_to_real(pos, 1.0),
+                vel:       [0.0, 0.0],
+            },
+            cutscene: None,
+            focus:    Focus {
+                pos,
+                angle:  None,
+                weight: 0.0,
+                anim:   None,
+            },
+            shake:    Shake {
+                style:       ShakeStyle::new(settings),
+                trauma:      0.0,
+                since_added: 0.0,
+                time:        0.0,
+            },
+        }
+    }
+
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        self.follow.frequency = settings.camera.follow_frequency;
+        self.shake.style = ShakeStyle::new(settings);
+    }
+
+    /// Moves every effect along, given where the camera itself now is.
+    pub fn step(&mut self, base: View, dt: f64) {
+        self.follow.step(base.pos, dt);
+        self.shake.step(dt);
+
+        if let Some(ref mut cutscene) = self.cutscene {
+            cutscene.step(dt);
+        }
+        if let Some(cutscene) = self.cutscene.take() {
+            if cutscene.is_finished() {
+                let mut end = base;
+                cutscene.apply(&mut end);
+                self.focus = Focus {
+                    pos:    end.pos,
+                    angle:  Some(end.angle),
+                    weight: 1.0,
+                    anim:   None,
+                };
+                self.focus.blend_to(0.0, CUTSCENE_RETURN_TIME);
+            } else {
+                self.cutscene = Some(cutscene);
+            }
+        }
+
+        self.focus.step(dt);
+    }
+
+    /// What the camera sees once every effect is applied to `base`.
+    pub fn view(&self, base: View) -> View {
+        let mut view = View { pos: self.follow.pos(), angle: base.angle };
+        if let Some(ref cutscene) = self.cutscene {
+            cutscene.apply(&mut view);
+        }
+
+        let focus = &self.focus;
+        let angle = focus.angle.unwrap_or(view.angle);
+        let view = blend(view, View { pos: focus.pos, angle }, focus.weight);
+
+        let (offset, turn) = self.shake.offset();
+        View {
+            pos:   view.pos + real_to_cube(offset, 1.0),
+            angle: view.angle + turn,
+        }
+    }
+
+    /// Shakes the camera harder, e.g. when something is hit. Trauma is
+    /// capped at `1`.
+    pub fn add_trauma(&mut self, amount: f64) {
+        self.shake.trauma = (self.shake.trauma() + amount).min(1.0);
+        self.shake.since_added = 0.0;
+    }
+
+    /// Takes the view along the path through the given keyframes, starting
+    /// from `from`, and then back to the camera. Any cutscene that's already
+    /// playing is cut short.
+    pub fn play_cutscene(&mut self, from: View, keyframes: &[Keyframe]) {
+        self.cutscene = cutscene(from, keyframes).map(Animator::new);
+    }
+
+    pub fn is_in_cutscene(&self) -> bool {
+        self.cutscene.is_some()
+    }
+
+    /// Turns the view towards `pos` over `duration` seconds, and keeps it
+    /// there until `look_back` is called.
+    pub fn look_at(&mut self, pos: CubePoint<f64>, duration: f64) {
+        self.focus.pos = pos;
+        self.focus.angle = None;
+        self.focus.blend_to(1.0, duration);
+    }
+
+    /// Turns the view back to the camera over `duration` seconds.
+    pub fn look_back(&mut self, duration: f64) {
+        self.focus.blend_to(0.0, duration);
+    }
+}
+
+impl Follow {
+    fn step(&mut self, target: CubePoint<f64>, dt: f64) {
+        let target = cube_to_real(target, 1.0);
+        if self.frequency <= 0.0 {
+            self.pos = target;
+            self.vel = [0.0, 0.0];
+            return;
+        }
+
+        for (i, &to) in target.iter().enumerate() {
+            let (pos, vel) = critically_damped(
+                self.pos[i],
+                self.vel[i],
+                to,
+                self.frequency,
+                dt,
+            );
+            self.pos[i] = pos;
+            self.vel[i] = vel;
+        }
+    }
+
+    fn pos(&self) -> CubePoint<f64> {
+        real_to_cube(self.pos, 1.0)
+    }
+}
+
+impl Focus {
+    /// Tweens the weight from what it is now to `weight`.
+    fn blend_to(&mut self, weight: f64, duration: f64) {
+        self.anim = Some(Animator::new(Timeline::tween(
+            |w: &mut f64| w,
+            self.weight,
+            weight,
+            duration,
+            Easing::CubicInOut,
+        )));
+    }
+}
+
+impl Temporal for Focus {
+    fn step(&mut self, dt: f64) {
+        if let Some(ref mut anim) = self.anim {
+            anim.step(dt);
+            anim.apply(&mut self.weight);
+        }
+        if self.anim.as_ref().map_or(false, Animator::is_finished) {
+            self.anim = None;
+        }
+    }
+}
+
+impl Shake {
+    fn trauma(&self) -> f64 {
+        (self.trauma - self.style.decay * self.since_added).max(0.0)
+    }
+
+    fn offset(&self) -> (Vec2d, f64) {
+        shake_offset(&self.style, self.trauma(), self.time)
+    }
+}
+
+impl Temporal for Shake {
+    fn step(&mut self, dt: f64) {
+        self.since_added += dt;
+        self.time += dt;
+    }
+}
+
+impl ShakeStyle {
+    pub fn new(settings: &Settings) -> Self {
+        let camera = &settings.camera;
+
+        ShakeStyle {
+            offset:    camera.shake_offset,
+            angle:     camera.shake_angle.to_radians(),
+            frequency: camera.shake_frequency,
+            decay:     camera.trauma_decay,
+        }
+    }
+}
+
+
+/// Where a critically damped spring that starts off at `from`, moving at
+/// `vel`, is `t` seconds later while being pulled towards `to` with the
+/// given angular frequency, along with how fast it's moving then.
+pub fn critically_damped(from:      f64,
+                         vel:       f64,
+                         to:        f64,
+                         frequency: f64,
+                         t:         f64) -> (f64, f64)
+{
+    let offset = from - to;
+    let c = vel + frequency * offset;
+    let decay = (-frequency * t).exp();
+
+    (to + (offset + c * t) * decay, (vel - frequency * c * t) * decay)
+}
+
+/// How far, in hex grid units, and by how many radians a view is shaken
+/// with the given trauma, `t` seconds into shaking.
+pub fn shake_offset(style: &ShakeStyle, trauma: f64, t: f64) -> (Vec2d, f64) {
+    let amount = trauma * trauma;
+    let x = t * style.frequency;
+
+    (
+        [
+            style.offset * amount * noise(0, x),
+            style.offset * amount * noise(1, x),
+        ],
+        style.angle * amount * noise(2, x),
+    )
+}
+
+/// The path through the given keyframes, starting from `from`, or `None` if
+/// there are no keyframes.
+pub fn cutscene(from:      View,
+                keyframes: &[Keyframe]) -> Option<Timeline<View>>
+{
+    let mut last = from;
+    keyframes
+        .iter()
+        .map(|k| {
+            let pos = Timeline::tween(
+                |v: &mut View| &mut v.pos,
+                last.pos,
+                k.view.pos,
+                k.time,
+                k.easing,
+            );
+            let angle = Timeline::tween(
+                |v: &mut View| &mut v.angle,
+                last.angle,
+                k.view.angle,
+                k.time,
+                k.easing,
+            );
+            last = k.view;
+
+            pos.with(angle)
+        })
+        .fold(None, |path, leg| match path {
+            Some(path) => Some(Timeline::then(path, leg)),
+            None => Some(leg),
+        })
+}
+
+/// The view drawn `weight` of the way from `from` to `to`.
+pub fn blend(from: View, to: View, weight: f64) -> View {
+    View {
+        pos:   CubePoint::tween(from.pos, to.pos, weight),
+        angle: Angle::tween(from.angle, to.angle, weight),
+    }
+}
+
+/// Smooth noise between `-1` and `1`, which is different for each seed.
+fn noise(seed: u64, x: f64) -> f64 {
+    let i = x.floor();
+    let f = x - i;
+    let i = i as i64;
+
+    lerp(hash(seed, i), hash(seed, i + 1), f * f * (3.0 - 2.0 * f))
+}
+
+/// A number between `-1` and `1` that looks random, but that's always the
+/// same for the same seed and index.
+fn hash(seed: u64, i: i64) -> f64 {
+    let mut z = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = z.wrapping_add(i as u64);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+
+    (z >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const EPSILON: f64 = 1e-9;
+
+    fn view(a: f64, b: f64, radians: f64) -> View {
+        View {
+            pos:   CubePoint::new(a, b, -a - b),
+            angle: Angle::new(radians),
+        }
+    }
+
+    fn assert_near(actual: View, expected: View) {
+        let (p, q) = (actual.pos, expected.pos);
+        let gap = (p.a - q.a).abs() + (p.b - q.b).abs() + (p.c - q.c).abs();
+        let turn = (actual.angle.radians() - expected.angle.radians()).abs();
+        let turn = turn.min(2.0 * PI - turn);
+        assert!(gap < EPSILON && turn < EPSILON, "{:?}", actual);
+    }
+
+    #[test]
+    fn follow_converges_without_overshoot() {
+        let (mut pos, mut vel) = (0.0, 0.0);
+        for _ in 0..300 {
+            let (next, next_vel) =
+                critically_damped(pos, vel, 1.0, 5.0, 1.0 / 60.0);
+            assert!(next >= pos && next <= 1.0, "{}", next);
+            pos = next;
+            vel = next_vel;
+        }
+        assert!((pos - 1.0).abs() < 1e-6, "{}", pos);
+
+        // Stepping a frame at a time gets to the same place as one big step.
+        let (at_once, _) = critically_damped(0.0, 0.0, 1.0, 5.0, 5.0);
+        assert!((pos - at_once).abs() < EPSILON, "{} {}", pos, at_once);
+    }
+
+    #[test]
+    fn shake_is_still_without_trauma_and_bounded_with_it() {
+        let style = ShakeStyle {
+            offset:    0.3,
+            angle:     0.1,
+            frequency: 20.0,
+            decay:     1.0,
+        };
+        for i in 0..1000 {
+            let t = f64::from(i) * 0.013;
+            assert_eq!(shake_offset(&style, 0.0, t), ([0.0, 0.0], 0.0));
+
+            for &trauma in [0.5, 1.0].iter() {
+                let ([x, y], turn) = shake_offset(&style, trauma, t);
+                let limit = trauma * trauma;
+                assert!(x.abs() <= style.offset * limit, "{}", x);
+                assert!(y.abs() <= style.offset * limit, "{}", y);
+                assert!(turn.abs() <= style.angle * limit, "{}", turn);
+            }
+        }
+    }
+
+    #[test]
+    fn cutscene_hits_each_keyframe_at_its_time() {
+        let from = view(0.0, 0.0, 0.0);
+        let keyframes = [
+            Keyframe {
+                time:   1.0,
+                view:   view(3.0, -1.0, 1.0),
+                easing: Easing::Linear,
+            },
+            Keyframe {
+                time:   2.0,
+                view:   view(-2.0, 4.0, 2.5),
+                easing: Easing::CubicInOut,
+            },
+        ];
+        let path = cutscene(from, &keyframes).unwrap();
+        assert!((path.duration() - 3.0).abs() < EPSILON);
+
+        for &(t, expected) in [
+            (0.0, from),
+            (1.0, keyframes[0].view),
+            (3.0, keyframes[1].view),
+        ].iter() {
+            let mut at = view(9.0, 9.0, 0.0);
+            path.apply(&mut at, t);
+            assert_near(at, expected);
+        }
+
+        assert!(cutscene(from, &[]).is_none());
+    }
+
+    #[test]
+    fn blend_goes_from_one_view_to_the_other() {
+        let from = view(0.0, 0.0, 0.1);
+        let to = view(2.0, -4.0, 2.0 * PI - 0.1);
+        assert_near(blend(from, to, 0.0), from);
+        assert_near(blend(from, to, 1.0), to);
+        // Halfway turns the short way round, through `0`.
+        assert_near(blend(from, to, 0.5), view(1.0, -2.0, 0.0));
+    }
+}
@@ -0,0 +1,234 @@
+//! Parsing of command-line arguments.
+
+use settings::{parse_value, set_dotted};
+use std::path::PathBuf;
+use toml::{value::Table, Value};
+
+
+pub const USAGE: &str = "\
+Usage: ahistorics [OPTIONS]
+
+Options:
+    --settings <PATH>    Project settings file to use. By default, the
+                         first ahistorics_settings.toml found in the current
+                         directory or any of its ancestors is used.
+    --map <PATH>         Map file to play on, instead of generating one. Map
+                         files are laid out like the [map] table of a save.
+    --seed <N>           Seed to generate the map from. Can't be used with
+                         --map.
+    --size <N>           Side length of the generated map, in hexes.
+    --windowed           Run in a window.
+    --fullscreen         Run fullscreen.
+    --headless           Run without a window, until the replay (if any)
+                         ends.
+    --replay <PATH>      Replay the key presses in the given file. Each line
+                         is `<seconds> press <key>` or `<seconds> release
+                         <key>`, where <seconds> is the game time since the
+                         start and <key> is named as in the [input]
+                         settings, e.g. `1.5 press W`. Blank lines and lines
+                         starting with `#` are ignored.
+    --set <KEY=VALUE>    Override a setting, e.g. `--set graphics.zoom=2`.
+                         May be given more than once.
+    --print-settings     Print every setting, and where each came from.
+    --bench              Time drawing on ever larger generated maps, without
+                         a window, and print how long frames took.
+    --check-assets       Load every asset in the asset manifest, without a
+                         window, and report any that can't be loaded.
+    -h, --help           Print this message.
+
+Settings are read from, in increasing order of precedence: the defaults;
+the user's settings file, at $XDG_CONFIG_HOME/ahistorics/settings.toml; the
+project's settings file; environment variables like
+AHISTORICS_GRAPHICS__ZOOM=2; and then --set and the other options above.
+";
+
+
+#[derive(Debug, Default)]
+pub struct Args {
+    pub settings:       Option<PathBuf>,
+    pub map:            Option<PathBuf>,
+    pub seed:           Option<u64>,
+    pub headless:       bool,
+    pub replay:         Option<PathBuf>,
+    /// Settings given on the command line, which take precedence over those
+    /// from the settings file.
+    pub overrides:      Table,
+    pub print_settings: bool,
+    pub bench:          bool,
+    pub check_assets:   bool,
+    pub help:           bool,
+}
+
+#[derive(Debug, Fail)]
+pub enum CliError {
+    #[fail(display = "Unknown option {:?}; try --help", flag)]
+    UnknownFlag {
+        flag: String,
+    },
+    #[fail(display = "{} needs a value", flag)]
+    MissingValue {
+        flag: String,
+    },
+    #[fail(display = "{} doesn't take a value", flag)]
+    UnexpectedValue {
+        flag: String,
+    },
+    #[fail(display = "Invalid value {:?} for {}: {}", value, flag, reason)]
+    BadValue {
+        flag:   String,
+        value:  String,
+        reason: &'static str,
+    },
+    #[fail(display = "{} and {} can't be used together; {}",
+           flag, other, reason)]
+    Conflict {
+        flag:   &'static str,
+        other:  &'static str,
+        reason: &'static str,
+    },
+}
+
+
+/// Parses the given arguments, not including the name of the program.
+pub fn parse<I>(args: I) -> Result<Args, CliError>
+    where I: IntoIterator<Item=String>
+{
+    let mut parsed = Args::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        // Values may be given either as `--flag value` or `--flag=value`.
+        let (flag, inline_value) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") =>
+                (arg[..i].to_owned(), Some(arg[i + 1..].to_owned())),
+            _ => (arg, None),
+        };
+
+        let takes_value = match flag.as_str() {
+            "--settings" | "--map" | "--seed" | "--size" | "--replay"
+            | "--set" => true,
+            "--windowed" | "--fullscreen" | "--headless" | "--print-settings"
+            | "--bench" | "--check-assets" | "-h" | "--help" => false,
+            _ => return Err(CliError::UnknownFlag { flag }),
+        };
+
+        let value = match (takes_value, inline_value) {
+            (true, Some(value)) => value,
+            (true, None) => args
+                .next()
+                .ok_or_else(|| CliError::MissingValue { flag: flag.clone() })?,
+            (false, Some(_)) => return Err(CliError::UnexpectedValue { flag }),
+            (false, None) => String::new(),
+        };
+        let bad_value = |reason| CliError::BadValue {
+            flag:  flag.clone(),
+            value: value.clone(),
+            reason,
+        };
+
+        match flag.as_str() {
+            "--settings" => parsed.settings = Some(value.into()),
+            "--map" => parsed.map = Some(value.into()),
+            "--seed" => parsed.seed = Some(
+                value
+                    .parse()
+                    .map_err(|_| bad_value("not a non-negative integer"))?,
+            ),
+            "--size" => {
+                let size = value
+                    .parse::<i64>()
+                    .map_err(|_| bad_value("not an integer"))?;
+                set_dotted(
+                    &mut parsed.overrides,
+                    "gameplay.map_size",
+                    Value::Integer(size),
+                );
+            },
+            "--windowed" | "--fullscreen" => set_dotted(
+                &mut parsed.overrides,
+                "graphics.fullscreen",
+                Value::Boolean(flag == "--fullscreen"),
+            ),
+            "--headless" => parsed.headless = true,
+            "--print-settings" => parsed.print_settings = true,
+            "--bench" => parsed.bench = true,
+            "--check-assets" => parsed.check_assets = true,
+            "--replay" => parsed.replay = Some(value.into()),
+            "--set" => {
+                let eq = value
+                    .find('=')
+                    .ok_or_else(|| bad_value("expected KEY=VALUE"))?;
+                let (key, raw) = (value[..eq].trim(), value[eq + 1..].trim());
+                if key.is_empty() || key.split('.').any(str::is_empty) {
+                    return Err(bad_value("expected a key like section.name"));
+                }
+
+                set_dotted(&mut parsed.overrides, key, parse_value(raw));
+            },
+            _ => parsed.help = true,
+        }
+    }
+
+    if parsed.map.is_some() && parsed.seed.is_some() {
+        return Err(CliError::Conflict {
+            flag:   "--map",
+            other:  "--seed",
+            reason: "maps from files aren't generated from a seed",
+        });
+    }
+
+    Ok(parsed)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_strs(args: &[&str]) -> Result<Args, CliError> {
+        parse(args.iter().map(|&a| a.to_owned()))
+    }
+
+    #[test]
+    fn set_layers_over_other_options() {
+        let args = parse_strs(&[
+            "--fullscreen",
+            "--set", "graphics.zoom=2",
+            "--set=graphics.fullscreen=false",
+            "--set", "colors.theme = dusk",
+        ]).unwrap();
+        let graphics = &args.overrides["graphics"];
+
+        assert_eq!(graphics["zoom"], Value::Integer(2));
+        assert_eq!(graphics["fullscreen"], Value::Boolean(false));
+        assert_eq!(
+            args.overrides["colors"]["theme"],
+            Value::String("dusk".to_owned()),
+        );
+    }
+
+    #[test]
+    fn map_and_seed_conflict() {
+        match parse_strs(&["--map", "map.toml", "--seed", "7"]) {
+            Err(CliError::Conflict { flag, other, .. }) => {
+                assert_eq!((flag, other), ("--map", "--seed"));
+            },
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_flag_is_an_error() {
+        match parse_strs(&["--headless", "--fast"]) {
+            Err(CliError::UnknownFlag { flag }) => assert_eq!(flag, "--fast"),
+            other => panic!("expected an unknown flag, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn help() {
+        assert!(parse_strs(&["--help"]).unwrap().help);
+        assert!(parse_strs(&["-h"]).unwrap().help);
+        assert!(!parse_strs(&[]).unwrap().help);
+    }
+}
@@ -0,0 +1,378 @@
+//! Parsing of colours, as written in settings files, and shading of them.
+//!
+//! Colours may be given in any of these forms:
+//!
+//! - Hex: `#rgb`, `#rgba`, `#rrggbb`, or `#rrggbbaa`.
+//! - Functions: `rgb(r, g, b)` and `rgba(r, g, b, a)`, with `r`, `g`, and `b`
+//!   within `[0, 255]` (or percentages), and `a` within `[0, 1]` (or a
+//!   percentage); and `hsl(h, s, l)` and `hsla(h, s, l, a)`, with `h` in
+//!   degrees and `s` and `l` as percentages.
+//! - Names, like `white` or `rebeccapurple`.
+
+use graphics::types::Color;
+
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum ColorParseError {
+    #[fail(display = "Colour is empty")]
+    Empty,
+    #[fail(display = "{:?} has {} hex digits, but should have 3, 4, 6, or 8",
+           color, len)]
+    BadHexLength {
+        color: String,
+        len:   usize,
+    },
+    #[fail(display = "{:?} contains something other than hex digits",
+           color)]
+    BadHexDigit {
+        color: String,
+    },
+    #[fail(display = "{:?} is not a colour function; try rgb(), rgba(), \
+                      hsl(), or hsla()", name)]
+    UnknownFunction {
+        name: String,
+    },
+    #[fail(display = "{:?} should have {} arguments", color, expected)]
+    BadArgCount {
+        color:    String,
+        expected: usize,
+    },
+    #[fail(display = "{:?} is not a valid argument to {}(), which expects \
+                      {}", arg, function, expected)]
+    BadArg {
+        function: String,
+        arg:      String,
+        expected: &'static str,
+    },
+    #[fail(display = "{:?} is not the name of a colour", name)]
+    UnknownName {
+        name: String,
+    },
+}
+
+
+/// Parses a colour in any of the forms described in the module docs.
+pub fn parse_color(s: &str) -> Result<Color, ColorParseError> {
+    let s = s.trim();
+
+    if s.is_empty() {
+        Err(ColorParseError::Empty)
+    } else if s.starts_with('#') {
+        parse_hex(s)
+    } else if let (Some(open), true) = (s.find('('), s.ends_with(')')) {
+        parse_function(s, &s[..open], &s[open + 1..s.len() - 1])
+    } else {
+        named(s).ok_or_else(|| ColorParseError::UnknownName {
+            name: s.to_owned(),
+        })
+    }
+}
+
+
+/// Darkens (for `brightness < 1`) or lightens (for `brightness > 1`) a
+/// colour, leaving its alpha alone.
+pub fn shade(color: Color, brightness: f32) -> Color {
+    let channel = |c: f32| (c * brightness).max(0.0).min(1.0);
+
+    [channel(color[0]), channel(color[1]), channel(color[2]), color[3]]
+}
+
+fn parse_hex(s: &str) -> Result<Color, ColorParseError> {
+    // `'#'` is a single byte, so this can't split a character.
+    let digits = &s[1..];
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ColorParseError::BadHexDigit { color: s.to_owned() });
+    }
+
+    // Every character is now known to be ASCII, so bytes are characters.
+    let nibble = |i: usize| {
+        u8::from_str_radix(&digits[i..=i], 16).unwrap_or(0)
+    };
+    let short = |i: usize| f32::from(nibble(i) * 17) / 255.0;
+    let long =
+        |i: usize| f32::from(nibble(2 * i) * 16 + nibble(2 * i + 1)) / 255.0;
+
+    match digits.len() {
+        3 => Ok([short(0), short(1), short(2), 1.0]),
+        4 => Ok([short(0), short(1), short(2), short(3)]),
+        6 => Ok([long(0), long(1), long(2), 1.0]),
+        8 => Ok([long(0), long(1), long(2), long(3)]),
+        len => Err(ColorParseError::BadHexLength {
+            color: s.to_owned(),
+            len,
+        }),
+    }
+}
+
+fn parse_function(s:    &str,
+                  name: &str,
+                  args: &str) -> Result<Color, ColorParseError>
+{
+    let name = name.trim().to_lowercase();
+    let args: Vec<_> = args.split(',').map(str::trim).collect();
+
+    let expected = match name.as_str() {
+        "rgb" | "hsl" => 3,
+        "rgba" | "hsla" => 4,
+        _ => return Err(ColorParseError::UnknownFunction { name }),
+    };
+    if args.len() != expected {
+        return Err(ColorParseError::BadArgCount {
+            color: s.to_owned(),
+            expected,
+        });
+    }
+
+    let bad_arg = |arg: &str, expected| ColorParseError::BadArg {
+        function: name.clone(),
+        arg:      arg.to_owned(),
+        expected,
+    };
+
+    let alpha = match args.get(3) {
+        Some(a) => fraction(a, 1.0).ok_or_else(|| {
+            bad_arg(a, "an alpha within [0, 1] or [0%, 100%]")
+        })?,
+        None => 1.0,
+    };
+
+    if name.starts_with("rgb") {
+        let mut rgb = [0.0; 3];
+        for (channel, arg) in rgb.iter_mut().zip(args.iter()) {
+            *channel = fraction(arg, 255.0).ok_or_else(|| {
+                bad_arg(arg, "a channel within [0, 255] or [0%, 100%]")
+            })?;
+        }
+
+        Ok([rgb[0], rgb[1], rgb[2], alpha])
+    } else {
+        let hue = args[0].trim_end_matches("deg").trim();
+        let hue = hue
+            .parse::<f32>()
+            .ok()
+            .filter(|h| h.is_finite())
+            .ok_or_else(|| bad_arg(args[0], "a hue in degrees"))?;
+
+        let percent = |arg: &str| {
+            fraction(arg.trim_end_matches('%'), 100.0)
+                .ok_or_else(|| bad_arg(arg, "a percentage within [0%, 100%]"))
+        };
+        let saturation = percent(args[1])?;
+        let lightness = percent(args[2])?;
+
+        let [r, g, b] = hsl_to_rgb(hue, saturation, lightness);
+
+        Ok([r, g, b, alpha])
+    }
+}
+
+/// Parses either a percentage, or a plain number out of `max`, into a
+/// fraction within `[0, 1]`.
+fn fraction(arg: &str, max: f32) -> Option<f32> {
+    let (num, max) = if arg.ends_with('%') {
+        (arg[..arg.len() - 1].trim(), 100.0)
+    } else {
+        (arg, max)
+    };
+
+    num.parse::<f32>()
+        .ok()
+        .map(|n| n / max)
+        // Written this way around so that NaN is rejected too.
+        .filter(|&f| f >= 0.0 && f <= 1.0)
+}
+
+/// Converts hue (in degrees), saturation, and lightness (both within
+/// `[0, 1]`) into red, green, and blue (all within `[0, 1]`).
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> [f32; 3] {
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let m = lightness - chroma / 2.0;
+
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+
+    [r + m, g + m, b + m]
+}
+
+/// Looks up a colour by name, ignoring case.
+fn named(name: &str) -> Option<Color> {
+    let hex = match name.to_lowercase().as_str() {
+        "transparent" => return Some([0.0; 4]),
+        "black" => "#000000",
+        "white" => "#ffffff",
+        "red" => "#ff0000",
+        "lime" => "#00ff00",
+        "green" => "#008000",
+        "blue" => "#0000ff",
+        "yellow" => "#ffff00",
+        "cyan" | "aqua" => "#00ffff",
+        "magenta" | "fuchsia" => "#ff00ff",
+        "silver" => "#c0c0c0",
+        "gray" | "grey" => "#808080",
+        "maroon" => "#800000",
+        "olive" => "#808000",
+        "purple" => "#800080",
+        "teal" => "#008080",
+        "navy" => "#000080",
+        "orange" => "#ffa500",
+        "brown" => "#a52a2a",
+        "pink" => "#ffc0cb",
+        "gold" => "#ffd700",
+        "indigo" => "#4b0082",
+        "violet" => "#ee82ee",
+        "tan" => "#d2b48c",
+        "khaki" => "#f0e68c",
+        "salmon" => "#fa8072",
+        "coral" => "#ff7f50",
+        "crimson" => "#dc143c",
+        "slategray" | "slategrey" => "#708090",
+        "rebeccapurple" => "#663399",
+        _ => return None,
+    };
+
+    parse_hex(hex).ok()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+    use rand_pcg::Pcg64;
+
+    fn assert_close(a: Color, b: Color) {
+        for i in 0..4 {
+            assert!((a[i] - b[i]).abs() < 1e-3, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn hex() {
+        assert_close(parse_color("#f80").unwrap(), [1.0, 0.533, 0.0, 1.0]);
+        assert_close(parse_color("#f808").unwrap(), [1.0, 0.533, 0.0, 0.533]);
+        assert_close(
+            parse_color("#FF8000").unwrap(),
+            [1.0, 0.502, 0.0, 1.0],
+        );
+        assert_close(
+            parse_color(" #ff800080 ").unwrap(),
+            [1.0, 0.502, 0.0, 0.502],
+        );
+    }
+
+    #[test]
+    fn functions() {
+        assert_close(
+            parse_color("rgb(255, 128, 0)").unwrap(),
+            [1.0, 0.502, 0.0, 1.0],
+        );
+        assert_close(
+            parse_color("RGBA(100%, 50%, 0%, 0.5)").unwrap(),
+            [1.0, 0.5, 0.0, 0.5],
+        );
+        assert_close(
+            parse_color("hsl(120deg, 100%, 50%)").unwrap(),
+            [0.0, 1.0, 0.0, 1.0],
+        );
+        assert_close(
+            parse_color("hsla(-120, 100%, 25%, 50%)").unwrap(),
+            [0.0, 0.0, 0.5, 0.5],
+        );
+    }
+
+    #[test]
+    fn names() {
+        assert_close(parse_color("white").unwrap(), [1.0; 4]);
+        assert_close(parse_color("Transparent").unwrap(), [0.0; 4]);
+        assert_close(
+            parse_color("rebeccapurple").unwrap(),
+            [0.4, 0.2, 0.6, 1.0],
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_color("  "), Err(ColorParseError::Empty));
+        assert_eq!(
+            parse_color("#12345"),
+            Err(ColorParseError::BadHexLength {
+                color: "#12345".to_owned(),
+                len:   5,
+            }),
+        );
+        assert_eq!(
+            parse_color("#12g"),
+            Err(ColorParseError::BadHexDigit { color: "#12g".to_owned() }),
+        );
+        assert_eq!(
+            parse_color("cmyk(0, 0, 0, 0)"),
+            Err(ColorParseError::UnknownFunction { name: "cmyk".to_owned() }),
+        );
+        assert_eq!(
+            parse_color("rgb(0, 0)"),
+            Err(ColorParseError::BadArgCount {
+                color:    "rgb(0, 0)".to_owned(),
+                expected: 3,
+            }),
+        );
+        assert!(parse_color("rgb(256, 0, 0)").is_err());
+        assert!(parse_color("rgba(0, 0, 0, NaN)").is_err());
+        assert!(parse_color("hsl(inf, 50%, 50%)").is_err());
+        assert!(parse_color("hsl(0, 150%, 50%)").is_err());
+        assert!(parse_color("greenish").is_err());
+    }
+
+    #[test]
+    fn formatted_hex_round_trips() {
+        let mut rng = Pcg64::seed_from_u64(0);
+        for _ in 0..1000 {
+            let channels: [u8; 4] = rng.gen();
+            let formatted = format!(
+                "#{:02x}{:02x}{:02x}{:02x}",
+                channels[0], channels[1], channels[2], channels[3],
+            );
+
+            let color = parse_color(&formatted).unwrap();
+            for i in 0..4 {
+                let channel = (color[i] * 255.0).round() as u8;
+                assert_eq!(channel, channels[i], "{}", formatted);
+            }
+        }
+    }
+
+    #[test]
+    fn arbitrary_strings_are_errors_not_panics() {
+        let pieces = [
+            "#", "(", ")", ",", "%", "deg", "rgb", "rgba", "hsl", "hsla",
+            " ", "-", ".", "0", "1", "255", "1e40", "NaN", "inf", "f", "é",
+            "\u{1F308}", "\0",
+        ];
+
+        let mut rng = Pcg64::seed_from_u64(0);
+        for _ in 0..10_000 {
+            let len = rng.gen_range(0, 12);
+            let s: String = (0..len)
+                .map(|_| *pieces.choose(&mut rng).unwrap())
+                .collect();
+
+            // Whatever happens, it mustn't panic, and anything that does
+            // parse must be a real colour.
+            if let Ok(color) = parse_color(&s) {
+                assert!(
+                    color.iter().all(|c| *c >= 0.0 && *c <= 1.0),
+                    "{:?} parsed as {:?}",
+                    s,
+                    color,
+                );
+            }
+        }
+    }
+}
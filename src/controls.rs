@@ -1,47 +1,213 @@
 use camera::Camera;
-use fnv::FnvHashSet as Set;
+use fnv::{FnvHashMap as Map, FnvHashSet as Set};
 use piston::input::keyboard::Key;
-use player::Player;
 use positioned::Positioned;
+use scene::Scene;
+use settings::{Input, Settings};
 
 
 pub struct Controls {
-    pressed_keys: Set<Key>,
+    pressed_keys:     Set<Key>,
+    bindings:         Map<Key, Action>,
+    /// Where the cursor was last seen while dragging the camera around, in
+    /// pixels, or `None` when it isn't being dragged.
+    drag:             Option<f64>,
+    /// Radians per second that holding a rotate key turns the camera by.
+    rotate_speed:     f64,
+    /// Radians that dragging the cursor one pixel turns the camera by.
+    drag_sensitivity: f64,
+    /// Whether the camera turns back to the nearest hex-aligned angle once
+    /// it's let go of.
+    snap_back:        bool,
+}
+
+/// Something that a key can be bound to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    TurnLeft,
+    TurnRight,
+    RotateLeft,
+    RotateRight,
+    Attack,
+    ZoomIn,
+    ZoomOut,
+    QuickSave,
+    QuickLoad,
+    Options,
+    NextTheme,
+    DebugOverlay,
+}
+
+/// Something that a keypress asks of the game as a whole, rather than of
+/// anything within the scene.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    QuickSave,
+    QuickLoad,
+    OpenOptions,
+    NextTheme,
+    ToggleDebugOverlay,
 }
 
 
 impl Controls {
-    pub fn new() -> Self {
-        Controls {
-            pressed_keys: Set::with_capacity_and_hasher(8, Default::default()),
+    pub fn new(settings: &Settings) -> Self {
+        let mut controls = Controls {
+            pressed_keys:     Set::with_capacity_and_hasher(
+                8,
+                Default::default(),
+            ),
+            bindings:         Map::default(),
+            drag:             None,
+            rotate_speed:     0.0,
+            drag_sensitivity: 0.0,
+            snap_back:        false,
+        };
+        controls.apply_settings(settings);
+
+        controls
+    }
+
+    /// Picks up the key bindings and how the camera is turned from the
+    /// settings.
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        let camera = &settings.camera;
+        self.rotate_speed = camera.rotate_speed.to_radians();
+        self.drag_sensitivity = camera.drag_sensitivity.to_radians();
+        self.snap_back = camera.snap_back;
+        self.set_bindings(&settings.input);
+    }
+
+    /// Replaces all of the key bindings.
+    fn set_bindings(&mut self, input: &Input) {
+        let bindings = [
+            (input.move_forward, Action::MoveForward),
+            (input.move_backward, Action::MoveBackward),
+            (input.turn_left, Action::TurnLeft),
+            (input.turn_right, Action::TurnRight),
+            (input.rotate_left, Action::RotateLeft),
+            (input.rotate_right, Action::RotateRight),
+            (input.attack, Action::Attack),
+            (input.zoom_in, Action::ZoomIn),
+            (input.zoom_out, Action::ZoomOut),
+            (input.quicksave, Action::QuickSave),
+            (input.quickload, Action::QuickLoad),
+            (input.options, Action::Options),
+            (input.next_theme, Action::NextTheme),
+            (input.debug_overlay, Action::DebugOverlay),
+        ];
+
+        self.bindings = bindings.iter().cloned().collect();
+    }
+
+    pub fn press(&mut self, key: Key, scene: &mut Scene) -> Option<Command> {
+        if !self.pressed_keys.insert(key) {
+            return None;
+        }
+
+        let (cam, player) = (&mut scene.camera, &mut scene.player);
+
+        // The player moves relative to the camera, whichever way it's turned,
+        // and waits for cutscenes to finish before doing anything at all.
+        match self.bindings.get(&key) {
+            Some(Action::MoveForward)
+            | Some(Action::MoveBackward)
+            | Some(Action::TurnLeft)
+            | Some(Action::TurnRight)
+            | Some(Action::Attack) if cam.is_in_cutscene() => (),
+            Some(Action::MoveForward) => {
+                player.walk(cam.facing(), true);
+                cam.unit_move(true);
+            },
+            Some(Action::MoveBackward) => {
+                player.walk(cam.facing(), false);
+                cam.unit_move(false);
+            },
+            Some(Action::TurnLeft) => {
+                cam.turn(true);
+                player.turn(true);
+            },
+            Some(Action::TurnRight) => {
+                cam.turn(false);
+                player.turn(false);
+            },
+            // Held down rather than pressed, so handled in `step`.
+            Some(Action::RotateLeft) | Some(Action::RotateRight) => (),
+            Some(Action::Attack) => scene.attack(),
+            Some(Action::ZoomIn) => cam.zoom_step(true),
+            Some(Action::ZoomOut) => cam.zoom_step(false),
+            Some(Action::QuickSave) => return Some(Command::QuickSave),
+            Some(Action::QuickLoad) => return Some(Command::QuickLoad),
+            Some(Action::Options) => return Some(Command::OpenOptions),
+            Some(Action::NextTheme) => return Some(Command::NextTheme),
+            Some(Action::DebugOverlay) => {
+                return Some(Command::ToggleDebugOverlay)
+            },
+            None => (),
         }
+
+        None
     }
 
-    pub fn press(&mut self, key: Key, cam: &mut Camera, player: &mut Player) {
-        if self.pressed_keys.insert(key) {
-            match key {
-                Key::W => {
-                    cam.unit_move(true);
-                    player.unit_move(true);
-                },
-                Key::S => {
-                    cam.unit_move(false);
-                    player.unit_move(false);
-                },
-                Key::A => {
-                    cam.turn(true);
-                    player.turn(true);
-                },
-                Key::D => {
-                    cam.turn(false);
-                    player.turn(false);
-                },
-                _ => (),
-            }
+    pub fn release(&mut self, key: &Key, cam: &mut Camera) {
+        if self.pressed_keys.remove(key) && self.is_rotate_key(key) {
+            self.let_go(cam);
         }
     }
 
-    pub fn release(&mut self, key: &Key) {
-        self.pressed_keys.remove(key);
+    /// Keeps turning the camera while a rotate key is held down.
+    pub fn step(&self, dt: f64, cam: &mut Camera) {
+        let held = |action| {
+            self.pressed_keys
+                .iter()
+                .any(|key| self.bindings.get(key) == Some(&action))
+        };
+        let left = held(Action::RotateLeft);
+        let right = held(Action::RotateRight);
+
+        if left != right {
+            let direction = if left { 1.0 } else { -1.0 };
+            cam.rotate(direction * self.rotate_speed * dt);
+        }
+    }
+
+    /// Starts dragging the camera around with the cursor at `x`.
+    pub fn start_drag(&mut self, x: f64) {
+        self.drag = Some(x);
+    }
+
+    /// Turns the camera along with the cursor, if it's being dragged, so
+    /// that dragging right turns it right.
+    pub fn move_cursor(&mut self, x: f64, cam: &mut Camera) {
+        if let Some(ref mut last_x) = self.drag {
+            cam.rotate((*last_x - x) * self.drag_sensitivity);
+            *last_x = x;
+        }
+    }
+
+    pub fn end_drag(&mut self, cam: &mut Camera) {
+        if self.drag.take().is_some() {
+            self.let_go(cam);
+        }
+    }
+
+    fn is_rotate_key(&self, key: &Key) -> bool {
+        let action = self.bindings.get(key);
+
+        action == Some(&Action::RotateLeft)
+            || action == Some(&Action::RotateRight)
+    }
+
+    /// Snaps the camera back to a hex-aligned angle, if that's wanted and
+    /// nothing else is still turning it.
+    fn let_go(&self, cam: &mut Camera) {
+        let turning = self.drag.is_some()
+            || self.pressed_keys.iter().any(|key| self.is_rotate_key(key));
+
+        if self.snap_back && !turning {
+            cam.snap_angle();
+        }
     }
 }
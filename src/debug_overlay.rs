@@ -0,0 +1,338 @@
+//! An overlay for tracking down bugs: the coordinates of every hex on
+//! screen, outlines around them, how the player and camera are moving, and
+//! how long frames and updates take.
+
+use geometry::{CubePoint, HEXAGON_POLY};
+use graphics::{
+    character::CharacterCache,
+    math::{add, Vec2d},
+    text::Text,
+    types::Color,
+    Context,
+    Graphics,
+    Line,
+    Rectangle,
+    Transformed,
+};
+use matrix::{rot, scale_uni};
+use positioned::Positioned;
+use scene::Scene;
+use settings::{Corner, Settings};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+use transitioned_grid_pos::TransitionedGridPos;
+
+
+/// How many of the latest frames and updates are graphed.
+const SAMPLES: usize = 120;
+
+/// Size, in pixels, of each graph.
+const GRAPH_SIZE: [f64; 2] = [2.0 * SAMPLES as f64, 40.0];
+/// Milliseconds that the full height of a graph stands for.
+const GRAPH_MAX_MS: f64 = 50.0;
+/// Milliseconds marked by a line across each graph: one frame at 60 FPS.
+const GRAPH_MARK_MS: f64 = 1000.0 / 60.0;
+
+const LABEL_FONT_SIZE: u32 = 9;
+/// Smallest size, in pixels, of a hex that still gets its coordinates
+/// written on it.
+const MIN_LABELLED_SIZE: f64 = 28.0;
+
+/// Space, in pixels, between the panel's contents and its edges.
+const PADDING: f64 = 6.0;
+
+const BACKDROP_COLOR: Color = [0.0, 0.0, 0.0, 0.6];
+const TEXT_COLOR: Color = [0.9, 0.9, 0.9, 1.0];
+const OUTLINE_COLOR: Color = [1.0, 1.0, 0.4, 0.5];
+const LABEL_COLOR: Color = [1.0, 1.0, 0.7, 1.0];
+const FRAME_COLOR: Color = [0.4, 0.8, 1.0, 0.9];
+const UPDATE_COLOR: Color = [1.0, 0.6, 0.3, 0.9];
+const MARK_COLOR: Color = [1.0, 1.0, 1.0, 0.4];
+
+
+pub struct DebugOverlay {
+    shown:        bool,
+    /// Milliseconds between each of the latest frames, oldest first.
+    frame_times:  VecDeque<f64>,
+    /// Milliseconds that each of the latest updates took, oldest first.
+    update_times: VecDeque<f64>,
+    last_frame:   Option<Instant>,
+}
+
+/// A row of the panel.
+enum Row<'a> {
+    Text(String),
+    Graph(&'a VecDeque<f64>, Color),
+}
+
+
+impl DebugOverlay {
+    pub fn new(settings: &Settings) -> Self {
+        DebugOverlay {
+            shown:        settings.debug.overlay,
+            frame_times:  VecDeque::with_capacity(SAMPLES),
+            update_times: VecDeque::with_capacity(SAMPLES),
+            last_frame:   None,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.shown = !self.shown;
+    }
+
+    /// Should be called whenever a frame is drawn. Timings are kept track
+    /// of even while the overlay is hidden, so that the graphs are full as
+    /// soon as it's shown.
+    pub fn rendered(&mut self) {
+        let now = Instant::now();
+        if let Some(last) = self.last_frame {
+            push_sample(&mut self.frame_times, now - last);
+        }
+
+        self.last_frame = Some(now);
+    }
+
+    /// Should be called with how long each update took.
+    pub fn updated(&mut self, took: Duration) {
+        push_sample(&mut self.update_times, took);
+    }
+
+    pub fn draw<C, G>(&self,
+                      scene:    &Scene,
+                      settings: &Settings,
+                      glyphs:   &mut C,
+                      ctx:      &Context,
+                      g:        &mut G)
+        where C: CharacterCache<Texture=G::Texture>,
+              G: Graphics
+    {
+        if !self.shown {
+            return;
+        }
+
+        draw_hexes(scene, glyphs, ctx, g);
+
+        let mut rows = Vec::new();
+        rows.extend(movement_rows("Player", scene.player.grid_pos()));
+        rows.extend(movement_rows("Camera", scene.camera.grid_pos()));
+        rows.push(Row::Text(timing_line("Frame", &self.frame_times)));
+        rows.push(Row::Graph(&self.frame_times, FRAME_COLOR));
+        rows.push(Row::Text(timing_line("Update", &self.update_times)));
+        rows.push(Row::Graph(&self.update_times, UPDATE_COLOR));
+
+        draw_panel(&rows, settings, glyphs, ctx, g);
+    }
+}
+
+
+/// Outlines every hex on the map that's on screen, writing its cube, axial,
+/// and offset (row and column) coordinates on it if there's room.
+fn draw_hexes<C, G>(scene:  &Scene,
+                    glyphs: &mut C,
+                    ctx:    &Context,
+                    g:      &mut G)
+    where C: CharacterCache<Texture=G::Texture>,
+          G: Graphics
+{
+    let camera = &scene.camera;
+    let scale_factor = camera.scale_factor();
+    let viewport = camera.viewport();
+    let corner_transform =
+        rot(camera.angle().radians()) * scale_uni(scale_factor);
+    let corners: Vec<Vec2d> = HEXAGON_POLY
+        .iter()
+        .map(|&corner| corner_transform.vec_mul(corner))
+        .collect();
+    let outline = Line::new(OUTLINE_COLOR, 0.5);
+    let label = Text::new_color(LABEL_COLOR, LABEL_FONT_SIZE);
+    let line_height = 1.2 * f64::from(LABEL_FONT_SIZE);
+
+    for (_, x, y) in scene.map.iter_visible(camera) {
+        let q = x as i32;
+        let r = y as i32 - q / 2;
+        let pos = CubePoint::from_q_r(q, r);
+        let height = scene.map.height(pos).unwrap_or(0);
+        let center = add(camera.screen_pos(pos.cast()), camera.lift(height));
+
+        if center[0] < -scale_factor
+            || center[0] > viewport[0] + scale_factor
+            || center[1] < -scale_factor
+            || center[1] > viewport[1] + scale_factor
+        {
+            continue;
+        }
+
+        for (i, &from) in corners.iter().enumerate() {
+            let from = add(center, from);
+            let to = add(center, corners[(i + 1) % corners.len()]);
+            outline.draw(
+                [from[0], from[1], to[0], to[1]],
+                &ctx.draw_state,
+                ctx.transform,
+                g,
+            );
+        }
+
+        if scale_factor < MIN_LABELLED_SIZE {
+            continue;
+        }
+
+        let lines = [
+            format!("{},{},{}", pos.a, pos.b, pos.c),
+            format!("q{} r{}", q, r),
+            format!("x{} y{}", x, y),
+        ];
+        for (i, line) in lines.iter().enumerate() {
+            let width = glyphs.width(LABEL_FONT_SIZE, line).unwrap_or(0.0);
+            let baseline = center[1] + line_height * (i as f64 - 0.5);
+            let _ = label.draw(
+                line,
+                glyphs,
+                &ctx.draw_state,
+                ctx.transform.trans(center[0] - width / 2.0, baseline),
+                g,
+            );
+        }
+    }
+}
+
+/// Rows describing how something is moving.
+fn movement_rows(name: &str, pos: &TransitionedGridPos) -> Vec<Row<'static>> {
+    let (at, target) = (pos.pos(), pos.target_pos());
+
+    vec![
+        Row::Text(format!(
+            "{}: ({:.2}, {:.2}, {:.2}) -> ({}, {}, {})",
+            name, at.a, at.b, at.c, target.a, target.b, target.c,
+        )),
+        Row::Text(format!(
+            "  pos_state {:.2}  angle {:.0} -> {:.0}  angle_state {:.2}",
+            pos.pos_state(),
+            pos.angle().radians().to_degrees(),
+            pos.target_angle().radians().to_degrees(),
+            pos.angle_state(),
+        )),
+        Row::Text(format!(
+            "  waypoints {}  estimated_duration {:.2}s",
+            pos.waypoints().count(),
+            pos.estimated_duration(),
+        )),
+    ]
+}
+
+/// Summary of a series of timings, in milliseconds.
+fn timing_line(name: &str, times: &VecDeque<f64>) -> String {
+    let average = times.iter().sum::<f64>() / times.len().max(1) as f64;
+    let worst = times.iter().cloned().fold(0.0, f64::max);
+
+    format!("{}: {:.1} ms average, {:.1} ms worst", name, average, worst)
+}
+
+/// Draws the rows, one above the other, on a backdrop in the bottom right
+/// corner of the window.
+fn draw_panel<C, G>(rows:     &[Row],
+                    settings: &Settings,
+                    glyphs:   &mut C,
+                    ctx:      &Context,
+                    g:        &mut G)
+    where C: CharacterCache<Texture=G::Texture>,
+          G: Graphics
+{
+    let font_size = settings.hud.font_size;
+    let line_height = 1.5 * f64::from(font_size);
+    let row_height = |row: &Row| match *row {
+        Row::Text(_) => line_height,
+        Row::Graph(..) => GRAPH_SIZE[1] + PADDING,
+    };
+
+    let width = rows
+        .iter()
+        .map(|row| match *row {
+            Row::Text(ref text) =>
+                glyphs.width(font_size, text).unwrap_or(0.0),
+            Row::Graph(..) => GRAPH_SIZE[0],
+        })
+        .fold(0.0, f64::max);
+    let height: f64 = rows.iter().map(row_height).sum();
+    let size = [width + 2.0 * PADDING, height + 2.0 * PADDING];
+    let [left, top] = Corner::BottomRight.place(
+        size,
+        settings.hud.margin,
+        ctx.get_view_size(),
+    );
+
+    Rectangle::new(BACKDROP_COLOR).draw(
+        [left, top, size[0], size[1]],
+        &ctx.draw_state,
+        ctx.transform,
+        g,
+    );
+
+    let x = left + PADDING;
+    let mut y = top + PADDING;
+    for row in rows {
+        match *row {
+            Row::Text(ref text) => {
+                // Text is drawn up from its baseline.
+                let _ = Text::new_color(TEXT_COLOR, font_size).draw(
+                    text,
+                    glyphs,
+                    &ctx.draw_state,
+                    ctx.transform.trans(x, y + 0.75 * line_height),
+                    g,
+                );
+            },
+            Row::Graph(times, color) =>
+                draw_graph(times, color, [x, y], ctx, g),
+        }
+
+        y += row_height(row);
+    }
+}
+
+/// Draws a bar for each timing, with the top left of the graph at `corner`.
+fn draw_graph<G: Graphics>(times:  &VecDeque<f64>,
+                           color:  Color,
+                           corner: Vec2d,
+                           ctx:    &Context,
+                           g:      &mut G)
+{
+    let [left, top] = corner;
+    let bottom = top + GRAPH_SIZE[1];
+    let bar_width = GRAPH_SIZE[0] / SAMPLES as f64;
+    let to_height = |ms: f64| GRAPH_SIZE[1] * (ms / GRAPH_MAX_MS).min(1.0);
+
+    // The latest timing is at the right.
+    let start = left + bar_width * (SAMPLES - times.len()) as f64;
+    let bar = Rectangle::new(color);
+    for (i, &ms) in times.iter().enumerate() {
+        let height = to_height(ms);
+        bar.draw(
+            [start + bar_width * i as f64, bottom - height, bar_width, height],
+            &ctx.draw_state,
+            ctx.transform,
+            g,
+        );
+    }
+
+    let mark = bottom - to_height(GRAPH_MARK_MS);
+    Line::new(MARK_COLOR, 0.5).draw(
+        [left, mark, left + GRAPH_SIZE[0], mark],
+        &ctx.draw_state,
+        ctx.transform,
+        g,
+    );
+}
+
+/// Adds a timing, in milliseconds, dropping the oldest if there are too
+/// many.
+fn push_sample(times: &mut VecDeque<f64>, took: Duration) {
+    if times.len() >= SAMPLES {
+        times.pop_front();
+    }
+
+    let secs = took.as_secs() as f64 + f64::from(took.subsec_nanos()) * 1e-9;
+    times.push_back(secs * 1000.0);
+}
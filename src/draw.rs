@@ -1,25 +1,48 @@
-use drawable::Drawable;
+use debug_overlay::DebugOverlay;
 use graphics;
-use opengl_graphics::GlGraphics;
+use hud::Hud;
+use menu::OptionsMenu;
+use minimap;
+use opengl_graphics::{GlGraphics, GlyphCache, Texture};
 use piston::input::RenderArgs;
 use scene::Scene;
 use settings::Settings;
+use sprite::Sprites;
 
 
 pub const SPACING_FACTOR: f64 = 0.875;
 
+/// Id in the asset manifest of the font that all text is drawn in.
+pub const FONT_ID: &str = "mono";
+
+/// The same font, built in for when it can't be loaded.
+pub const FONT: &[u8] = include_bytes!("../assets/fonts/DejaVuSansMono.ttf");
+
 
 /// Start drawing to the screen.
 pub fn draw(gl:          &mut GlGraphics,
+            glyphs:      &mut GlyphCache,
             render_args: &RenderArgs,
             settings:    &Settings,
-            scene:       &Scene)
+            scene:       &Scene,
+            sprites:     Option<&Sprites<Texture>>,
+            hud:         &Hud,
+            overlay:     &DebugOverlay,
+            menu:        &OptionsMenu)
 {
     gl.draw(render_args.viewport(), |ctx, g| {
         // Clear the entire window.
         graphics::clear(settings.colors.background_color, g);
 
         // Draw the scene.
-        scene.draw(&scene.camera, &ctx, g);
+        scene.draw_with(&scene.camera, &ctx, g, sprites);
+        minimap::draw(scene, settings, &ctx, g);
+        hud.draw(scene, settings, glyphs, &ctx, g);
+        overlay.draw(scene, settings, glyphs, &ctx, g);
+
+        // Draw the options menu over the top of everything else.
+        if menu.is_open() {
+            menu.draw(settings, glyphs, &ctx, g);
+        }
     });
 }
@@ -0,0 +1,382 @@
+//! Easing curves, for making transitions start and stop gracefully.
+//!
+//! Every curve maps progress `t` in `[0, 1]` to eased progress, with `0`
+//! mapping to `0` and `1` mapping to `1`. Curves in the "back" and "elastic"
+//! families overshoot in between, so their output is not confined to
+//! `[0, 1]`.
+
+use geometry::{bezier2, PI_2};
+use std::str::FromStr;
+
+
+/// How far "back" curves overshoot by.
+const BACK_OVERSHOOT: f64 = 1.701_58;
+
+/// Period of the oscillations of "elastic" curves.
+const ELASTIC_PERIOD: f64 = 0.3;
+
+/// Iterations of Newton's method to try before falling back to bisection,
+/// when inverting a cubic Bezier.
+const NEWTON_ITERATIONS: usize = 8;
+
+const BEZIER_EPSILON: f64 = 1e-7;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Easing {
+    Linear,
+    /// Quadratic Bezier with control points `0`, `p1` and `1`.
+    QuadBezier(f64),
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    QuintIn,
+    QuintOut,
+    QuintInOut,
+    BackIn,
+    BackOut,
+    BackInOut,
+    ElasticIn,
+    ElasticOut,
+    ElasticInOut,
+    BounceIn,
+    BounceOut,
+    BounceInOut,
+    /// Cubic Bezier running from `(0, 0)` to `(1, 1)` with the two given
+    /// control points `(x1, y1)` and `(x2, y2)`, the same as CSS's
+    /// `cubic-bezier()`. Both `x1` and `x2` must lie within `[0, 1]`.
+    CubicBezier(f64, f64, f64, f64),
+}
+
+#[derive(Debug, Fail)]
+pub enum EasingParseError {
+    #[fail(display = "{:?} is not the name of an easing curve", name)]
+    UnknownName {
+        name: String,
+    },
+    #[fail(display = "{:?} takes {} argument(s), but got {:?}",
+           name, expected, args)]
+    BadArgs {
+        name:     String,
+        expected: usize,
+        args:     String,
+    },
+    #[fail(display = "The x coordinates of the control points of a cubic \
+                      Bezier easing must lie within [0, 1], but got {} and \
+                      {}", x1, x2)]
+    BezierOutOfRange {
+        x1: f64,
+        x2: f64,
+    },
+}
+
+
+impl Easing {
+    /// Eases the given progress, which is clamped to `[0, 1]` first.
+    pub fn apply(self, t: f64) -> f64 {
+        let t = t.max(0.0).min(1.0);
+
+        match self {
+            Easing::Linear => t,
+            Easing::QuadBezier(p1) => bezier2(0.0, p1, 1.0, t),
+            Easing::CubicIn => t * t * t,
+            Easing::CubicOut => out(t, |t| t * t * t),
+            Easing::CubicInOut => in_out(t, |t| t * t * t),
+            Easing::QuintIn => t.powi(5),
+            Easing::QuintOut => out(t, |t| t.powi(5)),
+            Easing::QuintInOut => in_out(t, |t| t.powi(5)),
+            Easing::BackIn => back_in(t),
+            Easing::BackOut => out(t, back_in),
+            Easing::BackInOut => in_out(t, back_in),
+            Easing::ElasticIn => elastic_in(t),
+            Easing::ElasticOut => out(t, elastic_in),
+            Easing::ElasticInOut => in_out(t, elastic_in),
+            Easing::BounceIn => 1.0 - bounce_out(1.0 - t),
+            Easing::BounceOut => bounce_out(t),
+            Easing::BounceInOut => in_out(t, |t| 1.0 - bounce_out(1.0 - t)),
+            Easing::CubicBezier(x1, y1, x2, y2) =>
+                cubic_bezier(x1, y1, x2, y2, t),
+        }
+    }
+}
+
+impl Default for Easing {
+    fn default() -> Self {
+        Easing::QuadBezier(0.75)
+    }
+}
+
+impl FromStr for Easing {
+    type Err = EasingParseError;
+
+    /// Parses either a bare name like `"cubic_out"`, or a parameterized curve
+    /// like `"quad_bezier(0.75)"` or `"cubic_bezier(0.25, 0.1, 0.25, 1)"`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, args) = match s.find('(') {
+            Some(i) if s.ends_with(')') =>
+                (s[..i].trim(), Some(&s[i + 1..s.len() - 1])),
+            _ => (s, None),
+        };
+
+        let parse_args = |expected: usize| {
+            let bad_args = || EasingParseError::BadArgs {
+                name: name.to_owned(),
+                expected,
+                args: args.unwrap_or("").to_owned(),
+            };
+
+            let parsed = args
+                .ok_or_else(bad_args)?
+                .split(',')
+                .map(|a| a.trim().parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| bad_args())?;
+
+            if parsed.len() == expected {
+                Ok(parsed)
+            } else {
+                Err(bad_args())
+            }
+        };
+
+        let easing = match name {
+            "quad_bezier" => Easing::QuadBezier(parse_args(1)?[0]),
+            "cubic_bezier" => {
+                let a = parse_args(4)?;
+                if a[0] < 0.0 || a[0] > 1.0 || a[2] < 0.0 || a[2] > 1.0 {
+                    return Err(EasingParseError::BezierOutOfRange {
+                        x1: a[0],
+                        x2: a[2],
+                    });
+                }
+
+                Easing::CubicBezier(a[0], a[1], a[2], a[3])
+            },
+            _ => match named(name) {
+                Some(easing) if args.is_none() => easing,
+                Some(_) => return Err(EasingParseError::BadArgs {
+                    name:     name.to_owned(),
+                    expected: 0,
+                    args:     args.unwrap_or("").to_owned(),
+                }),
+                None => return Err(EasingParseError::UnknownName {
+                    name: name.to_owned(),
+                }),
+            },
+        };
+
+        Ok(easing)
+    }
+}
+
+
+/// Looks up one of the curves that doesn't take any arguments by name.
+fn named(name: &str) -> Option<Easing> {
+    let easing = match name {
+        "linear" => Easing::Linear,
+        "cubic_in" => Easing::CubicIn,
+        "cubic_out" => Easing::CubicOut,
+        "cubic_in_out" => Easing::CubicInOut,
+        "quint_in" => Easing::QuintIn,
+        "quint_out" => Easing::QuintOut,
+        "quint_in_out" => Easing::QuintInOut,
+        "back_in" => Easing::BackIn,
+        "back_out" => Easing::BackOut,
+        "back_in_out" => Easing::BackInOut,
+        "elastic_in" => Easing::ElasticIn,
+        "elastic_out" => Easing::ElasticOut,
+        "elastic_in_out" => Easing::ElasticInOut,
+        "bounce_in" => Easing::BounceIn,
+        "bounce_out" => Easing::BounceOut,
+        "bounce_in_out" => Easing::BounceInOut,
+        _ => return None,
+    };
+
+    Some(easing)
+}
+
+/// Turns an "in" curve into the corresponding "out" curve.
+fn out<F: Fn(f64) -> f64>(t: f64, ease_in: F) -> f64 {
+    1.0 - ease_in(1.0 - t)
+}
+
+/// Turns an "in" curve into the corresponding "in-out" curve.
+fn in_out<F: Fn(f64) -> f64>(t: f64, ease_in: F) -> f64 {
+    if t < 0.5 {
+        ease_in(2.0 * t) / 2.0
+    } else {
+        1.0 - ease_in(2.0 - 2.0 * t) / 2.0
+    }
+}
+
+fn back_in(t: f64) -> f64 {
+    t * t * ((BACK_OVERSHOOT + 1.0) * t - BACK_OVERSHOOT)
+}
+
+fn elastic_in(t: f64) -> f64 {
+    if t <= 0.0 || t >= 1.0 {
+        return t;
+    }
+
+    let s = ELASTIC_PERIOD / 4.0;
+
+    -(2.0f64.powf(10.0 * (t - 1.0)) *
+      ((t - 1.0 - s) * PI_2 / ELASTIC_PERIOD).sin())
+}
+
+fn bounce_out(t: f64) -> f64 {
+    const N: f64 = 7.5625;
+    const D: f64 = 2.75;
+
+    if t < 1.0 / D {
+        N * t * t
+    } else if t < 2.0 / D {
+        let t = t - 1.5 / D;
+        N * t * t + 0.75
+    } else if t < 2.5 / D {
+        let t = t - 2.25 / D;
+        N * t * t + 0.9375
+    } else {
+        let t = t - 2.625 / D;
+        N * t * t + 0.984_375
+    }
+}
+
+/// Evaluates a CSS-style cubic Bezier easing at `x = t`, by first finding the
+/// curve parameter that yields that `x`.
+fn cubic_bezier(x1: f64, y1: f64, x2: f64, y2: f64, t: f64) -> f64 {
+    // One-dimensional cubic Bezier with endpoints `0` and `1`.
+    let curve = |p1: f64, p2: f64, s: f64| {
+        let sc = 1.0 - s;
+        3.0 * sc * sc * s * p1 + 3.0 * sc * s * s * p2 + s * s * s
+    };
+    let slope = |p1: f64, p2: f64, s: f64| {
+        let sc = 1.0 - s;
+        3.0 * sc * sc * p1 +
+        6.0 * sc * s * (p2 - p1) +
+        3.0 * s * s * (1.0 - p2)
+    };
+
+    let mut s = t;
+    for _ in 0..NEWTON_ITERATIONS {
+        let err = curve(x1, x2, s) - t;
+        if err.abs() < BEZIER_EPSILON {
+            return curve(y1, y2, s);
+        }
+
+        let d = slope(x1, x2, s);
+        if d.abs() < BEZIER_EPSILON {
+            break;
+        }
+
+        s -= err / d;
+    }
+
+    // Newton's method didn't converge, so fall back to bisection, which
+    // always does since `x` is monotonic in `s` when `x1` and `x2` lie in
+    // `[0, 1]`.
+    let (mut lo, mut hi) = (0.0, 1.0);
+    s = t;
+    while hi - lo > BEZIER_EPSILON {
+        if curve(x1, x2, s) < t {
+            lo = s;
+        } else {
+            hi = s;
+        }
+
+        s = (lo + hi) / 2.0;
+    }
+
+    curve(y1, y2, s)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-9;
+
+    /// One of every kind of curve.
+    const ALL: [Easing; 18] = [
+        Easing::Linear,
+        Easing::QuadBezier(0.75),
+        Easing::CubicIn,
+        Easing::CubicOut,
+        Easing::CubicInOut,
+        Easing::QuintIn,
+        Easing::QuintOut,
+        Easing::QuintInOut,
+        Easing::BackIn,
+        Easing::BackOut,
+        Easing::BackInOut,
+        Easing::ElasticIn,
+        Easing::ElasticOut,
+        Easing::ElasticInOut,
+        Easing::BounceIn,
+        Easing::BounceOut,
+        Easing::BounceInOut,
+        Easing::CubicBezier(0.25, 0.1, 0.25, 1.0),
+    ];
+
+    /// Curves that neither overshoot nor bounce.
+    const MONOTONIC: [Easing; 10] = [
+        Easing::Linear,
+        Easing::QuadBezier(0.75),
+        Easing::QuadBezier(0.1),
+        Easing::CubicIn,
+        Easing::CubicOut,
+        Easing::CubicInOut,
+        Easing::QuintIn,
+        Easing::QuintOut,
+        Easing::QuintInOut,
+        Easing::CubicBezier(0.42, 0.0, 0.58, 1.0),
+    ];
+
+    #[test]
+    fn every_curve_starts_at_0_and_ends_at_1() {
+        for &easing in ALL.iter() {
+            assert!(easing.apply(0.0).abs() < EPSILON, "{:?}", easing);
+            assert!((easing.apply(1.0) - 1.0).abs() < EPSILON, "{:?}", easing);
+        }
+    }
+
+    #[test]
+    fn progress_is_clamped() {
+        for &easing in ALL.iter() {
+            assert_eq!(easing.apply(-0.5), easing.apply(0.0), "{:?}", easing);
+            assert_eq!(easing.apply(1.5), easing.apply(1.0), "{:?}", easing);
+        }
+    }
+
+    #[test]
+    fn curves_without_overshoot_never_go_backwards() {
+        for &easing in MONOTONIC.iter() {
+            let mut last = easing.apply(0.0);
+            for i in 1..=1000 {
+                let eased = easing.apply(f64::from(i) / 1000.0);
+                assert!(eased >= last - EPSILON, "{:?} at {}", easing, i);
+                assert!(eased <= 1.0 + EPSILON, "{:?} at {}", easing, i);
+                last = eased;
+            }
+        }
+    }
+
+    #[test]
+    fn names_parse() {
+        assert_eq!("cubic_out".parse::<Easing>().ok(), Some(Easing::CubicOut));
+        assert_eq!(
+            " quad_bezier( 0.5 ) ".parse::<Easing>().ok(),
+            Some(Easing::QuadBezier(0.5)),
+        );
+        assert_eq!(
+            "cubic_bezier(0.25, 0.1, 0.25, 1)".parse::<Easing>().ok(),
+            Some(Easing::CubicBezier(0.25, 0.1, 0.25, 1.0)),
+        );
+        assert!("sideways".parse::<Easing>().is_err());
+        assert!("linear(1)".parse::<Easing>().is_err());
+        assert!("quad_bezier".parse::<Easing>().is_err());
+        assert!("cubic_bezier(1.5, 0, 0, 1)".parse::<Easing>().is_err());
+    }
+}
@@ -41,7 +41,7 @@ pub enum Dir {
     UpRight   = 5,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct CubePoint<T> {
     pub a: T,
     pub b: T,
@@ -78,6 +78,27 @@ impl Into<usize> for Dir {
     }
 }
 
+impl Dir {
+    /// Finds the direction that the given unit displacement points in, if it
+    /// is in fact a unit displacement.
+    pub fn from_delta(delta: CubePoint<i32>) -> Option<Self> {
+        CUBE_DIRS
+            .iter()
+            .position(|d| *d == delta)
+            .map(|i| Dir::from(i as u8))
+    }
+
+    /// The direction nearest to the given angle.
+    pub fn from_angle(angle: Angle) -> Self {
+        Dir::from((angle.radians() / (PI / 3.0)).round() as u8 % 6)
+    }
+
+    /// The angle that something facing this direction is oriented at.
+    pub fn angle(self) -> Angle {
+        Angle::new(f64::from(self as u8) * PI / 3.0)
+    }
+}
+
 impl<T: Clone + Neg<Output=T> + Sub<Output=T>> CubePoint<T> {
     pub fn new(a: T, b: T, c: T) -> Self {
         CubePoint { a, b, c }
@@ -216,7 +237,9 @@ impl Angle {
                 ),
             }
         } else {
-            Angle { radians: (1.0 - t) * self.radians + t * end.radians }
+            // `t` may lie outside of `[0, 1]` for overshooting easings, so
+            // this still has to be wrapped.
+            Angle::new((1.0 - t) * self.radians + t * end.radians)
         }
     }
 }
@@ -296,6 +319,47 @@ pub fn cube_dir<D: Into<usize>>(dir: D) -> CubePoint<i32> {
     CUBE_DIRS[dir.into()]
 }
 
+/// All six neighbours of the given hex, in `Dir` order.
+pub fn cube_neighbors(cube_pos: CubePoint<i32>) -> [CubePoint<i32>; 6] {
+    let mut neighbors = [cube_pos; 6];
+    for (n, d) in neighbors.iter_mut().zip(CUBE_DIRS.iter()) {
+        *n += *d;
+    }
+
+    neighbors
+}
+
+/// Number of single-hex steps between two hexes.
+pub fn cube_distance(p0: CubePoint<i32>, p1: CubePoint<i32>) -> i32 {
+    let diff = p0 - p1;
+
+    diff.a.abs().max(diff.b.abs()).max(diff.c.abs())
+}
+
+/// Every hex on the straight line from `p0` to `p1`, inclusive of both ends.
+pub fn cube_line(p0: CubePoint<i32>,
+                 p1: CubePoint<i32>) -> Vec<CubePoint<i32>>
+{
+    let n = cube_distance(p0, p1);
+    if n == 0 {
+        return vec![p0];
+    }
+
+    // Nudge the start point so that lines running exactly along hex edges
+    // round consistently to one side.
+    let start = CubePoint::new(
+        f64::from(p0.a) + 1e-6,
+        f64::from(p0.b) + 1e-6,
+        f64::from(p0.c) - 2e-6,
+    );
+
+    (0..=n)
+        .map(|i| {
+            cube_round(cube_lerp(start, p1, f64::from(i) / f64::from(n)))
+        })
+        .collect()
+}
+
 pub fn cube_to_real<T: Into<f64>>(cube_pos: CubePoint<T>, size: f64) -> Vec2d {
     let cube_a = cube_pos.a.into();
     let x = size * 1.5 * cube_a;
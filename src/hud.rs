@@ -0,0 +1,199 @@
+//! The heads-up display: text drawn over the scene about the state of the
+//! game, anchored to the corners of the window.
+
+use geometry::Dir;
+use graphics::{
+    character::CharacterCache,
+    text::Text,
+    types::Color,
+    Context,
+    Graphics,
+    Rectangle,
+    Transformed,
+};
+use scene::Scene;
+use settings::{Corner, Settings};
+use std::collections::VecDeque;
+
+
+/// Most status messages shown at once. Older ones make way for newer ones.
+const MAX_MESSAGES: usize = 5;
+
+/// Seconds over which the frame rate is averaged.
+const FPS_INTERVAL: f64 = 0.5;
+
+/// Seconds that status messages spend fading out, at the end of their time.
+const FADE_TIME: f64 = 0.5;
+
+/// Space, in pixels, between the text and the edges of its backdrop.
+const PADDING: f64 = 6.0;
+
+const BACKDROP_COLOR: Color = [0.0, 0.0, 0.0, 0.5];
+const TEXT_COLOR: Color = [0.9, 0.9, 0.9, 1.0];
+const PROBLEM_COLOR: Color = [1.0, 0.5, 0.4, 1.0];
+
+
+pub struct Hud {
+    /// Status messages, oldest first, each with how many seconds it's been
+    /// shown for.
+    messages:  VecDeque<(String, f64)>,
+    /// A problem that stays on screen until it's dealt with.
+    problem:   Option<String>,
+    /// Frames drawn since the frame rate was last worked out.
+    frames:    u32,
+    /// Seconds since the frame rate was last worked out.
+    since_fps: f64,
+    fps:       f64,
+}
+
+
+impl Hud {
+    pub fn new() -> Self {
+        Hud {
+            messages:  VecDeque::new(),
+            problem:   None,
+            frames:    0,
+            since_fps: 0.0,
+            fps:       0.0,
+        }
+    }
+
+    /// Shows a message for a little while.
+    pub fn status(&mut self, message: String) {
+        if self.messages.len() >= MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+
+        self.messages.push_back((message, 0.0));
+    }
+
+    /// Shows a problem until it's replaced, or cleared with `None`.
+    pub fn set_problem(&mut self, problem: Option<String>) {
+        self.problem = problem;
+    }
+
+    /// Should be called whenever a frame is drawn, to keep track of the
+    /// frame rate.
+    pub fn rendered(&mut self) {
+        self.frames += 1;
+    }
+
+    pub fn step(&mut self, dt: f64, settings: &Settings) {
+        self.since_fps += dt;
+        if self.since_fps >= FPS_INTERVAL {
+            self.fps = f64::from(self.frames) / self.since_fps;
+            self.frames = 0;
+            self.since_fps = 0.0;
+        }
+
+        let message_time = settings.hud.message_time;
+        for message in self.messages.iter_mut() {
+            message.1 += dt;
+        }
+        self.messages.retain(|&(_, shown)| shown < message_time);
+    }
+
+    pub fn draw<C, G>(&self,
+                      scene:    &Scene,
+                      settings: &Settings,
+                      glyphs:   &mut C,
+                      ctx:      &Context,
+                      g:        &mut G)
+        where C: CharacterCache<Texture=G::Texture>,
+              G: Graphics
+    {
+        let hud = &settings.hud;
+        if !hud.shown {
+            return;
+        }
+
+        let player = scene.player.grid_pos();
+        let pos = player.target_pos();
+        let facing = Dir::from_angle(player.target_angle());
+        let info = [
+            format!("Position: {}, {}, {}", pos.a, pos.b, pos.c),
+            format!("Facing:   {}", compass(facing)),
+            format!("FPS:      {:.0}", self.fps),
+        ];
+        let info: Vec<_> =
+            info.iter().map(|line| (line.as_str(), TEXT_COLOR)).collect();
+        draw_block(&info, hud.info_corner, settings, glyphs, ctx, g);
+
+        let mut lines = Vec::new();
+        if let Some(ref problem) = self.problem {
+            lines.push((problem.as_str(), PROBLEM_COLOR));
+        }
+        for &(ref message, shown) in self.messages.iter() {
+            let left = hud.message_time - shown;
+            let mut color = TEXT_COLOR;
+            color[3] *= (left / FADE_TIME).min(1.0) as f32;
+
+            lines.push((message.as_str(), color));
+        }
+        draw_block(&lines, hud.messages_corner, settings, glyphs, ctx, g);
+    }
+}
+
+
+/// Draws lines of text, each in its own colour, on a backdrop tucked into
+/// the given corner of the window.
+fn draw_block<C, G>(lines:    &[(&str, Color)],
+                    corner:   Corner,
+                    settings: &Settings,
+                    glyphs:   &mut C,
+                    ctx:      &Context,
+                    g:        &mut G)
+    where C: CharacterCache<Texture=G::Texture>,
+          G: Graphics
+{
+    if lines.is_empty() {
+        return;
+    }
+
+    let font_size = settings.hud.font_size;
+    let line_height = 1.5 * f64::from(font_size);
+    // Anything that can't be measured is left out of the backdrop's width,
+    // and will be left out of the drawing too.
+    let width = lines
+        .iter()
+        .map(|&(text, _)| glyphs.width(font_size, text).unwrap_or(0.0))
+        .fold(0.0, f64::max);
+    let size = [
+        width + 2.0 * PADDING,
+        line_height * lines.len() as f64 + 2.0 * PADDING,
+    ];
+    let [left, top] =
+        corner.place(size, settings.hud.margin, ctx.get_view_size());
+
+    Rectangle::new(BACKDROP_COLOR).draw(
+        [left, top, size[0], size[1]],
+        &ctx.draw_state,
+        ctx.transform,
+        g,
+    );
+
+    for (i, &(text, color)) in lines.iter().enumerate() {
+        // Text is drawn up from its baseline.
+        let baseline = top + PADDING + line_height * (i as f64 + 0.75);
+        let _ = Text::new_color(color, font_size).draw(
+            text,
+            glyphs,
+            &ctx.draw_state,
+            ctx.transform.trans(left + PADDING, baseline),
+            g,
+        );
+    }
+}
+
+/// Name of the compass point that the given direction points to, where up is
+/// north.
+fn compass(dir: Dir) -> &'static str {
+    match dir {
+        Dir::Up => "north",
+        Dir::UpLeft => "north-west",
+        Dir::DownLeft => "south-west",
+        Dir::Down => "south",
+        Dir::DownRight => "south-east",
+        Dir::UpRight => "north-east",
+    }
+}
@@ -2,19 +2,37 @@
 
 //! A 2D action RPG, written in pure Rust
 
+mod assets;
+mod bench;
 mod camera;
+mod camera_effects;
+mod cli;
+mod color;
 mod controls;
+mod debug_overlay;
 mod draw;
 mod drawable;
+mod easing;
 mod geometry;
+mod hud;
 mod map_data;
 mod matrix;
+mod menu;
+mod minimap;
+mod npc;
+mod particles;
 mod player;
 mod positioned;
+mod replay;
+mod save;
 mod scene;
 mod settings;
+mod settings_file;
+mod sprite;
 mod temporal;
+mod tile_cache;
 mod transitioned_grid_pos;
+mod tween;
 mod window;
 
 extern crate failure;
@@ -22,35 +40,54 @@ extern crate failure;
 extern crate failure_derive;
 extern crate fnv;
 extern crate graphics;
+extern crate image;
 extern crate opengl_graphics;
 extern crate piston;
 extern crate rand;
+extern crate rand_pcg;
+extern crate rusttype;
 extern crate sdl2_window;
 #[macro_use]
 extern crate serde_derive;
 extern crate toml;
 extern crate vecmath;
 
+use assets::{Assets, TextureLoader};
 use camera::Camera;
-use controls::Controls;
-use failure::Error;
+use cli::Args;
+use controls::{Command, Controls};
+use debug_overlay::DebugOverlay;
+use failure::{err_msg, Error};
 use geometry::CubePoint;
-use map_data::simulated_map_data;
+use hud::Hud;
+use map_data::{simulated_map_data, TileStyle};
+use menu::OptionsMenu;
+use npc::Npc;
+use opengl_graphics::{Filter, GlyphCache, Texture, TextureSettings};
 use piston::{
     event_loop::Events,
     input::{
+        keyboard::Key,
         AfterRenderEvent,
         Button,
+        MouseButton,
+        MouseCursorEvent,
         PressEvent,
         ReleaseEvent,
         RenderEvent,
+        TextEvent,
         UpdateEvent,
     },
-    window::{OpenGLWindow, Window},
+    window::{AdvancedWindow, OpenGLWindow, Window},
 };
 use player::Player;
+use replay::{Replay, ReplayEvent};
+use save::{SaveGame, SavedMap, QUICKSAVE_PATH};
 use scene::Scene;
-use settings::Settings;
+use settings::{Settings, SettingsWatcher, Sources};
+use sprite::Sprites;
+use std::{env, time::Instant};
+use toml::Value;
 
 /// Entry point for the program.
 fn main() {
@@ -64,13 +101,126 @@ fn main() {
 
 /// Real entry point for the program.
 fn main_() -> Result<(), Error> {
-    let settings = Settings::get_from_recur("./ahistorics_settings.toml")?;
-    let player = Player::new(0.25, CubePoint::new(0.0, 0.0, 0.0), &settings);
-    let map = simulated_map_data(24, settings.colors.foreground_color)?;
-    let camera = Camera::new(0.4, CubePoint::new(0.0, 0.0, 0.0));
-    let scene = Scene::new(camera, map, player);
+    let args = cli::parse(env::args().skip(1))?;
+    if args.help {
+        print!("{}", cli::USAGE);
+        return Ok(());
+    }
+
+    let sources =
+        Sources::discover(args.settings.clone(), args.overrides.clone())?;
+    let settings = sources.load()?;
+    report_warnings(&settings);
+
+    if args.print_settings {
+        print!("{}", settings.describe());
+        return Ok(());
+    }
+    if args.bench {
+        return bench::run(&settings);
+    }
+    if args.check_assets {
+        return assets::check(&settings);
+    }
+
+    let scene = new_scene(&args, &settings)?;
+    let replay = match args.replay {
+        Some(ref path) => Some(Replay::read_from(path)?),
+        None => None,
+    };
+
+    if args.headless {
+        run_headless(scene, &settings, replay);
+
+        Ok(())
+    } else {
+        main_loop(
+            window::events(&settings.graphics),
+            window::init(&settings.graphics)?,
+            scene,
+            settings,
+            sources,
+            replay,
+        )
+    }
+}
+
+/// Sets up the scene, either from the map file given on the command line or
+/// from a freshly generated map.
+fn new_scene(args: &Args, settings: &Settings) -> Result<Scene, Error> {
+    let gameplay = &settings.gameplay;
+    let (map, intro) = match args.map {
+        Some(ref path) => SavedMap::load(path, settings)?,
+        None => {
+            let map = simulated_map_data(
+                gameplay.map_size,
+                args.seed.unwrap_or_else(rand::random),
+                TileStyle::new(settings),
+            )?;
+
+            (map, Vec::new())
+        },
+    };
+
+    let player = Player::new(
+        gameplay.player_anim_time,
+        CubePoint::new(0.0, 0.0, 0.0),
+        settings,
+    );
+    let npcs = map
+        .npcs()
+        .iter()
+        .enumerate()
+        .map(|(i, spawn)| Npc::new(gameplay.npc_anim_time, i, spawn, settings))
+        .collect();
+    let mut camera = Camera::new(
+        gameplay.camera_anim_time,
+        CubePoint::new(0.0, 0.0, 0.0),
+        settings,
+    );
+    camera.play_cutscene(&intro);
+
+    Ok(Scene::new(camera, map, player, npcs, settings))
+}
+
+/// Runs the game without a window, at a fixed timestep, until the replay
+/// runs out. Without a replay, this just makes sure that everything loads.
+fn run_headless(mut scene: Scene,
+                settings:  &Settings,
+                replay:    Option<Replay>)
+{
+    let mut controls = Controls::new(settings);
+    let dt = 1.0 / settings.graphics.ups as f64;
 
-    main_loop(window::events(), window::init()?, scene, &settings)
+    if let Some(mut replay) = replay {
+        while !replay.is_finished() {
+            for event in replay.due(scene.time) {
+                match event {
+                    ReplayEvent::Press(key) => {
+                        press(
+                            key,
+                            &mut controls,
+                            &mut scene,
+                            settings,
+                            &mut Vec::new(),
+                        );
+                    },
+                    ReplayEvent::Release(key) => {
+                        controls.release(&key, &mut scene.camera)
+                    },
+                }
+            }
+
+            controls.step(dt, &mut scene.camera);
+            scene.step(dt);
+        }
+    }
+
+    println!(
+        "Stopped after {:.2}s, with the player at {:?}.",
+        scene.time,
+        scene.player.grid_pos().target_pos(),
+    );
 }
 
 /// The main game loop.
@@ -78,42 +228,354 @@ fn main_loop<W>(
     mut events: Events,
     mut window: W,
     mut scene: Scene,
-    settings: &Settings,
+    mut settings: Settings,
+    sources: Sources,
+    mut replay: Option<Replay>,
 ) -> Result<(), Error>
 where
-    W: OpenGLWindow + Window,
+    W: AdvancedWindow + OpenGLWindow + Window,
 {
     // Initialize graphical backend.
     let mut gl = window::graphics_init(&mut window);
 
     // Initialize controls to handle keypresses, clicks, etc.
-    let mut controls = Controls::new();
+    let mut controls = Controls::new(&settings);
+
+    // Keep an eye on the settings files, so that changes to them show up
+    // without a restart.
+    let mut watcher = SettingsWatcher::new(sources);
+
+    // Sprites are small pixel art, which would be blurred by filtering.
+    let texture_settings = TextureSettings::new().filter(Filter::Nearest);
+    let load_texture: TextureLoader<Texture> = Box::new(move |path| {
+        Texture::from_path(path, &texture_settings)
+    });
+    // The game can do without any of its assets, so problems with them are
+    // only warnings.
+    let mut assets =
+        ok_or_warn(Assets::open(&settings.assets.manifest, load_texture));
+
+    let font = assets.as_mut().and_then(|a| ok_or_warn(a.font(draw::FONT_ID)));
+    let font = font.as_ref().map_or(draw::FONT, |f| &f[..]);
+    let mut glyphs = GlyphCache::from_bytes(font, (), TextureSettings::new())
+        .map_err(|_| err_msg("Couldn't load the font"))?;
+    let sprites_path = &settings.sprites.path;
+    let sprites = assets
+        .as_mut()
+        .filter(|_| settings.sprites.shown)
+        .and_then(|a| ok_or_warn(Sprites::load(sprites_path, a)));
+    let mut menu = OptionsMenu::new();
+    let mut hud = Hud::new();
+    let mut overlay = DebugOverlay::new(&settings);
+    // Where the mouse cursor was last seen, in pixels.
+    let mut cursor = [0.0, 0.0];
 
     while let Some(event) = events.next(&mut window) {
+        // Settings changed from within the game, to be saved once the event
+        // has been handled.
+        let mut changes = Vec::new();
+        // Settings changed from within the game for this session only.
+        let mut session_changes = Vec::new();
+        // Status messages for the HUD.
+        let mut messages = Vec::new();
+
         // Event triggered by a render.
         if let Some(render_args) = event.render_args() {
-            draw::draw(&mut gl, &render_args, &settings, &scene);
+            scene.camera.set_viewport(render_args.window_size);
+            draw::draw(
+                &mut gl,
+                &mut glyphs,
+                &render_args,
+                &settings,
+                &scene,
+                sprites.as_ref().filter(|_| settings.sprites.shown),
+                &hud,
+                &overlay,
+                &menu,
+            );
+            hud.rendered();
+            overlay.rendered();
         }
 
         // Event triggered by the end of rendering.
         if event.after_render_args().is_some() {}
 
-        // Event triggered by an "update" (done `ups` times per second, here
-        // we've set `ups = 60`).
+        // Event triggered by an "update" (done `ups` times per second, as
+        // set by the `graphics.ups` setting).
         if let Some(update_args) = event.update_args() {
+            let due = replay.as_mut().map(|r| r.due(scene.time));
+            for event in due.unwrap_or_default() {
+                match event {
+                    ReplayEvent::Press(key) => {
+                        let command = press(
+                            key,
+                            &mut controls,
+                            &mut scene,
+                            &settings,
+                            &mut messages,
+                        );
+                        if let Some(command) = command {
+                            session_changes.extend(window_command(
+                                command,
+                                &settings,
+                                &mut menu,
+                                &mut overlay,
+                            ));
+                        }
+                    },
+                    ReplayEvent::Release(key) => {
+                        controls.release(&key, &mut scene.camera)
+                    },
+                }
+            }
+
+            controls.step(update_args.dt, &mut scene.camera);
+            let started = Instant::now();
             scene.step(update_args.dt);
+            overlay.updated(started.elapsed());
+            hud.step(update_args.dt, &settings);
+
+            match watcher.poll(update_args.dt) {
+                Some(Ok(new_settings)) => switch_settings(
+                    new_settings,
+                    &mut settings,
+                    &mut scene,
+                    &mut controls,
+                    &mut hud,
+                ),
+                // Keep going with the last good settings, but make sure that
+                // the problem is noticed.
+                Some(Err(e)) => {
+                    let message = error_chain(&e);
+                    eprintln!("Couldn't reload settings: {}.", message);
+                    hud.set_problem(Some(format!(
+                        "Couldn't reload settings: {}.",
+                        message,
+                    )));
+                },
+                None => (),
+            }
         }
 
-        // Event triggered by a keyboard key being depressed.
+        // Event triggered by a keyboard key being depressed. While the
+        // options menu is open, it gets every key.
         if let Some(Button::Keyboard(key)) = event.press_args() {
-            controls.press(key, &mut scene.camera, &mut scene.player);
+            if menu.is_open() {
+                changes.extend(menu.press(key, &settings));
+            } else if key == Key::Escape {
+                window.set_should_close(true);
+            } else {
+                let command = press(
+                    key,
+                    &mut controls,
+                    &mut scene,
+                    &settings,
+                    &mut messages,
+                );
+                session_changes.extend(command.and_then(|c| {
+                    window_command(c, &settings, &mut menu, &mut overlay)
+                }));
+            }
+        }
+
+        // Event triggered by the mouse cursor moving.
+        if let Some(pos) = event.mouse_cursor_args() {
+            cursor = pos;
+            controls.move_cursor(pos[0], &mut scene.camera);
+        }
+
+        // Event triggered by a mouse button being depressed. Clicks on the
+        // minimap are only the minimap's.
+        if let Some(Button::Mouse(button)) = event.press_args() {
+            let on_minimap = minimap::contains(&scene, &settings, cursor);
+
+            match button {
+                _ if menu.is_open() => (),
+                MouseButton::Left if on_minimap =>
+                    minimap::click(&mut scene, &settings, cursor),
+                // Dragging with the right mouse button turns the camera.
+                MouseButton::Right if !on_minimap =>
+                    controls.start_drag(cursor[0]),
+                _ => (),
+            }
+        }
+
+        // Event triggered by a mouse button being released.
+        if let Some(Button::Mouse(MouseButton::Right)) = event.release_args() {
+            controls.end_drag(&mut scene.camera);
+        }
+
+        // Event triggered by text being typed.
+        if let Some(text) = event.text_args() {
+            if menu.is_open() {
+                menu.text(&text);
+            }
         }
 
         // Event triggered by a keyboard key being released.
         if let Some(Button::Keyboard(key)) = event.release_args() {
-            controls.release(&key);
+            controls.release(&key, &mut scene.camera);
+        }
+
+        for (name, value) in changes {
+            match watcher.write(&name, &value) {
+                Ok(new_settings) => {
+                    menu.saved(&name, &new_settings);
+                    switch_settings(
+                        new_settings,
+                        &mut settings,
+                        &mut scene,
+                        &mut controls,
+                        &mut hud,
+                    );
+                },
+                Err(e) => {
+                    let message = error_chain(&e);
+                    eprintln!("Couldn't save `{}`: {}.", name, message);
+                    menu.failed(message);
+                },
+            }
+        }
+
+        for (name, value) in session_changes {
+            match watcher.set_for_session(&name, value) {
+                Ok(new_settings) => switch_settings(
+                    new_settings,
+                    &mut settings,
+                    &mut scene,
+                    &mut controls,
+                    &mut hud,
+                ),
+                Err(e) => {
+                    let message = error_chain(&e);
+                    eprintln!("Couldn't change `{}`: {}.", name, message);
+                    messages.push(format!(
+                        "Couldn't change `{}`: {}.",
+                        name,
+                        message,
+                    ));
+                },
+            }
+        }
+
+        for message in messages {
+            hud.status(message);
         }
     }
 
     Ok(())
 }
+
+/// Switches over to newly loaded settings, picking up every change that can
+/// happen without a restart.
+fn switch_settings(new_settings: Settings,
+                   settings:     &mut Settings,
+                   scene:        &mut Scene,
+                   controls:     &mut Controls,
+                   hud:          &mut Hud)
+{
+    report_warnings(&new_settings);
+    for key in settings.needing_restart(&new_settings) {
+        eprintln!("Warning: `{}` will only change after a restart.", key);
+    }
+
+    scene.apply_settings(&new_settings);
+    controls.apply_settings(&new_settings);
+    *settings = new_settings;
+    hud.set_problem(None);
+}
+
+/// The value of `result`, or else `None` after warning about the error.
+fn ok_or_warn<T>(result: Result<T, Error>) -> Option<T> {
+    result.map_err(|e| eprintln!("Warning: {}.", error_chain(&e))).ok()
+}
+
+fn report_warnings(settings: &Settings) {
+    for warning in settings.warnings.iter() {
+        eprintln!("Warning: {}.", warning);
+    }
+}
+
+/// Flattens an error and all of its causes into one line.
+fn error_chain(e: &Error) -> String {
+    e.iter_chain()
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+/// Handles a key being pressed, whether by the player or by a replay, adding
+/// any status messages for the HUD to `messages`. Commands that need a
+/// window, as with `window_command`, are handed back to be carried out by
+/// the main loop.
+fn press(key:      Key,
+         controls: &mut Controls,
+         scene:    &mut Scene,
+         settings: &Settings,
+         messages: &mut Vec<String>) -> Option<Command>
+{
+    let command = controls.press(key, scene)?;
+    let done = match command {
+        Command::QuickSave => "Quicksaved",
+        Command::QuickLoad => "Quickloaded",
+        Command::OpenOptions
+        | Command::NextTheme
+        | Command::ToggleDebugOverlay => return Some(command),
+    };
+
+    // Failing to save or load shouldn't bring the whole game down, so just
+    // report it.
+    match run_command(command, scene, settings) {
+        Ok(()) => messages.push(format!("{}.", done)),
+        Err(e) => {
+            eprintln!("Couldn't carry out {:?}:", command);
+            e.iter_chain().for_each(|c| eprintln!("    {}.", c));
+            messages.push(format!(
+                "Couldn't carry out {:?}: {}.",
+                command,
+                error_chain(&e),
+            ));
+        },
+    }
+
+    None
+}
+
+/// Carries out a command issued by the controls.
+fn run_command(command:  Command,
+               scene:    &mut Scene,
+               settings: &Settings) -> Result<(), Error>
+{
+    match command {
+        Command::QuickSave =>
+            SaveGame::capture(scene, settings)?.write_to(QUICKSAVE_PATH),
+        Command::QuickLoad =>
+            SaveGame::read_from(QUICKSAVE_PATH)?.restore(scene, settings),
+        // Handed back to the main loop by `press`.
+        Command::OpenOptions
+        | Command::NextTheme
+        | Command::ToggleDebugOverlay => Ok(()),
+    }
+}
+
+/// Carries out a command that only makes sense with a window open,
+/// returning the dotted key and new value of any setting to be changed for
+/// the rest of the session, without being saved.
+fn window_command(command:  Command,
+                  settings: &Settings,
+                  menu:     &mut OptionsMenu,
+                  overlay:  &mut DebugOverlay) -> Option<(String, Value)>
+{
+    match command {
+        Command::OpenOptions => menu.open(),
+        Command::ToggleDebugOverlay => overlay.toggle(),
+        Command::NextTheme => {
+            let theme = settings.next_theme().to_owned();
+
+            return Some(("colors.theme".to_owned(), Value::String(theme)));
+        },
+        _ => (),
+    }
+
+    None
+}
@@ -1,30 +1,123 @@
 use camera::Camera;
+use color::shade;
 use draw::SPACING_FACTOR;
 use drawable::Drawable;
 use failure::Error;
-use geometry::{cube_to_real, CubePoint, HEXAGON_POLY};
-use graphics::{math::add, polygon::Polygon, types::Color, Context, Graphics};
-use matrix::{m, rot, scale_uni, trans};
+use fnv::FnvHashMap as Map;
+use geometry::{
+    cube_distance,
+    cube_line,
+    cube_neighbors,
+    cube_to_real,
+    CubePoint,
+    HEXAGON_POLY,
+};
+use graphics::{
+    draw_state::DrawState,
+    line::Line,
+    math::{add, dot, mul_scalar, Matrix2d, Vec2d},
+    polygon::Polygon,
+    types::Color,
+    Context,
+    Graphics,
+    Transformed,
+};
+use matrix::{m, rot, scale_uni, trans, Matrix};
+use npc::{NpcSpawn, Routine};
 use positioned::Positioned;
-use rand::{self, prelude::*};
-use window::{
-    HALF_WINDOW_HEIGHT,
-    HALF_WINDOW_WIDTH,
-    WINDOW_HEIGHT,
-    WINDOW_WIDTH,
+use rand::prelude::*;
+use rand_pcg::Pcg64;
+use settings::{Colors, Settings, Shading};
+use sprite::{draw_hexagon, TileTextures};
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::VecDeque,
+    ops::Range,
 };
+use tile_cache::{Hexagon, TileCache, Top};
+use tween::Tweenable;
+
+
+/// Largest difference in height between two adjacent tiles that can be
+/// walked between.
+pub const MAX_STEP_HEIGHT: i32 = 2;
+
+/// How far above the tile they're standing on an entity's eyes are, in units
+/// of tile height.
+pub const EYE_HEIGHT: i32 = 1;
+
+/// Brightness of the side walls of elevated tiles, relative to their tops,
+/// when facing straight down the screen.
+const WALL_SHADE: f32 = 0.65;
+
+/// Width, in pixels, of the outlines around tiles.
+pub const OUTLINE_WIDTH: f64 = 1.0;
+
+/// Most hexes that a map can have across, or down.
+pub const MAX_SIDE_LEN: usize = 1_024;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Hex {
     Blank,
-    Tile(i32),
+    /// A tile of the given height.
+    Tile(i32, Terrain),
+}
+
+/// What a tile is made of, which tints its colour.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Terrain {
+    Water,
+    Sand,
+    Grass,
+    Dirt,
+    Rock,
+}
+
+/// How much the player knows about a hex.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    /// Never seen, so not drawn at all.
+    Unseen,
+    /// Seen before, but out of sight now, so drawn dimmed.
+    Remembered,
+    Visible,
 }
 
 #[derive(Clone)]
 pub struct MapData {
-    row_size: usize,
-    data:     Vec<Hex>,
-    poly:     Polygon,
+    row_size:   usize,
+    data:       Vec<Hex>,
+    npcs:       Vec<NpcSpawn>,
+    /// Seed that this map was generated from, if it was generated at all.
+    seed:       Option<u64>,
+    style:      TileStyle,
+    /// How much the player knows about each hex, in the same order as
+    /// `data`.
+    visibility: Vec<Visibility>,
+    /// Indices of the hexes that are currently visible.
+    in_sight:   Vec<usize>,
+    /// Position and radius that sight was last worked out from.
+    viewpoint:  Option<(CubePoint<i32>, i32)>,
+    /// Lowest and highest tile heights. Changing tiles can only widen this,
+    /// so it may end up wider than it needs to be, but never narrower.
+    heights:    (i32, i32),
+    /// Geometry for drawing the map flat, kept between frames.
+    cache:      RefCell<TileCache>,
+}
+
+/// How tiles are coloured and lit.
+#[derive(Clone, Debug)]
+pub struct TileStyle {
+    colors:     Colors,
+    shading:    Shading,
+    /// Screen-space unit vector pointing towards the light.
+    light:      Vec2d,
+    fog_of_war: bool,
+    /// Whether flat maps are drawn from cached batches, rather than tile by
+    /// tile.
+    batched:    bool,
 }
 
 pub struct MapDataIter<'a> {
@@ -34,18 +127,52 @@ pub struct MapDataIter<'a> {
 }
 
 impl MapData {
-    pub fn new(row_size: usize, data: Vec<Hex>, color: Color) -> Self {
+    pub fn new(row_size: usize,
+               data:     Vec<Hex>,
+               npcs:     Vec<NpcSpawn>,
+               style:    TileStyle) -> Self
+    {
+        let mut heights = data.iter().filter_map(|hex| match *hex {
+            Hex::Tile(h, _) => Some(h),
+            Hex::Blank => None,
+        });
+        let first = heights.next().unwrap_or(0);
+        let heights = heights.fold((first, first), |(low, high), h| {
+            (low.min(h), high.max(h))
+        });
+
         Self {
             row_size,
+            visibility: vec![Visibility::Unseen; data.len()],
             data,
-            poly: Polygon::new(color),
+            npcs,
+            seed: None,
+            style,
+            in_sight: Vec::new(),
+            viewpoint: None,
+            heights,
+            cache: RefCell::default(),
         }
     }
 
+    pub fn set_style(&mut self, style: TileStyle) {
+        self.style = style;
+        self.cache.get_mut().clear();
+    }
+
     pub fn data(&self) -> &Vec<Hex> {
         &self.data
     }
 
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// The NPCs that this map starts out populated with.
+    pub fn npcs(&self) -> &[NpcSpawn] {
+        &self.npcs
+    }
+
     pub fn row_size(&self) -> usize {
         self.row_size
     }
@@ -65,14 +192,628 @@ impl MapData {
     }
 
     pub fn get_axial(&self, q: i32, r: i32) -> Option<&Hex> {
-        self.data
-            .get(((r + q / 2) * self.row_size as i32 + q) as usize)
+        if q < 0 || q >= self.row_size as i32 {
+            return None;
+        }
+
+        let y = r + q / 2;
+        if y < 0 {
+            return None;
+        }
+
+        self.get_rect(q as usize, y as usize)
+    }
+
+    pub fn get(&self, pos: CubePoint<i32>) -> Option<&Hex> {
+        self.get_axial(pos.a, pos.c)
     }
 
     pub fn get_rect(&self, x: usize, y: usize) -> Option<&Hex> {
+        if x >= self.row_size {
+            return None;
+        }
+
         self.data.get(y * self.row_size + x)
     }
 
+    /// Index into `data` of the hex at the given position.
+    fn index(&self, pos: CubePoint<i32>) -> Option<usize> {
+        let q = pos.a;
+        let y = pos.c + q / 2;
+        if q < 0 || q >= self.row_size as i32 || y < 0 {
+            return None;
+        }
+
+        let i = y as usize * self.row_size + q as usize;
+        if i < self.data.len() {
+            Some(i)
+        } else {
+            None
+        }
+    }
+
+    /// Replaces the hex at the given rectangular coordinates, returning the
+    /// old one. Coordinates outside of the map are ignored.
+    pub fn set_rect(&mut self, x: usize, y: usize, hex: Hex) -> Option<Hex> {
+        if x >= self.row_size {
+            return None;
+        }
+
+        // The new hex may block or open up the view.
+        self.viewpoint = None;
+        // Tiles are lit according to the heights of their neighbors too.
+        let cache = self.cache.get_mut();
+        for y in y.saturating_sub(1)..=y + 1 {
+            for x in x.saturating_sub(1)..=x + 1 {
+                cache.invalidate(x, y);
+            }
+        }
+        if let Hex::Tile(h, _) = hex {
+            self.heights = (self.heights.0.min(h), self.heights.1.max(h));
+        }
+
+        self.data
+            .get_mut(y * self.row_size + x)
+            .map(|old| ::std::mem::replace(old, hex))
+    }
+
+    /// How much the player knows about the hex at the given position. With
+    /// fog of war turned off, everything is visible.
+    pub fn visibility(&self, pos: CubePoint<i32>) -> Visibility {
+        if !self.style.fog_of_war {
+            return Visibility::Visible;
+        }
+
+        self.index(pos).map_or(Visibility::Unseen, |i| self.visibility[i])
+    }
+
+    /// Colour of the tile at the given position, before lighting but dimmed
+    /// if it's only remembered, or `None` if the player hasn't seen a tile
+    /// there.
+    pub fn tile_color(&self, pos: CubePoint<i32>) -> Option<Color> {
+        let (height, terrain) = match *self.get(pos)? {
+            Hex::Tile(h, t) => (h, t),
+            Hex::Blank => return None,
+        };
+
+        match self.visibility(pos) {
+            Visibility::Unseen => None,
+            visibility => Some(self.style.fogged(
+                self.style.base_color(height, terrain),
+                visibility,
+            )),
+        }
+    }
+
+    /// Makes visible every hex within `radius` steps of `viewpoint` that
+    /// can be seen from there, and leaves those that were visible before but
+    /// aren't anymore as remembered.
+    pub fn update_sight(&mut self, viewpoint: CubePoint<i32>, radius: i32) {
+        if self.viewpoint == Some((viewpoint, radius)) {
+            return;
+        }
+        self.viewpoint = Some((viewpoint, radius));
+
+        let previous = ::std::mem::replace(&mut self.in_sight, Vec::new());
+        for &i in previous.iter() {
+            self.visibility[i] = Visibility::Remembered;
+        }
+
+        for dq in -radius..=radius {
+            let r_min = (-radius).max(-dq - radius);
+            let r_max = radius.min(radius - dq);
+            for dr in r_min..=r_max {
+                let pos = viewpoint + CubePoint::from_q_r(dq, dr);
+                let i = match self.index(pos) {
+                    Some(i) => i,
+                    None => continue,
+                };
+
+                if self.has_line_of_sight(viewpoint, pos) {
+                    self.visibility[i] = Visibility::Visible;
+                    self.in_sight.push(i);
+                }
+            }
+        }
+
+        if self.style.fog_of_war {
+            let cache = self.cache.get_mut();
+            for &i in previous.iter().chain(self.in_sight.iter()) {
+                cache.invalidate(i % self.row_size, i / self.row_size);
+            }
+        }
+    }
+
+    /// The hexes that have been seen, as runs of `[start, length]` of
+    /// indices into `data`.
+    pub fn explored(&self) -> Vec<[usize; 2]> {
+        let mut runs: Vec<[usize; 2]> = Vec::new();
+        for (i, &v) in self.visibility.iter().enumerate() {
+            if v == Visibility::Unseen {
+                continue;
+            }
+
+            match runs.last_mut() {
+                Some(run) if run[0] + run[1] == i => run[1] += 1,
+                _ => runs.push([i, 1]),
+            }
+        }
+
+        runs
+    }
+
+    /// Marks the given runs of hexes, as from `explored`, as having been
+    /// seen. What's visible right now is worked out afresh by the next call
+    /// to `update_sight`.
+    pub fn set_explored(&mut self, runs: &[[usize; 2]]) {
+        for &[start, len] in runs {
+            let end = start.saturating_add(len).min(self.visibility.len());
+            for v in self.visibility.iter_mut().take(end).skip(start) {
+                *v = Visibility::Remembered;
+            }
+        }
+
+        self.viewpoint = None;
+        self.cache.get_mut().clear();
+    }
+
+    /// Height of the tile at the given position, if there is one there.
+    pub fn height(&self, pos: CubePoint<i32>) -> Option<i32> {
+        match self.get(pos) {
+            Some(Hex::Tile(h, _)) => Some(*h),
+            _ => None,
+        }
+    }
+
+    /// Whether something standing at `from` can walk directly onto the
+    /// adjacent hex `to`.
+    pub fn can_step(&self, from: CubePoint<i32>, to: CubePoint<i32>) -> bool {
+        match (self.height(from), self.height(to)) {
+            (Some(h0), Some(h1)) => (h1 - h0).abs() <= MAX_STEP_HEIGHT,
+            // Stepping off of the map (or a blank) onto any tile is allowed,
+            // so that nothing gets stranded.
+            (None, Some(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether an entity standing at `from` can see an entity standing at
+    /// `to`. Sight is only blocked by tiles that rise above the eyes of both
+    /// parties.
+    pub fn has_line_of_sight(&self,
+                             from: CubePoint<i32>,
+                             to:   CubePoint<i32>) -> bool
+    {
+        let eye_level = self.height(from).unwrap_or(0)
+            .max(self.height(to).unwrap_or(0)) + EYE_HEIGHT;
+
+        let line = cube_line(from, to);
+        let mut between =
+            line.iter().skip(1).take(line.len().saturating_sub(2));
+
+        between.all(|&p| match self.height(p) {
+            Some(h) => h <= eye_level,
+            None => true,
+        })
+    }
+
+    /// Finds a shortest walkable path from `from` to `to`, not including
+    /// `from` itself. Only hexes within `radius` steps of `from` are
+    /// searched.
+    pub fn find_path(&self,
+                     from:   CubePoint<i32>,
+                     to:     CubePoint<i32>,
+                     radius: i32) -> Option<Vec<CubePoint<i32>>>
+    {
+        if from == to {
+            return Some(Vec::new());
+        }
+        if cube_distance(from, to) > radius {
+            return None;
+        }
+
+        let mut came_from = Map::default();
+        let mut frontier = VecDeque::new();
+        came_from.insert(from, from);
+        frontier.push_back(from);
+
+        while let Some(current) = frontier.pop_front() {
+            if current == to {
+                let mut path = vec![current];
+                let mut p = current;
+                while let Some(&prev) = came_from.get(&p) {
+                    if prev == from {
+                        break;
+                    }
+
+                    path.push(prev);
+                    p = prev;
+                }
+                path.reverse();
+
+                return Some(path);
+            }
+
+            for &next in cube_neighbors(current).iter() {
+                if came_from.contains_key(&next)
+                    || cube_distance(from, next) > radius
+                    || !self.can_step(current, next)
+                {
+                    continue;
+                }
+
+                came_from.insert(next, current);
+                frontier.push_back(next);
+            }
+        }
+
+        None
+    }
+
+    /// Draws the map as raised columns with shaded sides, back to front, so
+    /// that nearer tiles cover farther ones. After each tile, `on_tile` is
+    /// called with the tile's position and a context lifted to its top, so
+    /// that whatever is standing there can be drawn over it in turn. Tiles
+    /// with one of `textures` have it on top.
+    pub fn draw_elevated<G, F>(&self,
+                               camera:      &Camera,
+                               ctx:         &Context,
+                               g:           &mut G,
+                               textures:    Option<&TileTextures<G::Texture>>,
+                               mut on_tile: F)
+        where G: Graphics,
+              F: FnMut(CubePoint<i32>, &Context, &mut G)
+    {
+        let scale_factor = camera.scale_factor();
+        let viewport = camera.viewport();
+        let corner_transform = rot(camera.angle().radians())
+            * scale_uni(scale_factor * SPACING_FACTOR);
+        let corners: Vec<Vec2d> = HEXAGON_POLY
+            .iter()
+            .map(|&corner| corner_transform.vec_mul(corner))
+            .collect();
+
+        // Walls facing off of the map, or onto blanks, go all the way down to
+        // just below the lowest tile.
+        let floor = self.heights.0 - 1;
+        let floor_lift = camera.lift(floor)[1];
+
+        let dirs = neighbor_dirs(camera.angle().radians());
+
+        let mut tiles: Vec<_> = self
+            .iter_visible(camera)
+            .filter_map(|(hex, x, y)| {
+                let (height, terrain) = match *hex {
+                    Hex::Tile(h, t) => (h, t),
+                    Hex::Blank => return None,
+                };
+                let q = x as i32;
+                let r = y as i32 - q / 2;
+                let pos = CubePoint::from_q_r(q, r);
+                let visibility = self.visibility(pos);
+                if visibility == Visibility::Unseen {
+                    return None;
+                }
+                let base = camera.screen_pos(pos.cast());
+
+                Some((pos, height, terrain, base, visibility))
+            })
+            .filter(|&(_, height, _, base, _)| {
+                let top = base[1] + camera.lift(height)[1];
+                let bottom = base[1] + floor_lift;
+
+                base[0] > -scale_factor
+                    && base[0] < viewport[0] + scale_factor
+                    && top.min(bottom) < viewport[1] + scale_factor
+                    && top.max(bottom) > -scale_factor
+            })
+            .collect();
+        // Farther tiles are higher up the screen.
+        tiles.sort_by(|a, b| {
+            a.3[1].partial_cmp(&b.3[1]).unwrap_or(Ordering::Equal)
+        });
+
+        for (pos, height, terrain, base, visibility) in tiles {
+            let top = add(base, camera.lift(height));
+            let color = self.style.base_color(height, terrain);
+
+            for (&neighbor, &outwards) in
+                cube_neighbors(pos).iter().zip(dirs.iter())
+            {
+                let bottom_height = self.height(neighbor).unwrap_or(floor);
+                // Walls facing up the screen are behind the tile's top, and
+                // walls onto taller neighbors are behind the neighbor.
+                if outwards[1] <= 0.0 || bottom_height >= height {
+                    continue;
+                }
+
+                // The edge facing the neighbor is made up of the two corners
+                // that are farthest towards it.
+                let mut edge = corners.clone();
+                edge.sort_by(|a, b| {
+                    dot(*b, outwards)
+                        .partial_cmp(&dot(*a, outwards))
+                        .unwrap_or(Ordering::Equal)
+                });
+                let bottom = add(base, camera.lift(bottom_height));
+                let wall = [
+                    add(top, edge[0]),
+                    add(top, edge[1]),
+                    add(bottom, edge[1]),
+                    add(bottom, edge[0]),
+                ];
+
+                let brightness =
+                    WALL_SHADE * self.style.brightness(outwards);
+                let wall_color =
+                    self.style.fogged(shade(color, brightness), visibility);
+                Polygon::new(wall_color).draw(
+                    &wall,
+                    &ctx.draw_state,
+                    ctx.transform,
+                    g,
+                );
+            }
+
+            let top_face: Vec<_> =
+                corners.iter().map(|&corner| add(top, corner)).collect();
+            let top_color = self.top_color(pos, height, terrain, &dirs);
+            let top_color = self.style.fogged(top_color, visibility);
+            match textures.and_then(|t| t.get(&terrain)) {
+                Some(texture) => draw_hexagon(
+                    &**texture,
+                    top_color,
+                    &top_face,
+                    &ctx.draw_state,
+                    ctx.transform,
+                    g,
+                ),
+                None => Polygon::new(top_color).draw(
+                    &top_face,
+                    &ctx.draw_state,
+                    ctx.transform,
+                    g,
+                ),
+            }
+            self.style.draw_outline(
+                &top_face,
+                OUTLINE_WIDTH / 2.0,
+                visibility,
+                &ctx.draw_state,
+                ctx.transform,
+                g,
+            );
+
+            on_tile(pos, &ctx.trans(top[0] - base[0], top[1] - base[1]), g);
+        }
+    }
+
+    /// Colour of the top of the tile at `pos`, lit according to how it
+    /// slopes, judging by its neighbors. `dirs` are as from `neighbor_dirs`.
+    fn top_color(&self,
+                 pos:     CubePoint<i32>,
+                 height:  i32,
+                 terrain: Terrain,
+                 dirs:    &[Vec2d; 6]) -> Color
+    {
+        let brightness = self.style.brightness(self.facing(pos, height, dirs));
+
+        shade(self.style.base_color(height, terrain), brightness)
+    }
+
+    /// Which way, and how steeply, the top of the tile at `pos` faces, as
+    /// for `TileStyle::brightness`, in the same space as `dirs`.
+    fn facing(&self, pos: CubePoint<i32>, height: i32, dirs: &[Vec2d; 6])
+        -> Vec2d
+    {
+        let mut uphill = [0.0, 0.0];
+        for (&neighbor, &dir) in cube_neighbors(pos).iter().zip(dirs.iter()) {
+            if let Some(h) = self.height(neighbor) {
+                uphill = add(uphill, mul_scalar(dir, f64::from(h - height)));
+            }
+        }
+
+        // Each axis has a neighbor on either side, so this averages the slope
+        // along the three axes.
+        mul_scalar(uphill, -1.0 / 3.0)
+    }
+
+    /// Every hex that the given camera might be able to see, along with its
+    /// rectangular coordinates, in the same order as `iter`. Only the hexes
+    /// near the screen are visited, so this takes about as long however big
+    /// the map is, but some of the hexes may still turn out to be just off
+    /// screen.
+    pub fn iter_visible<'a>(&'a self, camera: &Camera)
+        -> impl Iterator<Item=(&'a Hex, usize, usize)> + 'a
+    {
+        let (xs, ys) = self.visible_rect(camera);
+
+        ys.flat_map(move |y| {
+            xs.clone().map(move |x| (&self.data[y * self.row_size + x], x, y))
+        })
+    }
+
+    /// Ranges of rectangular coordinates that `iter_visible` covers.
+    fn visible_rect(&self, camera: &Camera) -> (Range<usize>, Range<usize>) {
+        let margin = camera.scale_factor();
+        let [width, height] = camera.viewport();
+        // Tiles can be lifted up into view from below the bottom of the
+        // screen, and their walls can hang down into view from above the
+        // top, so the area on the ground that needs visiting is taller.
+        let below = camera.lift(self.heights.1)[1].min(0.0);
+        let above = camera.lift(self.heights.0 - 1)[1].max(0.0);
+        let (top, bottom) = (-margin - above, height + margin - below);
+        let screen_corners = [
+            [-margin, top],
+            [width + margin, top],
+            [-margin, bottom],
+            [width + margin, bottom],
+        ];
+
+        // Bounds of the ground under the corners of the screen, in the
+        // same rectangular coordinates as `data`.
+        let (mut x_min, mut x_max) = (f64::INFINITY, f64::NEG_INFINITY);
+        let (mut y_min, mut y_max) = (f64::INFINITY, f64::NEG_INFINITY);
+        for &corner in screen_corners.iter() {
+            let pos = camera.ground_pos(corner);
+            let (x, y) = (pos.a, pos.c + pos.a / 2.0);
+            x_min = x_min.min(x);
+            x_max = x_max.max(x);
+            y_min = y_min.min(y);
+            y_max = y_max.max(y);
+        }
+
+        // Rounding `q / 2` in `y` can put a hex up to half a row off, so
+        // an extra hex is taken on each side to be safe.
+        let clamp = |v: f64, len: usize| v.max(0.0).min(len as f64) as usize;
+        let xs = clamp(x_min.floor() - 1.0, self.cols())
+            ..clamp(x_max.ceil() + 2.0, self.cols());
+        let ys = clamp(y_min.floor() - 1.0, self.rows())
+            ..clamp(y_max.ceil() + 2.0, self.rows());
+
+        (xs, ys)
+    }
+
+    /// Draws the map flat, from batches of tiles that are cached a chunk at
+    /// a time.
+    fn draw_batched<G: Graphics>(&self,
+                                 camera:   &Camera,
+                                 ctx:      &Context,
+                                 g:        &mut G,
+                                 textures: Option<&TileTextures<G::Texture>>)
+    {
+        let (xs, ys) = self.visible_rect(camera);
+        // Chunks are kept in hex grid units, so tiles face the way that they
+        // would with the camera unturned until they're drawn.
+        let dirs = neighbor_dirs(0.0);
+
+        let mut cache = self.cache.borrow_mut();
+        cache.draw(xs, ys, camera, ctx, g, textures, &self.style, |xs, ys| {
+            self.chunk_hexagons(xs, ys, &dirs)
+        });
+    }
+
+    /// The tops of the tiles with rectangular coordinates in the given
+    /// ranges, as drawn flat, before they're lit. `dirs` are as from
+    /// `neighbor_dirs`.
+    fn chunk_hexagons(&self,
+                      xs:   Range<usize>,
+                      ys:   Range<usize>,
+                      dirs: &[Vec2d; 6]) -> Vec<Hexagon>
+    {
+        let hexes = ys.flat_map(|y| xs.clone().map(move |x| (x, y)));
+
+        hexes.filter_map(|(x, y)| {
+            let (height, terrain) = match *self.get_rect(x, y)? {
+                Hex::Tile(h, t) => (h, t),
+                Hex::Blank => return None,
+            };
+            let q = x as i32;
+            let r = y as i32 - q / 2;
+            let pos = CubePoint::from_q_r(q, r);
+            let visibility = self.visibility(pos);
+            if visibility == Visibility::Unseen {
+                return None;
+            }
+
+            let depth_factor = 1.0 + f64::from(height) / 16.0;
+            let size = (SPACING_FACTOR * depth_factor).min(0.975);
+            let center = cube_to_real(pos, 1.0);
+            let mut corners = [[0.0; 2]; 6];
+            for (corner, &unit) in corners.iter_mut().zip(HEXAGON_POLY) {
+                *corner = add(center, mul_scalar(unit, size));
+            }
+
+            Some((corners, Top {
+                color: self.style.base_color(height, terrain),
+                facing: self.facing(pos, height, dirs),
+                visibility,
+                terrain,
+            }))
+        })
+        .collect()
+    }
+
+    /// Draws the map with the given textures on top of the tiles that have
+    /// them, and plain tops on the rest.
+    pub fn draw_with<G: Graphics>(&self,
+                                  camera:   &Camera,
+                                  ctx:      &Context,
+                                  g:        &mut G,
+                                  textures: Option<&TileTextures<G::Texture>>)
+    {
+        if camera.is_elevated() {
+            self.draw_elevated(camera, ctx, g, textures, |_, _, _| ());
+            return;
+        }
+        if self.style.batched {
+            self.draw_batched(camera, ctx, g, textures);
+            return;
+        }
+
+        let scale_factor = camera.scale_factor();
+        let viewport = camera.viewport();
+        let cam_rotation = rot(camera.angle().radians());
+        let dirs = neighbor_dirs(camera.angle().radians());
+
+        for (hex, x, y) in self.iter_visible(camera) {
+            let (height, terrain) = match *hex {
+                Hex::Tile(h, t) => (h, t),
+                Hex::Blank => continue,
+            };
+            let q = x as i32;
+            let r = y as i32 - q / 2;
+            let pos = CubePoint::from_q_r(q, r);
+            let visibility = self.visibility(pos);
+            if visibility == Visibility::Unseen {
+                continue;
+            }
+            let screen_pos = camera.screen_pos(pos.cast());
+
+            if screen_pos[0] > -scale_factor
+                && screen_pos[0] < viewport[0] + scale_factor
+                && screen_pos[1] > -scale_factor
+                && screen_pos[1] < viewport[1] + scale_factor
+            {
+                let depth_factor = 1.0 + f64::from(height) / 16.0;
+                let size =
+                    scale_factor * (SPACING_FACTOR * depth_factor).min(0.975);
+
+                let transform = cam_rotation
+                    * scale_uni(size)
+                    * trans(screen_pos)
+                    * m(ctx.transform);
+
+                let color = self.top_color(pos, height, terrain, &dirs);
+                let color = self.style.fogged(color, visibility);
+                match textures.and_then(|t| t.get(&terrain)) {
+                    Some(texture) => draw_hexagon(
+                        &**texture,
+                        color,
+                        HEXAGON_POLY,
+                        &ctx.draw_state,
+                        transform.repr,
+                        g,
+                    ),
+                    None => Polygon::new(color).draw(
+                        HEXAGON_POLY,
+                        &ctx.draw_state,
+                        transform.repr,
+                        g,
+                    ),
+                }
+                self.style.draw_outline(
+                    HEXAGON_POLY,
+                    OUTLINE_WIDTH / 2.0 / size,
+                    visibility,
+                    &ctx.draw_state,
+                    transform.repr,
+                    g,
+                );
+            }
+        }
+    }
+
     pub fn iter(&self) -> MapDataIter {
         MapDataIter {
             i:        0,
@@ -99,69 +840,192 @@ impl<'a> Iterator for MapDataIter<'a> {
 
 impl Drawable for MapData {
     fn draw<G: Graphics>(&self, camera: &Camera, ctx: &Context, g: &mut G) {
-        // TODO: `hex_scaled_height` should be dynamic state.
-        let hex_scaled_height = 12.0;
-        let scale_factor = f64::from(WINDOW_HEIGHT) / hex_scaled_height;
+        self.draw_with(camera, ctx, g, None);
+    }
+}
 
-        for (hex, x, y) in self.iter().filter(|h| h.0 != &Hex::Blank) {
-            let q = x as i32;
-            let r = y as i32 - q / 2;
-            let abs_cube_pos = CubePoint::from_q_r(q, r).cast();
+impl Terrain {
+    /// The terrain that generated tiles of the given height are made of.
+    pub fn for_height(height: i32) -> Self {
+        match height {
+            h if h <= -5 => Terrain::Water,
+            -4 | -3 => Terrain::Sand,
+            -2 ..= 0 => Terrain::Grass,
+            1 => Terrain::Dirt,
+            _ => Terrain::Rock,
+        }
+    }
+}
 
-            let tile_minus_cam = abs_cube_pos - *camera.pos();
+impl TileStyle {
+    pub fn new(settings: &Settings) -> Self {
+        let light_angle = settings.shading.light_angle.to_radians();
 
-            let cam_rotation = rot(camera.angle().radians());
-            let pos = add(
-                cam_rotation
-                    .vec_mul(cube_to_real(tile_minus_cam, scale_factor)),
-                [HALF_WINDOW_WIDTH, HALF_WINDOW_HEIGHT],
-            );
+        TileStyle {
+            colors:     settings.colors.clone(),
+            shading:    settings.shading.clone(),
+            // Screen-space y points down.
+            light:      [light_angle.cos(), -light_angle.sin()],
+            fog_of_war: settings.gameplay.fog_of_war,
+            batched:    settings.graphics.batch_tiles,
+        }
+    }
 
-            if pos[0] > -scale_factor
-                && pos[0] < f64::from(WINDOW_WIDTH) + scale_factor
-                && pos[1] > -scale_factor
-                && pos[1] < f64::from(WINDOW_HEIGHT) + scale_factor
-            {
-                let depth_factor = if let Hex::Tile(depth) = *hex {
-                    1.0 + f64::from(depth) / 16.0
-                } else {
-                    1.0
-                };
+    /// Colour of a tile of the given height and terrain, before lighting.
+    fn base_color(&self, height: i32, terrain: Terrain) -> Color {
+        let (low, high) = (self.shading.low_height, self.shading.high_height);
+        let t = f64::from(height - low) / f64::from(high - low);
+        let ground = Color::tween(
+            self.colors.low_ground_color,
+            self.colors.high_ground_color,
+            t.max(0.0).min(1.0),
+        );
 
-                let transform = cam_rotation
-                    * scale_uni(
-                        scale_factor
-                            * (SPACING_FACTOR * depth_factor).min(0.975),
-                    )
-                    * trans(pos)
-                    * m(ctx.transform);
+        let terrain_color = match terrain {
+            Terrain::Water => self.colors.water_color,
+            Terrain::Sand => self.colors.sand_color,
+            Terrain::Grass => self.colors.grass_color,
+            Terrain::Dirt => self.colors.dirt_color,
+            Terrain::Rock => self.colors.rock_color,
+        };
 
-                self.poly.draw(
-                    HEXAGON_POLY,
-                    &ctx.draw_state,
-                    transform.repr,
-                    g,
-                );
-            }
+        Color::tween(ground, terrain_color, self.shading.terrain_weight)
+    }
+
+    /// Brightness of a surface that faces in the given screen-space
+    /// direction, where the length of `facing` is how steeply it faces that
+    /// way: `0` for level ground, and `1` for a wall.
+    fn brightness(&self, facing: Vec2d) -> f32 {
+        let towards_light = dot(facing, self.light).max(-1.0).min(1.0);
+
+        (1.0 + self.shading.light_strength * towards_light) as f32
+    }
+
+    /// Colour that the given top of a tile is filled with, with the camera
+    /// turned by `rotation`.
+    pub fn top_color(&self, top: &Top, rotation: &Matrix) -> Color {
+        let brightness = self.brightness(rotation.vec_mul(top.facing));
+
+        self.fogged(shade(top.color, brightness), top.visibility)
+    }
+
+    /// Colour of the outline around the top of a tile with the given
+    /// visibility.
+    pub fn outline_color(&self, visibility: Visibility) -> Color {
+        self.fogged(self.colors.tile_outline_color, visibility)
+    }
+
+    /// Dims the colour of a hex that's only remembered.
+    fn fogged(&self, color: Color, visibility: Visibility) -> Color {
+        match visibility {
+            Visibility::Remembered =>
+                shade(color, self.shading.remembered_brightness as f32),
+            _ => color,
+        }
+    }
+
+    /// Outlines the polygon with the given corners, unless outlines are
+    /// turned off by making them transparent.
+    fn draw_outline<G: Graphics>(&self,
+                                 corners:    &[Vec2d],
+                                 radius:     f64,
+                                 visibility: Visibility,
+                                 draw_state: &DrawState,
+                                 transform:  Matrix2d,
+                                 g:          &mut G)
+    {
+        let color = self.outline_color(visibility);
+        if color[3] <= 0.0 {
+            return;
+        }
+
+        let line = Line::new(color, radius);
+        for (i, &from) in corners.iter().enumerate() {
+            let to = corners[(i + 1) % corners.len()];
+            let points = [from[0], from[1], to[0], to[1]];
+            line.draw(points, draw_state, transform, g);
         }
     }
 }
 
+
+/// Screen-space unit vectors pointing from a hex towards each of its
+/// neighbors, in the same order as `cube_neighbors`, as seen by a camera
+/// turned by the given angle, in radians.
+fn neighbor_dirs(angle: f64) -> [Vec2d; 6] {
+    let cam_rotation = rot(angle);
+    let origin = CubePoint::new(0, 0, 0);
+
+    let mut dirs = [[0.0; 2]; 6];
+    for (dir, &neighbor) in dirs.iter_mut().zip(cube_neighbors(origin).iter())
+    {
+        let screen_dir = cam_rotation.vec_mul(cube_to_real(neighbor, 1.0));
+        let len = dot(screen_dir, screen_dir).sqrt();
+        *dir = mul_scalar(screen_dir, 1.0 / len);
+    }
+
+    dirs
+}
+
+/// Generates a random map. The same `side_len` and `seed` always generate the
+/// same map, on every platform, since saves of generated maps only store the
+/// seed and what's changed since.
 pub fn simulated_map_data(
     side_len: usize,
-    color: Color,
+    seed: u64,
+    style: TileStyle,
 ) -> Result<MapData, Error> {
     let area = side_len * side_len;
     let mut data = Vec::with_capacity(area);
 
-    let mut rng = rand::thread_rng();
+    let mut rng = Pcg64::seed_from_u64(seed);
     for _ in 0..area {
         data.push(if rng.gen() {
             Hex::Blank
         } else {
-            Hex::Tile(rng.gen_range(-6, 3))
+            let height = rng.gen_range(-6, 3);
+            Hex::Tile(height, Terrain::for_height(height))
+        });
+    }
+
+    let mut tiles = data
+        .iter()
+        .enumerate()
+        .filter(|(_, hex)| hex != &&Hex::Blank)
+        .map(|(i, _)| {
+            let q = (i % side_len) as i32;
+            let r = (i / side_len) as i32 - q / 2;
+
+            CubePoint::from_q_r(q, r)
+        });
+
+    let mut npcs = Vec::with_capacity(side_len / 6);
+    for i in 0..side_len / 6 {
+        let pos = match tiles.nth(rng.gen_range(0, side_len)) {
+            Some(p) => p,
+            None => break,
+        };
+
+        let routine = if i % 2 == 0 {
+            Routine::Wander { radius: 3 }
+        } else {
+            let mut waypoints = vec![pos];
+            waypoints.extend(tiles.by_ref().take(2));
+
+            Routine::Patrol { waypoints }
+        };
+
+        npcs.push(NpcSpawn {
+            pos,
+            routine,
+            health:         10.0,
+            sight_radius:   5,
+            flee_threshold: 0.3,
         });
     }
 
-    Ok(MapData::new(side_len, data, color))
+    let mut map = MapData::new(side_len, data, npcs, style);
+    map.seed = Some(seed);
+
+    Ok(map)
 }
@@ -0,0 +1,378 @@
+//! The in-game options menu, which changes settings and saves them to the
+//! settings file.
+
+use graphics::{
+    character::CharacterCache,
+    text::Text,
+    types::Color,
+    Context,
+    Graphics,
+    Rectangle,
+    Transformed,
+};
+use piston::input::keyboard::Key;
+use settings::{key_from_name, Origin, Settings, DEFAULT_THEME};
+use toml::Value;
+
+
+const FONT_SIZE: u32 = 16;
+const LINE_HEIGHT: f64 = 24.0;
+const MARGIN: f64 = 32.0;
+/// How far from the left edge values are drawn, past their labels.
+const VALUE_OFFSET: f64 = 280.0;
+
+const BACKDROP_COLOR: Color = [0.0, 0.0, 0.0, 0.75];
+const TEXT_COLOR: Color = [0.9, 0.9, 0.9, 1.0];
+const SELECTED_COLOR: Color = [1.0, 0.85, 0.4, 1.0];
+const HINT_COLOR: Color = [0.6, 0.6, 0.6, 1.0];
+
+const HINT: &str = "Up/Down: choose   Left/Right: adjust   Return: edit   \
+                    Escape: close";
+
+/// Every setting that can be changed from the menu, in the order shown.
+const ENTRIES: &[Entry] = &[
+    Entry::theme("Theme", "colors.theme"),
+    Entry::color("Background colour", "background_color"),
+    Entry::color("Low ground colour", "low_ground_color"),
+    Entry::color("High ground colour", "high_ground_color"),
+    Entry::color("Tile outline colour", "tile_outline_color"),
+    Entry::color("Player colour", "player_color"),
+    Entry::color("Player outline colour", "player_outline_color"),
+    Entry::color("NPC colour", "npc_color"),
+    Entry::color("NPC outline colour", "npc_outline_color"),
+    Entry::binding("Move forward", "input.move_forward"),
+    Entry::binding("Move backward", "input.move_backward"),
+    Entry::binding("Turn left", "input.turn_left"),
+    Entry::binding("Turn right", "input.turn_right"),
+    Entry::binding("Rotate camera left", "input.rotate_left"),
+    Entry::binding("Rotate camera right", "input.rotate_right"),
+    Entry::binding("Attack", "input.attack"),
+    Entry::binding("Zoom in", "input.zoom_in"),
+    Entry::binding("Zoom out", "input.zoom_out"),
+    Entry::binding("Quicksave", "input.quicksave"),
+    Entry::binding("Quickload", "input.quickload"),
+    Entry::binding("Options", "input.options"),
+    Entry::binding("Next theme", "input.next_theme"),
+    Entry::binding("Debug overlay", "input.debug_overlay"),
+    Entry::number("Player step time", "gameplay.player_anim_time", 0.05),
+    Entry::number("Camera step time", "gameplay.camera_anim_time", 0.05),
+    Entry::number("NPC step time", "gameplay.npc_anim_time", 0.05),
+    Entry::number("Minimum zoom", "graphics.min_zoom", 0.05),
+    Entry::number("Maximum zoom", "graphics.max_zoom", 0.25),
+    Entry::number("Zoom step", "graphics.zoom_step", 0.05),
+    Entry::number("Tile height", "graphics.tile_height", 0.05),
+    Entry::number("Minimap size", "minimap.size", 16.0),
+    Entry::number("Minimap opacity", "minimap.opacity", 0.05),
+];
+
+
+pub struct OptionsMenu {
+    open:     bool,
+    /// Index into `ENTRIES` of the highlighted setting.
+    selected: usize,
+    editing:  Option<Editing>,
+    /// What became of the last change, shown under the settings.
+    status:   Option<String>,
+}
+
+enum Editing {
+    /// Typing in a new value, which so far is the given text.
+    Text(String),
+    /// Waiting for the key to bind.
+    Binding,
+}
+
+struct Entry {
+    label: &'static str,
+    /// The setting's dotted key, or for colours, the name of the colour.
+    key:   &'static str,
+    kind:  Kind,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    /// One of the themes, which the left and right arrow keys cycle through.
+    Theme,
+    /// A colour of the active theme.
+    Color,
+    Binding,
+    /// A number that the left and right arrow keys step up and down by the
+    /// given amount.
+    Number(f64),
+}
+
+
+impl OptionsMenu {
+    pub fn new() -> Self {
+        OptionsMenu {
+            open:     false,
+            selected: 0,
+            editing:  None,
+            status:   None,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn open(&mut self) {
+        self.open = true;
+        self.editing = None;
+        self.status = None;
+    }
+
+    /// Handles a key being pressed while the menu is open, returning the
+    /// dotted key and new value of any setting that it changes.
+    pub fn press(&mut self,
+                 key:      Key,
+                 settings: &Settings) -> Option<(String, Value)>
+    {
+        let entry = &ENTRIES[self.selected];
+        let setting = entry.setting(settings);
+
+        match self.editing.take() {
+            Some(Editing::Text(text)) => match key {
+                Key::Return => return Some((setting, Value::String(text))),
+                Key::Escape => (),
+                Key::Backspace => {
+                    let mut text = text;
+                    text.pop();
+                    self.editing = Some(Editing::Text(text));
+                },
+                // Anything else is typed in through `text`.
+                _ => self.editing = Some(Editing::Text(text)),
+            },
+            Some(Editing::Binding) => {
+                if key == Key::Escape {
+                    return None;
+                }
+
+                // Bindings are saved by the name that `key_from_name` takes,
+                // so keys that it doesn't know of can't be bound.
+                let name = format!("{:?}", key);
+                if key_from_name(&name).is_some() {
+                    return Some((setting, Value::String(name)));
+                }
+
+                self.status = Some(format!("{} can't be bound.", name));
+            },
+            None => match key {
+                Key::Escape => self.open = false,
+                _ if key == settings.input.options => self.open = false,
+                Key::Up => {
+                    let count = ENTRIES.len();
+                    self.selected = (self.selected + count - 1) % count;
+                },
+                Key::Down => {
+                    self.selected = (self.selected + 1) % ENTRIES.len();
+                },
+                Key::Left | Key::Right => {
+                    let forwards = key == Key::Right;
+                    let value = match entry.kind {
+                        Kind::Theme => {
+                            let theme = cycle_theme(settings, forwards);
+                            Value::String(theme.to_owned())
+                        },
+                        Kind::Number(step) => {
+                            let step = if forwards { step } else { -step };
+                            let value = settings.value(&setting);
+                            let value = number(value) + step;
+                            // Round away any error that builds up from
+                            // stepping, so that the file gets tidy numbers.
+                            Value::Float((value * 100.0).round() / 100.0)
+                        },
+                        _ => return None,
+                    };
+
+                    return Some((setting, value));
+                },
+                Key::Return => match entry.kind {
+                    Kind::Color => {
+                        let text = settings
+                            .value(&entry.source(settings))
+                            .and_then(Value::as_str)
+                            .unwrap_or_default()
+                            .to_owned();
+                        self.editing = Some(Editing::Text(text));
+                    },
+                    Kind::Binding => self.editing = Some(Editing::Binding),
+                    Kind::Theme | Kind::Number(_) => (),
+                },
+                _ => (),
+            },
+        }
+
+        None
+    }
+
+    /// Handles text being typed while the menu is open.
+    pub fn text(&mut self, text: &str) {
+        if let Some(Editing::Text(ref mut typed)) = self.editing {
+            typed.extend(text.chars().filter(|c| !c.is_control()));
+        }
+    }
+
+    /// Notes down that the given setting was saved.
+    pub fn saved(&mut self, key: &str, settings: &Settings) {
+        let status = match *settings.origin(key) {
+            Origin::Environment(ref name) => format!(
+                "Saved `{}`, but ${} still overrides it.",
+                key,
+                name,
+            ),
+            Origin::CommandLine => format!(
+                "Saved `{}`, but the command line still overrides it.",
+                key,
+            ),
+            _ => format!("Saved `{}`.", key),
+        };
+
+        self.status = Some(status);
+    }
+
+    /// Notes down that a setting couldn't be saved.
+    pub fn failed(&mut self, message: String) {
+        self.status = Some(format!("Not saved: {}.", message));
+    }
+
+    pub fn draw<C, G>(&self,
+                      settings: &Settings,
+                      glyphs:   &mut C,
+                      ctx:      &Context,
+                      g:        &mut G)
+        where C: CharacterCache<Texture=G::Texture>,
+              G: Graphics
+    {
+        let [width, height] = ctx.get_view_size();
+        Rectangle::new(BACKDROP_COLOR).draw(
+            [0.0, 0.0, width, height],
+            &ctx.draw_state,
+            ctx.transform,
+            g,
+        );
+
+        let mut y = MARGIN + LINE_HEIGHT;
+        let mut line = |text: &str, x: f64, y: f64, color: Color| {
+            // A glyph that won't render isn't worth bringing the game down
+            // over, so errors are ignored.
+            let _ = Text::new_color(color, FONT_SIZE).draw(
+                text,
+                glyphs,
+                &ctx.draw_state,
+                ctx.transform.trans(x, y),
+                g,
+            );
+        };
+
+        line("Options", MARGIN, y, TEXT_COLOR);
+        y += LINE_HEIGHT;
+
+        for (i, entry) in ENTRIES.iter().enumerate() {
+            y += LINE_HEIGHT;
+
+            let selected = i == self.selected;
+            let color = if selected { SELECTED_COLOR } else { TEXT_COLOR };
+            let value = match self.editing {
+                Some(Editing::Text(ref text)) if selected =>
+                    format!("{}_", text),
+                Some(Editing::Binding) if selected =>
+                    "press a key, or Escape to cancel".to_owned(),
+                _ => display_value(settings, &entry.source(settings)),
+            };
+
+            line(entry.label, MARGIN, y, color);
+            line(&value, MARGIN + VALUE_OFFSET, y, color);
+        }
+
+        y += 2.0 * LINE_HEIGHT;
+        line(HINT, MARGIN, y, HINT_COLOR);
+        if let Some(ref status) = self.status {
+            y += LINE_HEIGHT;
+            line(status, MARGIN, y, TEXT_COLOR);
+        }
+    }
+}
+
+impl Entry {
+    const fn theme(label: &'static str, key: &'static str) -> Self {
+        Entry { label, key, kind: Kind::Theme }
+    }
+
+    const fn color(label: &'static str, key: &'static str) -> Self {
+        Entry { label, key, kind: Kind::Color }
+    }
+
+    const fn binding(label: &'static str, key: &'static str) -> Self {
+        Entry { label, key, kind: Kind::Binding }
+    }
+
+    const fn number(label: &'static str,
+                    key:   &'static str,
+                    step:  f64) -> Self
+    {
+        Entry { label, key, kind: Kind::Number(step) }
+    }
+
+    /// The dotted key of the setting that this changes. Colours are changed
+    /// in the active theme, even if it inherits them from another.
+    fn setting(&self, settings: &Settings) -> String {
+        match self.kind {
+            Kind::Color if settings.colors.theme != DEFAULT_THEME =>
+                format!("themes.{}.{}", settings.colors.theme, self.key),
+            Kind::Color => format!("colors.{}", self.key),
+            _ => self.key.to_owned(),
+        }
+    }
+
+    /// The dotted key of the setting that this currently gets its value
+    /// from, which for colours may be in an inherited theme.
+    fn source(&self, settings: &Settings) -> String {
+        match self.kind {
+            Kind::Color => settings.color_key(self.key),
+            _ => self.key.to_owned(),
+        }
+    }
+}
+
+
+/// The theme before or after the active one, wrapping around.
+fn cycle_theme(settings: &Settings, forwards: bool) -> &str {
+    if forwards {
+        return settings.next_theme();
+    }
+
+    let themes = settings.themes();
+    let active = themes
+        .iter()
+        .position(|&t| t == settings.colors.theme)
+        .unwrap_or(0);
+
+    themes[(active + themes.len() - 1) % themes.len()]
+}
+
+/// Reads a numeric setting, which may have been written as an integer.
+fn number(value: Option<&Value>) -> f64 {
+    match value {
+        Some(&Value::Float(f)) => f,
+        Some(&Value::Integer(i)) => i as f64,
+        _ => 0.0,
+    }
+}
+
+/// The value of a setting as it's shown in the menu, noting if it's
+/// overridden by something that saving it won't change.
+fn display_value(settings: &Settings, key: &str) -> String {
+    let value = match settings.value(key) {
+        Some(Value::String(s)) => s.clone(),
+        Some(v) => v.to_string(),
+        None => String::new(),
+    };
+
+    match *settings.origin(key) {
+        Origin::Environment(ref name) =>
+            format!("{}  (set by ${})", value, name),
+        Origin::CommandLine => format!("{}  (set on the command line)", value),
+        _ => value,
+    }
+}
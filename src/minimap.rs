@@ -0,0 +1,210 @@
+//! A small overview of the whole map, drawn in a corner of the window, that
+//! can be clicked on to pan the camera.
+
+use color::shade;
+use geometry::{cube_round, cube_to_real, real_to_cube, CubePoint, SQRT_3};
+use graphics::{
+    math::{add, mul_scalar, sub, Vec2d},
+    types::Color,
+    Context,
+    Graphics,
+    Line,
+    Polygon,
+    Rectangle,
+};
+use positioned::Positioned;
+use scene::Scene;
+use settings::Settings;
+
+
+/// Smallest size, in pixels, that tiles are drawn at. Tiles any smaller than
+/// this are skipped over, so that only one in every so many is drawn.
+const MIN_TILE_SIZE: f64 = 1.5;
+
+/// Length, in pixels, of the arrow marking the player.
+const PLAYER_MARKER_SIZE: f64 = 8.0;
+
+const VIEW_OUTLINE_COLOR: Color = [1.0, 1.0, 1.0, 0.9];
+const VIEW_OUTLINE_WIDTH: f64 = 0.5;
+
+
+/// Where the minimap is on the screen, and how the map is scaled to fit.
+struct Layout {
+    /// Top-left corner of the minimap, in pixels.
+    corner: Vec2d,
+    size:   Vec2d,
+    /// Pixels per unit of the hex grid.
+    scale:  f64,
+    /// Top-left corner of the map, in hex grid units.
+    min:    Vec2d,
+}
+
+
+/// Draws the minimap, if it's shown.
+pub fn draw<G: Graphics>(scene:    &Scene,
+                         settings: &Settings,
+                         ctx:      &Context,
+                         g:        &mut G)
+{
+    let minimap = &settings.minimap;
+    if !minimap.shown {
+        return;
+    }
+
+    let layout = Layout::new(scene, settings);
+    let opacity = minimap.opacity as f32;
+    let faded = |c: Color| [c[0], c[1], c[2], c[3] * opacity];
+
+    let [left, top] = layout.corner;
+    let rect = [left, top, layout.size[0], layout.size[1]];
+    let background = shade(settings.colors.background_color, 0.5);
+    Rectangle::new(faded(background)).draw(
+        rect,
+        &ctx.draw_state,
+        ctx.transform,
+        g,
+    );
+
+    // Nothing drawn from here on should spill out of the minimap.
+    let draw_state = ctx.draw_state.scissor(scissor_rect(ctx, rect));
+
+    let map = &scene.map;
+    let stride =
+        (MIN_TILE_SIZE / (1.5 * layout.scale)).ceil().max(1.0) as usize;
+    let tile_size = mul_scalar([1.5, SQRT_3], layout.scale * stride as f64);
+    for y in (0..map.rows()).step_by(stride) {
+        for x in (0..map.cols()).step_by(stride) {
+            let q = x as i32;
+            let r = y as i32 - q / 2;
+            let pos = CubePoint::from_q_r(q, r);
+            let color = match map.tile_color(pos) {
+                Some(c) => c,
+                None => continue,
+            };
+
+            let center = layout.to_screen(cube_to_real(pos, 1.0));
+            let top_left = sub(center, mul_scalar(tile_size, 0.5));
+            Rectangle::new(faded(color)).draw(
+                [top_left[0], top_left[1], tile_size[0], tile_size[1]],
+                &draw_state,
+                ctx.transform,
+                g,
+            );
+        }
+    }
+
+    // Outline what the camera can see of the ground.
+    let camera = &scene.camera;
+    let [width, height] = camera.viewport();
+    let screen_corners =
+        [[0.0, 0.0], [width, 0.0], [width, height], [0.0, height]];
+    let view: Vec<_> = screen_corners
+        .iter()
+        .map(|&p| layout.to_screen(cube_to_real(camera.ground_pos(p), 1.0)))
+        .collect();
+    let line = Line::new(faded(VIEW_OUTLINE_COLOR), VIEW_OUTLINE_WIDTH);
+    for (i, &from) in view.iter().enumerate() {
+        let to = view[(i + 1) % view.len()];
+        line.draw(
+            [from[0], from[1], to[0], to[1]],
+            &draw_state,
+            ctx.transform,
+            g,
+        );
+    }
+
+    // Mark the player with an arrow pointing the way that they face. At an
+    // angle of `0`, that's straight up.
+    let player = &scene.player;
+    let angle = player.angle().radians();
+    let forwards = [-angle.sin(), -angle.cos()];
+    let sideways = [-forwards[1], forwards[0]];
+    let center = layout.to_screen(cube_to_real(*player.pos(), 1.0));
+    let tip = add(center, mul_scalar(forwards, PLAYER_MARKER_SIZE / 2.0));
+    let back = sub(tip, mul_scalar(forwards, PLAYER_MARKER_SIZE));
+    let arrow = [
+        tip,
+        add(back, mul_scalar(sideways, PLAYER_MARKER_SIZE / 2.0)),
+        add(back, mul_scalar(sideways, -PLAYER_MARKER_SIZE / 2.0)),
+    ];
+    Polygon::new(faded(settings.colors.player_color)).draw(
+        &arrow,
+        &draw_state,
+        ctx.transform,
+        g,
+    );
+}
+
+/// Whether `cursor` is over the minimap, so that clicks there are the
+/// minimap's rather than the world's.
+pub fn contains(scene: &Scene, settings: &Settings, cursor: Vec2d) -> bool {
+    settings.minimap.shown
+        && Layout::new(scene, settings).contains(cursor)
+}
+
+/// Pans the camera to wherever on the map `cursor` is over, if it's over the
+/// minimap at all.
+pub fn click(scene: &mut Scene, settings: &Settings, cursor: Vec2d) {
+    if !contains(scene, settings, cursor) {
+        return;
+    }
+
+    let grid_pos = Layout::new(scene, settings).to_grid(cursor);
+    let target = real_to_cube(grid_pos, 1.0);
+    scene.camera.grid_pos_mut().set_target_pos(cube_round(target));
+}
+
+
+impl Layout {
+    fn new(scene: &Scene, settings: &Settings) -> Self {
+        let minimap = &settings.minimap;
+        let map = &scene.map;
+
+        // Hexes are a unit from their centers to their corners, and every
+        // other column is shifted half a hex down.
+        let min = [-1.0, -SQRT_3 / 2.0];
+        let map_size = [
+            1.5 * map.cols() as f64 + 0.5,
+            SQRT_3 * (map.rows() as f64 + 0.5),
+        ];
+        let scale = minimap.size / map_size[0].max(map_size[1]);
+        let size = mul_scalar(map_size, scale);
+        let viewport = scene.camera.viewport();
+        let corner = minimap.corner.place(size, minimap.margin, viewport);
+
+        Layout { corner, size, scale, min }
+    }
+
+    /// Where on the screen the given position in hex grid units is.
+    fn to_screen(&self, grid_pos: Vec2d) -> Vec2d {
+        add(self.corner, mul_scalar(sub(grid_pos, self.min), self.scale))
+    }
+
+    /// The inverse of `to_screen`.
+    fn to_grid(&self, screen_pos: Vec2d) -> Vec2d {
+        let from_corner = sub(screen_pos, self.corner);
+
+        add(self.min, mul_scalar(from_corner, 1.0 / self.scale))
+    }
+
+    fn contains(&self, screen_pos: Vec2d) -> bool {
+        let inside = sub(screen_pos, self.corner);
+
+        inside[0] >= 0.0
+            && inside[1] >= 0.0
+            && inside[0] <= self.size[0]
+            && inside[1] <= self.size[1]
+    }
+}
+
+
+/// The given rectangle of the window, in the pixels that scissoring works
+/// in, which may be finer than the window's own on high-DPI displays.
+fn scissor_rect(ctx: &Context, rect: [f64; 4]) -> [u32; 4] {
+    let ratio = ctx.viewport.map_or(1.0, |v| {
+        f64::from(v.draw_size[0]) / v.window_size[0]
+    });
+    let scaled = |x: f64| (x * ratio).max(0.0).round() as u32;
+
+    [scaled(rect[0]), scaled(rect[1]), scaled(rect[2]), scaled(rect[3])]
+}
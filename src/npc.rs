@@ -0,0 +1,416 @@
+use camera::Camera;
+use drawable::Drawable;
+use geometry::{cube_distance, cube_neighbors, cube_round, CubePoint, Dir};
+use graphics::{
+    Context,
+    Graphics,
+    rectangle::{Border, Rectangle, Shape},
+};
+use map_data::MapData;
+use rand::prelude::*;
+use settings::Settings;
+use sprite::{AnimationState, SpriteSheet, SPRITE_WIDTH};
+use temporal::Temporal;
+use transitioned_grid_pos::{
+    MovementEvent,
+    TransitionedGridPos,
+    Waypoint,
+};
+use tween::{step_effects, Animator, Appearance, Timeline};
+
+
+/// Seconds that a patrolling NPC lingers at each waypoint.
+const PATROL_PAUSE: f64 = 1.5;
+
+/// How many random destinations a wandering NPC tries before giving up and
+/// resting for a while instead.
+const WANDER_ATTEMPTS: usize = 8;
+
+
+/// What an NPC does when the player isn't around.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Routine {
+    /// Mill about, walking to random tiles within `radius` of home and
+    /// resting in between.
+    Wander {
+        radius: i32,
+    },
+    /// Walk between the given waypoints in order, looping back around to the
+    /// first after the last.
+    Patrol {
+        waypoints: Vec<CubePoint<i32>>,
+    },
+}
+
+/// Everything that a map needs to say about an NPC in order to spawn it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NpcSpawn {
+    pub pos:            CubePoint<i32>,
+    pub routine:        Routine,
+    pub health:         f64,
+    /// Furthest distance, in hexes, that this NPC can see the player from.
+    pub sight_radius:   i32,
+    /// Fraction of maximum health at or below which this NPC runs away from
+    /// the player instead of chasing them.
+    pub flee_threshold: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AiState {
+    /// Following a `Routine::Wander`.
+    Wander,
+    /// Following a `Routine::Patrol`, currently headed for the waypoint with
+    /// index `next`.
+    Patrol {
+        next: usize,
+    },
+    /// Closing in on the player.
+    Chase,
+    /// Getting away from the player.
+    Flee,
+}
+
+#[derive(Clone)]
+pub struct Npc {
+    /// Index of the map's spawn that this NPC came from.
+    spawn:          usize,
+    pos:            TransitionedGridPos,
+    rect:           Rectangle,
+    /// Current appearance, with all effects applied.
+    appearance:     Appearance,
+    effects:        Vec<Animator<Appearance>>,
+    animation:      AnimationState,
+    home:           CubePoint<i32>,
+    routine:        Routine,
+    state:          AiState,
+    /// Seconds of standing still left before the routine will carry on.
+    rest:           f64,
+    health:         f64,
+    max_health:     f64,
+    sight_radius:   i32,
+    flee_threshold: f64,
+}
+
+
+impl Npc {
+    /// An NPC fresh from the map's spawn with the given index.
+    pub fn new(anim_time: f64,
+               index:     usize,
+               spawn:     &NpcSpawn,
+               settings:  &Settings) -> Self
+    {
+        let state = match spawn.routine {
+            Routine::Wander { .. } => AiState::Wander,
+            Routine::Patrol { .. } => AiState::Patrol { next: 0 },
+        };
+
+        let easing = settings.animation.npc;
+        let mut pos = TransitionedGridPos::new(anim_time, spawn.pos.cast());
+        pos.set_easing(easing.translation, easing.rotation);
+
+        Self {
+            spawn:          index,
+            pos,
+            rect:           Self::rect(settings),
+            appearance:     Appearance::default(),
+            effects:        Vec::new(),
+            animation:      AnimationState::default(),
+            home:           spawn.pos,
+            routine:        spawn.routine.clone(),
+            state,
+            rest:           0.0,
+            health:         spawn.health,
+            max_health:     spawn.health,
+            sight_radius:   spawn.sight_radius,
+            flee_threshold: spawn.flee_threshold,
+        }
+    }
+
+    fn rect(settings: &Settings) -> Rectangle {
+        Rectangle::new(settings.colors.npc_color)
+            .shape(Shape::Bevel(1.0))
+            .border(Border {
+                color:  settings.colors.npc_outline_color,
+                radius: 1.0,
+            })
+    }
+
+    /// Picks up any changes to the settings that affect NPCs.
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        let easing = settings.animation.npc;
+        self.pos.set_easing(easing.translation, easing.rotation);
+        self.pos.set_anim_time(settings.gameplay.npc_anim_time);
+        self.rect = Self::rect(settings);
+    }
+
+    pub fn grid_pos(&self) -> &TransitionedGridPos {
+        &self.pos
+    }
+
+    /// Draws the NPC with the given sprite sheet, or as a plain rectangle
+    /// without one.
+    pub fn draw_with<G: Graphics>(&self,
+                                  camera: &Camera,
+                                  ctx:    &Context,
+                                  g:      &mut G,
+                                  sheet:  Option<&SpriteSheet<G::Texture>>)
+    {
+        let size = camera.scale_factor() * self.appearance.scale;
+        let npc_trans =
+            camera.entity_transform(*self.pos.pos(), self.pos.angle(), ctx);
+
+        if let Some(sheet) = sheet {
+            sheet.draw(
+                &self.animation,
+                size * SPRITE_WIDTH,
+                self.appearance.color(self.rect.color),
+                &ctx.draw_state,
+                npc_trans.repr,
+                g,
+            );
+            return;
+        }
+
+        let rect = self.appearance.rect(&self.rect);
+        rect.draw(
+            [-size / 4.0, -size / 4.0,
+              size / 2.0,  size / 2.0],
+            &ctx.draw_state,
+            npc_trans.repr,
+            g,
+        );
+    }
+
+    pub fn grid_pos_mut(&mut self) -> &mut TransitionedGridPos {
+        &mut self.pos
+    }
+
+    /// Plays an effect on top of this NPC's appearance.
+    pub fn add_effect(&mut self, effect: Timeline<Appearance>) {
+        self.effects.push(Animator::new(effect));
+    }
+
+    pub fn spawn(&self) -> usize {
+        self.spawn
+    }
+
+    pub fn health(&self) -> f64 {
+        self.health
+    }
+
+    pub fn set_health(&mut self, health: f64) {
+        self.health = health.max(0.0).min(self.max_health);
+    }
+
+    /// Decides what to do next, given the current surroundings. New routes
+    /// are only planned once the previous one has been walked, but noticing
+    /// (or losing sight of) the player cuts the current route short. Any
+    /// randomness, e.g. in where to wander to, comes from `rng`.
+    pub fn think<R: Rng>(&mut self,
+                         map:        &MapData,
+                         player_pos: CubePoint<f64>,
+                         rng:        &mut R)
+    {
+        let routine_finished = self
+            .pos
+            .drain_events()
+            .any(|e| e == MovementEvent::QueueFinished);
+
+        let here = *self.pos.target_pos();
+        let player = cube_round(player_pos);
+        let sees_player = cube_distance(here, player) <= self.sight_radius
+            && map.has_line_of_sight(here, player);
+
+        let next_state = self.next_state(sees_player);
+        if next_state != self.state {
+            self.pos.cancel_waypoints();
+            self.state = next_state;
+        } else if routine_finished {
+            self.finish_route(rng);
+        }
+
+        if !self.pos.is_idle() {
+            return;
+        }
+
+        match self.state {
+            AiState::Wander => self.wander(map, here, rng),
+            AiState::Patrol { next } => self.patrol(map, here, next, rng),
+            AiState::Chase => self.chase(map, here, player),
+            AiState::Flee => self.flee(map, here, player),
+        }
+    }
+
+    fn next_state(&self, sees_player: bool) -> AiState {
+        let fleeing = self.health <= self.flee_threshold * self.max_health;
+
+        match self.state {
+            _ if sees_player && fleeing => AiState::Flee,
+            _ if sees_player => AiState::Chase,
+            // Lost sight of the player, so go back to the usual routine.
+            AiState::Chase | AiState::Flee => match self.routine {
+                Routine::Wander { .. } => AiState::Wander,
+                Routine::Patrol { ref waypoints } => {
+                    let here = *self.pos.target_pos();
+                    let next = (0..waypoints.len())
+                        .min_by_key(|&i| cube_distance(here, waypoints[i]))
+                        .unwrap_or(0);
+
+                    AiState::Patrol { next }
+                },
+            },
+            s => s,
+        }
+    }
+
+    /// Called when the route planned by the routine has been walked to its
+    /// end.
+    fn finish_route<R: Rng>(&mut self, rng: &mut R) {
+        match (self.state, &self.routine) {
+            (AiState::Wander, _) => self.rest = rng.gen_range(1.0, 3.0),
+            (AiState::Patrol { next }, Routine::Patrol { waypoints }) => {
+                self.state = AiState::Patrol {
+                    next: (next + 1) % waypoints.len().max(1),
+                };
+                self.rest = PATROL_PAUSE;
+            },
+            _ => (),
+        }
+    }
+
+    fn wander<R: Rng>(&mut self,
+                      map:  &MapData,
+                      here: CubePoint<i32>,
+                      rng:  &mut R)
+    {
+        let radius = match self.routine {
+            Routine::Wander { radius } => radius,
+            _ => return,
+        };
+
+        if self.rest > 0.0 {
+            return;
+        }
+
+        for _ in 0..WANDER_ATTEMPTS {
+            let q = rng.gen_range(-radius, radius + 1);
+            let r = rng.gen_range((-radius).max(-q - radius),
+                                  radius.min(-q + radius) + 1);
+            let dest = self.home + CubePoint::from_q_r(q, r);
+
+            match map.find_path(here, dest, 2 * radius + 1) {
+                Some(ref path) if !path.is_empty() => {
+                    self.walk(here, path);
+                    return;
+                },
+                _ => (),
+            }
+        }
+
+        // Nowhere to go, so have a rest before trying again.
+        self.rest = rng.gen_range(1.0, 3.0);
+    }
+
+    fn patrol<R: Rng>(&mut self,
+                      map:  &MapData,
+                      here: CubePoint<i32>,
+                      next: usize,
+                      rng:  &mut R)
+    {
+        let waypoints = match self.routine {
+            Routine::Patrol { ref waypoints } if !waypoints.is_empty() =>
+                waypoints.clone(),
+            _ => return,
+        };
+
+        if self.rest > 0.0 {
+            return;
+        }
+
+        let target = waypoints[next % waypoints.len()];
+        let max_len = 4 * cube_distance(here, target) + 1;
+        match map.find_path(here, target, max_len) {
+            Some(ref path) if !path.is_empty() => self.walk(here, path),
+            // Either already there, or the waypoint can't be reached from
+            // here; either way, move on to the next one.
+            _ => self.finish_route(rng),
+        }
+    }
+
+    fn chase(&mut self,
+             map:    &MapData,
+             here:   CubePoint<i32>,
+             player: CubePoint<i32>)
+    {
+        if cube_distance(here, player) <= 1 {
+            if let Some(dir) = Dir::from_delta(player - here) {
+                self.pos.push_waypoint(Waypoint::face(dir.angle()));
+            }
+
+            return;
+        }
+
+        // Only ever take the first step of the path, since the player is
+        // likely to have moved by the time we've taken it.
+        if let Some(path) = map.find_path(here, player, 2 * self.sight_radius)
+        {
+            self.walk(here, &path[..path.len().min(1)]);
+        }
+    }
+
+    fn flee(&mut self,
+            map:    &MapData,
+            here:   CubePoint<i32>,
+            player: CubePoint<i32>)
+    {
+        let current_dist = cube_distance(here, player);
+        let escape = cube_neighbors(here)
+            .iter()
+            .filter(|&&n| map.can_step(here, n))
+            .max_by_key(|&&n| cube_distance(n, player))
+            .cloned();
+
+        if let Some(n) = escape {
+            if cube_distance(n, player) > current_dist {
+                self.walk(here, &[n]);
+            }
+        }
+    }
+
+    /// Queues up a walk along the given path, facing the direction of travel
+    /// for each hop.
+    fn walk(&mut self, from: CubePoint<i32>, path: &[CubePoint<i32>]) {
+        let hops = path.iter().scan(from, |prev, &hop| {
+            let waypoint = match Dir::from_delta(hop - *prev) {
+                Some(dir) => Waypoint::to(hop).facing(dir.angle()),
+                None => Waypoint::to(hop),
+            };
+            *prev = hop;
+
+            Some(waypoint)
+        });
+
+        self.pos.extend_waypoints(hops);
+    }
+}
+
+impl Drawable for Npc {
+    fn draw<G: Graphics>(&self, camera: &Camera, ctx: &Context, g: &mut G) {
+        self.draw_with(camera, ctx, g, None);
+    }
+}
+
+impl Temporal for Npc {
+    fn step(&mut self, dt: f64) {
+        self.pos.step(dt);
+        self.animation.step(self.pos.motion(), dt);
+        self.appearance =
+            step_effects(&mut self.effects, &Appearance::default(), dt);
+
+        // Only rest once there's nowhere left to go.
+        if self.pos.is_idle() {
+            self.rest = (self.rest - dt).max(0.0);
+        }
+    }
+}
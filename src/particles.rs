@@ -0,0 +1,247 @@
+//! Short-lived specks drawn on top of the scene, such as dust kicked up by
+//! footsteps and sparks from hits. Emitters give off particles for a while,
+//! and are forgotten once they've stopped and their particles have died.
+
+use camera::Camera;
+use color::shade;
+use drawable::Drawable;
+use easing::Easing;
+use geometry::{cube_to_real, CubePoint};
+use graphics::{
+    math::Vec2d,
+    types::Color,
+    Context,
+    Graphics,
+    Rectangle,
+};
+use rand::prelude::*;
+use rand_pcg::Pcg64;
+use std::f64::consts::PI;
+use temporal::Temporal;
+use tween::Tweenable;
+
+
+/// Colour of the sparks given off by hits.
+const HIT_COLOR: Color = [1.0, 0.85, 0.4, 1.0];
+
+/// How a kind of particle is given off, moves, and changes over its life.
+#[derive(Clone, Copy, Debug)]
+pub struct ParticleStyle {
+    /// Particles given off per second.
+    pub rate:      f64,
+    /// Seconds that each particle lives for.
+    pub lifetime:  f64,
+    /// Slowest and fastest that particles start off moving, in hexes per
+    /// second.
+    pub speed:     [f64; 2],
+    /// Direction that particles head off in, in radians from the x axis of
+    /// the ground towards its y axis, give or take up to `spread`.
+    pub direction: f64,
+    pub spread:    f64,
+    /// Rate at which particles slow down: their speed falls by a factor of
+    /// `e` every `1 / drag` seconds, however long the frames are.
+    pub drag:      f64,
+    /// Colours at birth and at death, which are tweened between.
+    pub colors:    [Color; 2],
+    /// Sizes at birth and at death, in hexes, which are tweened between.
+    pub sizes:     [f64; 2],
+    pub easing:    Easing,
+}
+
+/// Gives off particles of one style from one place.
+#[derive(Clone, Debug)]
+pub struct Emitter {
+    style:     ParticleStyle,
+    /// In hex grid units, as given by `cube_to_real` with a size of `1`.
+    pos:       Vec2d,
+    /// Tile height that the particles are lifted to.
+    height:    i32,
+    /// Seconds left to keep giving off particles for.
+    remaining: f64,
+    /// Particles due to be given off, including the fraction of one that's
+    /// carried over from the last step.
+    due:       f64,
+    particles: Vec<Particle>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Particle {
+    /// In hex grid units, as for `Emitter::pos`.
+    pos: Vec2d,
+    /// In hexes per second.
+    vel: Vec2d,
+    /// Seconds since this was given off.
+    age: f64,
+}
+
+/// Every emitter in the scene.
+#[derive(Clone, Debug)]
+pub struct Particles {
+    emitters: Vec<Emitter>,
+    /// Where particles are given off, and which way they head.
+    rng:      Pcg64,
+}
+
+
+impl ParticleStyle {
+    /// A brief puff of dust the colour of the ground that it comes off.
+    pub fn dust(ground: Color) -> Self {
+        let color = shade(ground, 1.3);
+
+        ParticleStyle {
+            rate:      80.0,
+            lifetime:  0.5,
+            speed:     [0.3, 0.8],
+            direction: 0.0,
+            spread:    PI,
+            drag:      2.5,
+            colors:    [[color[0], color[1], color[2], 0.6],
+                        [color[0], color[1], color[2], 0.0]],
+            sizes:     [0.06, 0.16],
+            easing:    Easing::CubicOut,
+        }
+    }
+
+    /// Sparks flying every which way from a hit.
+    pub fn hit() -> Self {
+        ParticleStyle {
+            rate:      300.0,
+            lifetime:  0.35,
+            speed:     [1.5, 3.0],
+            direction: 0.0,
+            spread:    PI,
+            drag:      3.0,
+            colors:    [HIT_COLOR, [HIT_COLOR[0], 0.2, 0.1, 0.0]],
+            sizes:     [0.1, 0.03],
+            easing:    Easing::Linear,
+        }
+    }
+
+    fn spawn<R: Rng>(&self, pos: Vec2d, rng: &mut R) -> Particle {
+        let spread = self.spread * rng.gen_range(-1.0, 1.0);
+        let direction = self.direction + spread;
+        let speed = rng.gen_range(self.speed[0], self.speed[1]);
+
+        Particle {
+            pos,
+            vel: [speed * direction.cos(), speed * direction.sin()],
+            age: 0.0,
+        }
+    }
+
+    /// Colour and size of a particle of the given age.
+    fn at(&self, age: f64) -> (Color, f64) {
+        let t = self.easing.apply(age / self.lifetime);
+
+        (
+            Color::tween(self.colors[0], self.colors[1], t),
+            f64::tween(self.sizes[0], self.sizes[1], t),
+        )
+    }
+}
+
+impl Emitter {
+    /// Gives off particles from `pos`, on a tile of the given height, for
+    /// `duration` seconds.
+    pub fn new(style:    ParticleStyle,
+               pos:      CubePoint<f64>,
+               height:   i32,
+               duration: f64) -> Self
+    {
+        Emitter {
+            style,
+            pos:       cube_to_real(pos, 1.0),
+            height,
+            remaining: duration,
+            due:       0.0,
+            particles: Vec::new(),
+        }
+    }
+
+    /// Whether this has stopped giving off particles, and they've all died.
+    pub fn is_finished(&self) -> bool {
+        self.remaining <= 0.0 && self.particles.is_empty()
+    }
+
+    /// Moves the particles along by `dt` seconds, giving off any new ones
+    /// with `rng`.
+    fn step<R: Rng>(&mut self, dt: f64, rng: &mut R) {
+        let (lifetime, drag) = (self.style.lifetime, self.style.drag);
+        let slowing = (-drag * dt).exp();
+        for particle in self.particles.iter_mut() {
+            let vel = [particle.vel[0] * slowing, particle.vel[1] * slowing];
+            particle.pos[0] += vel[0] * dt;
+            particle.pos[1] += vel[1] * dt;
+            particle.vel = vel;
+            particle.age += dt;
+        }
+        self.particles.retain(|p| p.age < lifetime);
+
+        if self.remaining > 0.0 {
+            self.due += self.style.rate * dt.min(self.remaining);
+            self.remaining -= dt;
+
+            while self.due >= 1.0 {
+                self.due -= 1.0;
+                self.particles.push(self.style.spawn(self.pos, rng));
+            }
+        }
+    }
+}
+
+impl Particles {
+    /// No emitters, with particles scattered by an RNG seeded with `seed`.
+    pub fn new(seed: u64) -> Self {
+        Particles {
+            emitters: Vec::new(),
+            rng:      Pcg64::seed_from_u64(seed),
+        }
+    }
+
+    /// Scatters particles from here on with an RNG seeded with `seed`.
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = Pcg64::seed_from_u64(seed);
+    }
+
+    pub fn add(&mut self, emitter: Emitter) {
+        self.emitters.push(emitter);
+    }
+}
+
+impl Temporal for Particles {
+    fn step(&mut self, dt: f64) {
+        for emitter in self.emitters.iter_mut() {
+            emitter.step(dt, &mut self.rng);
+        }
+        self.emitters.retain(|e| !e.is_finished());
+    }
+}
+
+impl Drawable for Emitter {
+    fn draw<G: Graphics>(&self, camera: &Camera, ctx: &Context, g: &mut G) {
+        let ground = camera.ground_transform();
+        let lift = camera.lift(self.height);
+        let scale = camera.scale_factor();
+
+        for particle in self.particles.iter() {
+            let (color, size) = self.style.at(particle.age);
+            let [x, y] = ground.pos_mul(particle.pos);
+            let (x, y, size) = (x + lift[0], y + lift[1], size * scale);
+
+            Rectangle::new(color).draw(
+                [x - size / 2.0, y - size / 2.0, size, size],
+                &ctx.draw_state,
+                ctx.transform,
+                g,
+            );
+        }
+    }
+}
+
+impl Drawable for Particles {
+    fn draw<G: Graphics>(&self, camera: &Camera, ctx: &Context, g: &mut G) {
+        for emitter in self.emitters.iter() {
+            emitter.draw(camera, ctx, g);
+        }
+    }
+}
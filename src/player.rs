@@ -1,26 +1,29 @@
 use camera::Camera;
 use drawable::Drawable;
-use geometry::{Angle, cube_dir, cube_to_real, CubePoint};
+use geometry::{Angle, cube_dir, CubePoint, Dir};
 use graphics::{
     Context,
     Graphics,
-    math::add,
     rectangle::{Border, Rectangle, Shape},
 };
-use matrix::{m, rot, trans};
 use positioned::Positioned;
 use settings::Settings;
+use sprite::{AnimationState, SpriteSheet, SPRITE_WIDTH};
 use std::f64::consts::FRAC_PI_3;
 use temporal::Temporal;
 use transitioned_grid_pos::TransitionedGridPos;
-use window::{HALF_WINDOW_HEIGHT, HALF_WINDOW_WIDTH, WINDOW_HEIGHT};
+use tween::{step_effects, Animator, Appearance, Timeline};
 
 
 #[derive(Clone)]
 pub struct Player {
     /// Position of player in terms of the underlying cubic coordinate system.
-    pos:  TransitionedGridPos,
-    rect: Rectangle,
+    pos:        TransitionedGridPos,
+    rect:       Rectangle,
+    /// Current appearance, with all effects applied.
+    appearance: Appearance,
+    effects:    Vec<Animator<Appearance>>,
+    animation:  AnimationState,
 }
 
 
@@ -29,15 +32,95 @@ impl Player {
                start_pos: CubePoint<f64>,
                settings:  &Settings) -> Self
     {
+        let easing = settings.animation.player;
+        let mut pos = TransitionedGridPos::new(anim_time, start_pos);
+        pos.set_easing(easing.translation, easing.rotation);
+
         Self {
-            pos:  TransitionedGridPos::new(anim_time, start_pos),
-            rect: Rectangle::new(settings.colors.player_color)
-                      .shape(Shape::Bevel(1.0))
-                      .border(Border {
-                          color:  settings.colors.player_outline_color,
-                          radius: 1.0,
-                      }),
+            pos,
+            rect:       Self::rect(settings),
+            appearance: Appearance::default(),
+            effects:    Vec::new(),
+            animation:  AnimationState::default(),
+        }
+    }
+
+    fn rect(settings: &Settings) -> Rectangle {
+        Rectangle::new(settings.colors.player_color)
+            .shape(Shape::Bevel(1.0))
+            .border(Border {
+                color:  settings.colors.player_outline_color,
+                radius: 1.0,
+            })
+    }
+
+    /// Picks up any changes to the settings that affect the player.
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        let easing = settings.animation.player;
+        self.pos.set_easing(easing.translation, easing.rotation);
+        self.pos.set_anim_time(settings.gameplay.player_anim_time);
+        self.rect = Self::rect(settings);
+    }
+
+    /// Plays an effect on top of this player's appearance.
+    pub fn add_effect(&mut self, effect: Timeline<Appearance>) {
+        self.effects.push(Animator::new(effect));
+    }
+
+    pub fn grid_pos(&self) -> &TransitionedGridPos {
+        &self.pos
+    }
+
+    /// Turns to face `dir`, and steps one hex that way, or backs away one
+    /// hex from it.
+    pub fn walk(&mut self, dir: Dir, forwards: bool) {
+        let target_pos = *self.pos.target_pos();
+        let new_target_pos = if forwards {
+            target_pos + cube_dir(dir)
+        } else {
+            target_pos - cube_dir(dir)
+        };
+
+        self.pos.set_target_angle(dir.angle());
+        self.pos.set_target_pos(new_target_pos);
+    }
+
+    pub fn grid_pos_mut(&mut self) -> &mut TransitionedGridPos {
+        &mut self.pos
+    }
+
+    /// Draws the player with the given sprite sheet, or as a plain rectangle
+    /// without one.
+    pub fn draw_with<G: Graphics>(&self,
+                                  camera: &Camera,
+                                  ctx:    &Context,
+                                  g:      &mut G,
+                                  sheet:  Option<&SpriteSheet<G::Texture>>)
+    {
+        let size = camera.scale_factor() * self.appearance.scale;
+        let player_trans =
+            camera.entity_transform(*self.pos(), self.angle(), ctx);
+
+        if let Some(sheet) = sheet {
+            sheet.draw(
+                &self.animation,
+                size * SPRITE_WIDTH,
+                self.appearance.color(self.rect.color),
+                &ctx.draw_state,
+                player_trans.repr,
+                g,
+            );
+            return;
         }
+
+        let rect = self.appearance.rect(&self.rect);
+        rect.draw(
+            [-size / 4.0, -size / 4.0,
+              size / 2.0,  size / 2.0],
+            &ctx.draw_state,
+            player_trans.repr,
+            g,
+        );
     }
 }
 
@@ -77,42 +160,15 @@ impl Positioned for Player {
 
 impl Drawable for Player {
     fn draw<G: Graphics>(&self, camera: &Camera, ctx: &Context, g: &mut G) {
-        // TODO: `hex_scaled_height` should be dynamic state.
-        let hex_scaled_height = 12.0;
-        let scale_factor = f64::from(WINDOW_HEIGHT) / hex_scaled_height;
-
-        let cam_rotation = rot(camera.angle().radians());
-
-        let player_abs_pos = *self.pos();
-        let player_minus_cam = player_abs_pos - *camera.pos();
-        let player_disp = add(
-            cam_rotation.vec_mul(cube_to_real(
-                player_minus_cam,
-                scale_factor,
-            )),
-            [HALF_WINDOW_WIDTH, HALF_WINDOW_HEIGHT],
-        );
-
-        let player_abs_angle = self.angle();
-        let player_ang_minus_cam = player_abs_angle - camera.angle();
-
-        let player_trans =
-            rot(-player_ang_minus_cam.radians()) *
-            trans(player_disp) *
-            m(ctx.transform);
-
-        self.rect.draw(
-            [-scale_factor / 4.0, -scale_factor / 4.0,
-              scale_factor / 2.0,  scale_factor / 2.0],
-            &ctx.draw_state,
-            player_trans.repr,
-            g,
-        );
+        self.draw_with(camera, ctx, g, None);
     }
 }
 
 impl Temporal for Player {
     fn step(&mut self, dt: f64) {
         self.pos.step(dt);
+        self.animation.step(self.pos.motion(), dt);
+        self.appearance =
+            step_effects(&mut self.effects, &Appearance::default(), dt);
     }
 }
@@ -0,0 +1,119 @@
+//! Replays of key presses, read from plain text event logs.
+//!
+//! Each line of a replay is `<seconds> press <key>` or `<seconds> release
+//! <key>`, where `<seconds>` is the game-time at which the event happens and
+//! `<key>` is named as in the `[input]` settings. Blank lines and lines
+//! starting with `#` are ignored.
+
+use failure::Error;
+use piston::input::keyboard::Key;
+use settings::key_from_name;
+use std::{
+    cmp::Ordering,
+    collections::VecDeque,
+    fs::File,
+    io::Read,
+    path::Path,
+    str::FromStr,
+};
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplayEvent {
+    Press(Key),
+    Release(Key),
+}
+
+#[derive(Clone, Debug)]
+pub struct Replay {
+    /// Events that have yet to happen, along with when they happen, in
+    /// order.
+    events: VecDeque<(f64, ReplayEvent)>,
+}
+
+#[derive(Debug, Fail)]
+pub enum ReplayError {
+    #[fail(display = "Line {} of the replay is malformed: {}", line, reason)]
+    BadLine {
+        line:   usize,
+        reason: String,
+    },
+}
+
+
+impl Replay {
+    pub fn read_from<P: AsRef<Path>>(replay_path: P) -> Result<Self, Error> {
+        let mut replay_file = File::open(replay_path)?;
+        let mut contents = String::new();
+        replay_file.read_to_string(&mut contents)?;
+
+        Ok(contents.parse()?)
+    }
+
+    /// Takes all of the events that happen at or before the given time.
+    pub fn due(&mut self, time: f64) -> Vec<ReplayEvent> {
+        let mut due = Vec::new();
+        while let Some(&(t, event)) = self.events.front() {
+            if t > time {
+                break;
+            }
+
+            due.push(event);
+            self.events.pop_front();
+        }
+
+        due
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+impl FromStr for Replay {
+    type Err = ReplayError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut events = Vec::new();
+
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let bad_line = |reason: &str| ReplayError::BadLine {
+                line:   i + 1,
+                reason: reason.to_owned(),
+            };
+
+            let words: Vec<_> = line.split_whitespace().collect();
+            if words.len() != 3 {
+                return Err(bad_line("expected `<seconds> press <key>` or \
+                                     `<seconds> release <key>`"));
+            }
+
+            let time = words[0]
+                .parse::<f64>()
+                .ok()
+                .filter(|t| t.is_finite() && *t >= 0.0)
+                .ok_or_else(|| bad_line("time isn't a non-negative number"))?;
+            let key = key_from_name(words[2])
+                .ok_or_else(|| bad_line("unknown key name"))?;
+            let event = match words[1] {
+                "press" => ReplayEvent::Press(key),
+                "release" => ReplayEvent::Release(key),
+                _ => return Err(bad_line("expected `press` or `release`")),
+            };
+
+            events.push((time, event));
+        }
+
+        // Sorting is stable, so events at the same time keep their order.
+        events.sort_by(|a, b| {
+            a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal)
+        });
+
+        Ok(Replay { events: events.into() })
+    }
+}
@@ -0,0 +1,599 @@
+//! Saving and loading of the game state to and from TOML files.
+//!
+//! Saves are versioned: every save records the `SAVE_VERSION` that it was
+//! written with, and older saves are brought up to date by running them
+//! through `MIGRATIONS` before being deserialized. Whenever the format
+//! changes, bump `SAVE_VERSION` and append a migration that turns the
+//! previous version's `toml::Value` into the new one.
+
+use camera_effects::{Keyframe, View};
+use easing::Easing;
+use failure::Error;
+use geometry::{Angle, CubePoint};
+use map_data::{
+    simulated_map_data,
+    Hex,
+    MapData,
+    Terrain,
+    TileStyle,
+    MAX_SIDE_LEN,
+};
+use npc::{Npc, NpcSpawn};
+use scene::Scene;
+use settings::Settings;
+use std::{fs::File, io::{Read, Write}, path::Path};
+use toml::{self, value::Table, Value};
+use transitioned_grid_pos::TransitionedGridPos;
+
+
+/// Version of the save format that this build writes.
+pub const SAVE_VERSION: i64 = 4;
+
+/// Where quicksaves are written to and quickloaded from.
+pub const QUICKSAVE_PATH: &str = "./ahistorics_quicksave.toml";
+
+/// Upgrades a save by one version. The migration at index `i` turns a save
+/// of version `i + 1` into one of version `i + 2`.
+type Migration = fn(&mut Table) -> Result<(), Error>;
+
+const MIGRATIONS: &[Migration] = &[add_terrain, add_explored, add_spawns];
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SaveGame {
+    pub version: i64,
+    /// Game-time, in seconds, at the moment of saving.
+    pub time:    f64,
+    /// What all of the randomness in the scene was seeded with at the
+    /// moment of saving, reinterpreted as an `i64` like `SavedMap::seed`.
+    pub seed:    i64,
+    pub map:     SavedMap,
+    pub camera:  SavedPos,
+    pub player:  SavedPos,
+    pub npcs:    Vec<SavedNpc>,
+}
+
+/// A map is saved either as the seed that it was generated from plus the
+/// tiles that have changed since, or (for maps that weren't generated) as
+/// every non-blank tile and every NPC spawn.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedMap {
+    /// TOML only has signed integers, so the seed is stored reinterpreted as
+    /// an `i64`.
+    pub seed:     Option<i64>,
+    pub row_size: usize,
+    pub rows:     usize,
+    /// For seeded maps, only the tiles that differ from what the seed
+    /// generates. Otherwise, all non-blank tiles.
+    pub tiles:    Vec<SavedTile>,
+    /// Only present for maps that weren't generated from a seed.
+    pub npcs:     Option<Vec<NpcSpawn>>,
+    /// Hexes that the player has seen, as runs of `[start, length]` in row
+    /// order. Map files may leave this out, to start with nothing seen.
+    #[serde(default)]
+    pub explored: Vec<[usize; 2]>,
+    /// Path that the camera takes over the map when a new game starts on
+    /// it. Only map files have this, so it's never saved.
+    #[serde(default, skip_serializing)]
+    pub intro:    Vec<SavedKeyframe>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedTile {
+    pub x:      usize,
+    pub y:      usize,
+    /// Height of the tile, or `None` if this hex is blank.
+    pub height:  Option<i32>,
+    /// What the tile is made of. Map files may leave this out, in which case
+    /// it's the same as for a generated tile of the same height.
+    pub terrain: Option<Terrain>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedPos {
+    pub pos:   CubePoint<i32>,
+    pub angle: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedKeyframe {
+    /// Seconds that it takes to get here from the previous keyframe.
+    pub time:   f64,
+    pub pos:    CubePoint<i32>,
+    pub angle:  f64,
+    /// Name of the easing curve to get here with, as in the settings.
+    /// Defaults to `cubic_in_out`.
+    pub easing: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedNpc {
+    /// Index of the map's spawn that the NPC came from.
+    pub spawn:  usize,
+    pub pos:    CubePoint<i32>,
+    pub angle:  f64,
+    pub health: f64,
+}
+
+#[derive(Debug, Fail)]
+pub enum SaveError {
+    #[fail(display = "Save file doesn't say what version it is")]
+    MissingVersion,
+    #[fail(display = "Save file is version {}, which this build doesn't \
+                      know how to load", version)]
+    UnsupportedVersion {
+        version: i64,
+    },
+    #[fail(display = "Save file is not a TOML table")]
+    NotATable,
+    #[fail(display = "Map is {} hexes across and {} down, but both must be \
+                      within [1, {}]", row_size, rows, max)]
+    BadMapSize {
+        row_size: usize,
+        rows:     usize,
+        max:      usize,
+    },
+    #[fail(display = "Tile at ({}, {}) is off the edge of the {}x{} map",
+           x, y, row_size, rows)]
+    TileOutOfBounds {
+        x:        usize,
+        y:        usize,
+        row_size: usize,
+        rows:     usize,
+    },
+    #[fail(display = "Saved NPC came from spawn {}, but the map only has {}",
+           spawn, spawns)]
+    UnknownSpawn {
+        spawn:  usize,
+        spawns: usize,
+    },
+}
+
+
+impl SaveGame {
+    /// Takes a snapshot of everything needed to restore the given scene.
+    /// The scene is reseeded, so that it plays on from here the same way
+    /// that it will once the save is loaded.
+    pub fn capture(scene:    &mut Scene,
+                   settings: &Settings) -> Result<Self, Error>
+    {
+        let npcs = scene
+            .npcs
+            .iter()
+            .map(|npc| SavedNpc {
+                spawn:  npc.spawn(),
+                pos:    *npc.grid_pos().target_pos(),
+                angle:  npc.grid_pos().target_angle().radians(),
+                health: npc.health(),
+            })
+            .collect();
+
+        Ok(SaveGame {
+            version: SAVE_VERSION,
+            time:    scene.time,
+            seed:    scene.reseed() as i64,
+            map:     SavedMap::capture(&scene.map, settings)?,
+            camera:  SavedPos::capture(scene.camera.grid_pos()),
+            player:  SavedPos::capture(scene.player.grid_pos()),
+            npcs,
+        })
+    }
+
+    /// Puts the given scene into the state that this save describes.
+    pub fn restore(&self,
+                   scene:    &mut Scene,
+                   settings: &Settings) -> Result<(), Error>
+    {
+        let map = self.map.restore(settings)?;
+        let anim_time = settings.gameplay.npc_anim_time;
+        let spawns = map.npcs();
+        let mut npcs = Vec::with_capacity(self.npcs.len());
+        for saved in self.npcs.iter() {
+            let spawn = spawns.get(saved.spawn).ok_or(SaveError::UnknownSpawn {
+                spawn:  saved.spawn,
+                spawns: spawns.len(),
+            })?;
+
+            let mut npc = Npc::new(anim_time, saved.spawn, spawn, settings);
+            npc.grid_pos_mut()
+                .teleport(saved.pos, Angle::new(saved.angle));
+            npc.set_health(saved.health);
+            npcs.push(npc);
+        }
+
+        self.camera.restore(scene.camera.grid_pos_mut());
+        self.player.restore(scene.player.grid_pos_mut());
+        scene.map = map;
+        scene.npcs = npcs;
+        scene.time = self.time;
+        scene.seed_with(self.seed as u64);
+        scene.update_sight();
+
+        Ok(())
+    }
+
+    pub fn write_to<P: AsRef<Path>>(&self, save_path: P) -> Result<(), Error> {
+        // Going through a `Value` first makes sure that plain values are
+        // written out before tables, as TOML requires.
+        let contents = toml::to_string(&Value::try_from(self)?)?;
+        let mut save_file = File::create(save_path)?;
+        save_file.write_all(contents.as_bytes())?;
+
+        Ok(())
+    }
+
+    pub fn read_from<P: AsRef<Path>>(save_path: P) -> Result<Self, Error> {
+        let mut save_file = File::open(save_path)?;
+        let mut contents = String::new();
+        save_file.read_to_string(&mut contents)?;
+
+        let mut value = match contents.parse()? {
+            Value::Table(t) => t,
+            _ => return Err(SaveError::NotATable.into()),
+        };
+        migrate(&mut value)?;
+
+        Ok(Value::Table(value).try_into()?)
+    }
+}
+
+impl SavedMap {
+    /// Loads a map file, which is laid out just like the `[map]` table of a
+    /// save, along with the cutscene that it opens with, if any.
+    pub fn load<P: AsRef<Path>>(map_path: P, settings: &Settings)
+        -> Result<(MapData, Vec<Keyframe>), Error>
+    {
+        let mut map_file = File::open(map_path)?;
+        let mut contents = String::new();
+        map_file.read_to_string(&mut contents)?;
+
+        let saved = toml::from_str::<Self>(&contents)?;
+        let intro = saved
+            .intro
+            .iter()
+            .map(SavedKeyframe::restore)
+            .collect::<Result<_, _>>()?;
+
+        Ok((saved.restore(settings)?, intro))
+    }
+
+    fn capture(map: &MapData, settings: &Settings) -> Result<Self, Error> {
+        let (tiles, npcs) = if let Some(seed) = map.seed() {
+            let generated = simulated_map_data(
+                map.row_size(),
+                seed,
+                TileStyle::new(settings),
+            )?;
+
+            let diffs = map
+                .iter()
+                .zip(generated.iter())
+                .filter(|((hex, _, _), (gen_hex, _, _))| hex != gen_hex)
+                .map(|((hex, x, y), _)| SavedTile::new(hex, x, y))
+                .collect();
+
+            (diffs, None)
+        } else {
+            let tiles = map
+                .iter()
+                .filter(|(hex, _, _)| hex != &&Hex::Blank)
+                .map(|(hex, x, y)| SavedTile::new(hex, x, y))
+                .collect();
+
+            (tiles, Some(map.npcs().to_vec()))
+        };
+
+        Ok(SavedMap {
+            seed: map.seed().map(|s| s as i64),
+            row_size: map.row_size(),
+            rows: map.rows(),
+            tiles,
+            npcs,
+            explored: map.explored(),
+            intro: Vec::new(),
+        })
+    }
+
+    fn restore(&self, settings: &Settings) -> Result<MapData, Error> {
+        let (row_size, rows) = (self.row_size, self.rows);
+        let in_range = |len| len >= 1 && len <= MAX_SIDE_LEN;
+        if !in_range(row_size) || !in_range(rows) {
+            let max = MAX_SIDE_LEN;
+            return Err(SaveError::BadMapSize { row_size, rows, max }.into());
+        }
+
+        let style = TileStyle::new(settings);
+        let mut map = if let Some(seed) = self.seed {
+            simulated_map_data(self.row_size, seed as u64, style)?
+        } else {
+            MapData::new(
+                self.row_size,
+                vec![Hex::Blank; self.row_size * self.rows],
+                self.npcs.clone().unwrap_or_default(),
+                style,
+            )
+        };
+
+        for tile in self.tiles.iter() {
+            if tile.x >= map.row_size() || tile.y >= map.rows() {
+                return Err(SaveError::TileOutOfBounds {
+                    x:        tile.x,
+                    y:        tile.y,
+                    row_size: map.row_size(),
+                    rows:     map.rows(),
+                }.into());
+            }
+
+            let hex = tile.height.map_or(Hex::Blank, |height| {
+                let terrain = tile
+                    .terrain
+                    .unwrap_or_else(|| Terrain::for_height(height));
+
+                Hex::Tile(height, terrain)
+            });
+            map.set_rect(tile.x, tile.y, hex);
+        }
+        map.set_explored(&self.explored);
+
+        Ok(map)
+    }
+}
+
+impl SavedTile {
+    fn new(hex: &Hex, x: usize, y: usize) -> Self {
+        let (height, terrain) = match *hex {
+            Hex::Tile(h, t) => (Some(h), Some(t)),
+            Hex::Blank => (None, None),
+        };
+
+        SavedTile { x, y, height, terrain }
+    }
+}
+
+impl SavedKeyframe {
+    fn restore(&self) -> Result<Keyframe, Error> {
+        let easing = match self.easing {
+            Some(ref name) => name.parse()?,
+            None => Easing::CubicInOut,
+        };
+
+        Ok(Keyframe {
+            time: self.time,
+            view: View {
+                pos:   self.pos.cast(),
+                angle: Angle::new(self.angle),
+            },
+            easing,
+        })
+    }
+}
+
+impl SavedPos {
+    fn capture(pos: &TransitionedGridPos) -> Self {
+        SavedPos {
+            pos:   *pos.target_pos(),
+            angle: pos.target_angle().radians(),
+        }
+    }
+
+    fn restore(&self, pos: &mut TransitionedGridPos) {
+        pos.teleport(self.pos, Angle::new(self.angle));
+    }
+}
+
+
+/// Brings a freshly parsed save up to `SAVE_VERSION`.
+fn migrate(save: &mut Table) -> Result<(), Error> {
+    let version = save
+        .get("version")
+        .and_then(Value::as_integer)
+        .ok_or(SaveError::MissingVersion)?;
+    if version < 1 || version > SAVE_VERSION {
+        return Err(SaveError::UnsupportedVersion { version }.into());
+    }
+
+    for (i, migration) in MIGRATIONS
+        .iter()
+        .enumerate()
+        .skip(version as usize - 1)
+    {
+        migration(save)?;
+        save.insert("version".to_owned(), Value::Integer(i as i64 + 2));
+    }
+
+    Ok(())
+}
+
+/// Version 2 gave every tile a terrain. Tiles in older saves get the terrain
+/// that generated tiles of the same height have.
+fn add_terrain(save: &mut Table) -> Result<(), Error> {
+    let tiles = save
+        .get_mut("map")
+        .and_then(|map| map.get_mut("tiles"))
+        .and_then(Value::as_array_mut);
+
+    for tile in tiles.into_iter().flatten() {
+        let height = tile.get("height").and_then(Value::as_integer);
+        if let (Some(height), Some(tile)) = (height, tile.as_table_mut()) {
+            let terrain = Terrain::for_height(height as i32);
+            tile.insert("terrain".to_owned(), Value::try_from(terrain)?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Version 3 started keeping track of which hexes the player has seen.
+/// Nothing counts as seen in older saves, so it's all uncovered afresh.
+fn add_explored(save: &mut Table) -> Result<(), Error> {
+    if let Some(map) = save.get_mut("map").and_then(Value::as_table_mut) {
+        map.insert("explored".to_owned(), Value::Array(Vec::new()));
+    }
+
+    Ok(())
+}
+
+/// Version 4 matched saved NPCs up with the spawns that they came from, and
+/// started saving the seed of the scene's randomness. NPCs in older saves
+/// were saved in the same order as their spawns, and their scenes were
+/// seeded from the map, as they are when the game starts.
+fn add_spawns(save: &mut Table) -> Result<(), Error> {
+    let npcs = save.get_mut("npcs").and_then(Value::as_array_mut);
+    for (i, npc) in npcs.into_iter().flatten().enumerate() {
+        if let Some(npc) = npc.as_table_mut() {
+            npc.insert("spawn".to_owned(), Value::Integer(i as i64));
+        }
+    }
+
+    let seed = save
+        .get("map")
+        .and_then(|map| map.get("seed"))
+        .and_then(Value::as_integer)
+        .unwrap_or(0);
+    save.insert("seed".to_owned(), Value::Integer(seed));
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A save of a generated map with one tile dug out and raised, and an
+    /// NPC, as version 1 wrote it.
+    const VERSION_1: &str = r#"
+        version = 1
+        time = 12.5
+
+        [map]
+        seed = 7
+        row_size = 32
+        rows = 32
+
+        [[map.tiles]]
+        x = 3
+        y = 4
+
+        [[map.tiles]]
+        x = 5
+        y = 6
+        height = 2
+
+        [camera]
+        pos = { a = 1, b = -1, c = 0 }
+        angle = 0.0
+
+        [player]
+        pos = { a = 1, b = -1, c = 0 }
+        angle = 0.0
+
+        [[npcs]]
+        pos = { a = 2, b = 0, c = -2 }
+        angle = 1.0
+        health = 5.0
+
+        [[npcs]]
+        pos = { a = 0, b = 3, c = -3 }
+        angle = 2.0
+        health = 10.0
+    "#;
+
+    /// The same save as version 2 wrote it, with terrain on every tile.
+    const VERSION_2: &str = r#"
+        version = 2
+        time = 12.5
+
+        [map]
+        seed = 7
+        row_size = 32
+        rows = 32
+
+        [[map.tiles]]
+        x = 3
+        y = 4
+
+        [[map.tiles]]
+        x = 5
+        y = 6
+        height = 2
+        terrain = "sand"
+
+        [camera]
+        pos = { a = 1, b = -1, c = 0 }
+        angle = 0.0
+
+        [player]
+        pos = { a = 1, b = -1, c = 0 }
+        angle = 0.0
+
+        [[npcs]]
+        pos = { a = 2, b = 0, c = -2 }
+        angle = 1.0
+        health = 5.0
+
+        [[npcs]]
+        pos = { a = 0, b = 3, c = -3 }
+        angle = 2.0
+        health = 10.0
+    "#;
+
+    fn migrated(save: &str) -> SaveGame {
+        let mut save = match save.parse().unwrap() {
+            Value::Table(t) => t,
+            _ => panic!("not a table"),
+        };
+        migrate(&mut save).unwrap();
+
+        Value::Table(save).try_into().unwrap()
+    }
+
+    /// What every version has in common.
+    fn assert_unchanged(save: &SaveGame) {
+        assert_eq!(save.version, SAVE_VERSION);
+        assert_eq!(save.time, 12.5);
+        assert_eq!(save.map.seed, Some(7));
+        assert_eq!((save.map.row_size, save.map.rows), (32, 32));
+        assert_eq!(save.player.pos, CubePoint::new(1, -1, 0));
+
+        let blank = &save.map.tiles[0];
+        assert_eq!((blank.x, blank.y), (3, 4));
+        assert_eq!((blank.height, blank.terrain), (None, None));
+
+        // Older saves had NPCs in the same order as their spawns, and their
+        // randomness seeded from the map.
+        let spawns: Vec<_> = save.npcs.iter().map(|npc| npc.spawn).collect();
+        assert_eq!(spawns, [0, 1]);
+        assert_eq!(save.npcs[1].health, 10.0);
+        assert_eq!(save.seed, 7);
+        assert!(save.map.explored.is_empty());
+    }
+
+    #[test]
+    fn version_1_saves_get_terrain_by_height() {
+        let save = migrated(VERSION_1);
+        assert_unchanged(&save);
+
+        let raised = &save.map.tiles[1];
+        assert_eq!(raised.height, Some(2));
+        assert_eq!(raised.terrain, Some(Terrain::for_height(2)));
+    }
+
+    #[test]
+    fn version_2_saves_keep_their_terrain() {
+        let save = migrated(VERSION_2);
+        assert_unchanged(&save);
+
+        assert_eq!(save.map.tiles[1].terrain, Some(Terrain::Sand));
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        for &version in [0, SAVE_VERSION + 1].iter() {
+            let mut save = Table::new();
+            save.insert("version".to_owned(), Value::Integer(version));
+            assert!(migrate(&mut save).is_err(), "{}", version);
+        }
+        assert!(migrate(&mut Table::new()).is_err());
+    }
+}
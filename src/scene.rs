@@ -1,32 +1,254 @@
 use camera::Camera;
 use drawable::Drawable;
-use graphics::{Context, Graphics};
-use map_data::MapData;
+use fnv::FnvHashMap as Map;
+use geometry::{cube_dir, cube_round, CubePoint, Dir};
+use graphics::{types::Color, Context, Graphics};
+use map_data::{MapData, TileStyle, Visibility};
+use npc::Npc;
+use particles::{Emitter, ParticleStyle, Particles};
 use player::Player;
+use positioned::Positioned;
+use rand::prelude::*;
+use rand_pcg::Pcg64;
+use settings::Settings;
+use sprite::Sprites;
 use temporal::Temporal;
+use tween::Appearance;
+
+
+/// Seconds that a footstep kicks up dust for.
+const DUST_TIME: f64 = 0.1;
+
+/// Seconds that a hit gives off sparks for.
+const SPARKS_TIME: f64 = 0.06;
+
+/// What an NPC flashes when it's hit, and for how many seconds.
+const HIT_FLASH: (Color, f64) = ([1.0, 0.3, 0.3, 1.0], 0.3);
+
+/// Trauma that a hit adds to the camera's shake.
+const HIT_TRAUMA: f64 = 0.35;
+
+/// Health that each of the player's attacks takes off.
+const ATTACK_DAMAGE: f64 = 2.5;
+
+/// How much bigger the player swells when attacking, and for how many
+/// seconds.
+const ATTACK_PULSE: (f64, f64) = (1.25, 0.2);
 
 
 pub struct Scene {
-    pub camera: Camera,
-    pub map:    MapData,
-    pub player: Player,
+    pub camera:    Camera,
+    pub map:       MapData,
+    pub player:    Player,
+    pub npcs:      Vec<Npc>,
+    pub particles: Particles,
+    /// Seconds of game-time that have elapsed.
+    pub time:      f64,
+    /// How many hexes away the player can see.
+    sight_radius:  i32,
+    /// Tile that the player was on at the last step.
+    player_tile:   CubePoint<i32>,
+    /// Where all of the randomness in the scene comes from, seeded from the
+    /// map so that replays on the same map always play out the same, and
+    /// reseeded whenever the game is saved or loaded.
+    rng:           Pcg64,
 }
 
 
 impl Scene {
-    pub fn new(camera: Camera, map: MapData, player: Player) -> Self {
-        Self { camera, map, player }
+    pub fn new(camera:   Camera,
+               map:      MapData,
+               player:   Player,
+               npcs:     Vec<Npc>,
+               settings: &Settings) -> Self
+    {
+        let player_tile = cube_round(*player.pos());
+        let seed = map.seed().unwrap_or(0);
+        let mut scene = Self {
+            camera,
+            map,
+            player,
+            npcs,
+            particles: Particles::new(0),
+            time: 0.0,
+            sight_radius: settings.gameplay.sight_radius,
+            player_tile,
+            rng: Pcg64::seed_from_u64(0),
+        };
+        scene.seed_with(seed);
+        scene.update_sight();
+
+        scene
+    }
+
+    /// Seeds all of the randomness in the scene with `seed`.
+    pub fn seed_with(&mut self, seed: u64) {
+        self.rng = Pcg64::seed_from_u64(seed);
+        self.particles.reseed(self.rng.gen());
+    }
+
+    /// Seeds all of the randomness in the scene afresh from what it would
+    /// have come up with next, returning the new seed.
+    pub fn reseed(&mut self) -> u64 {
+        let seed = self.rng.gen();
+        self.seed_with(seed);
+
+        seed
+    }
+
+    /// Picks up any changes to the settings, e.g. after they're reloaded.
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        self.camera.apply_settings(settings);
+        self.map.set_style(TileStyle::new(settings));
+        self.sight_radius = settings.gameplay.sight_radius;
+        self.player.apply_settings(settings);
+        for npc in self.npcs.iter_mut() {
+            npc.apply_settings(settings);
+        }
     }
 
     pub fn step(&mut self, dt: f64) {
+        self.time += dt;
         self.camera.step(dt);
         self.player.step(dt);
+        self.update_sight();
+
+        let player_pos = *self.player.pos();
+        for npc in self.npcs.iter_mut() {
+            npc.think(&self.map, player_pos, &mut self.rng);
+            npc.step(dt);
+        }
+
+        let player_tile = cube_round(player_pos);
+        if player_tile != self.player_tile {
+            self.player_tile = player_tile;
+            self.kick_up_dust(player_pos);
+        }
+        self.particles.step(dt);
+    }
+
+    /// Has the player attack the hex that they're facing, hitting any NPC
+    /// that's standing there.
+    pub fn attack(&mut self) {
+        self.player
+            .add_effect(Appearance::pulse(ATTACK_PULSE.0, ATTACK_PULSE.1));
+
+        let grid_pos = self.player.grid_pos();
+        let facing = Dir::from_angle(grid_pos.target_angle());
+        let target = *grid_pos.target_pos() + cube_dir(facing);
+
+        let index = self
+            .npcs
+            .iter()
+            .position(|npc| cube_round(*npc.grid_pos().pos()) == target);
+        if let Some(index) = index {
+            self.hit_npc(index, ATTACK_DAMAGE);
+        }
+    }
+
+    /// Hits the given NPC for `damage`, making it flash, throwing off sparks,
+    /// and shaking the camera.
+    fn hit_npc(&mut self, index: usize, damage: f64) {
+        let npc = &mut self.npcs[index];
+        let health = npc.health();
+        npc.set_health(health - damage);
+        npc.add_effect(Appearance::flash(HIT_FLASH.0, HIT_FLASH.1));
+
+        let pos = *npc.grid_pos().pos();
+        let height = self.map.height(cube_round(pos)).unwrap_or(0);
+        let sparks = ParticleStyle::hit();
+        self.particles.add(Emitter::new(sparks, pos, height, SPARKS_TIME));
+        self.camera.add_trauma(HIT_TRAUMA);
+    }
+
+    /// Puffs dust off the tile at `pos`, if there's a tile there that can be
+    /// seen.
+    fn kick_up_dust(&mut self, pos: CubePoint<f64>) {
+        let tile = cube_round(pos);
+        if let (Some(height), Some(color)) =
+            (self.map.height(tile), self.map.tile_color(tile))
+        {
+            let dust = ParticleStyle::dust(color);
+            self.particles.add(Emitter::new(dust, pos, height, DUST_TIME));
+        }
+    }
+
+    /// Works out what the player can see from the tile that they're on.
+    pub fn update_sight(&mut self) {
+        let viewpoint = cube_round(*self.player.pos());
+        self.map.update_sight(viewpoint, self.sight_radius);
+    }
+
+    /// Whether the given NPC is on a hex that the player can see.
+    fn in_sight(&self, npc: &Npc) -> bool {
+        let tile = cube_round(*npc.grid_pos().pos());
+
+        self.map.visibility(tile) == Visibility::Visible
+    }
+
+    /// Draws the scene with the given sprites, or with plain shapes without
+    /// them.
+    pub fn draw_with<G: Graphics>(&self,
+                                  camera:  &Camera,
+                                  ctx:     &Context,
+                                  g:       &mut G,
+                                  sprites: Option<&Sprites<G::Texture>>)
+    {
+        if camera.is_elevated() {
+            self.draw_elevated(camera, ctx, g, sprites);
+            return;
+        }
+
+        let npc_sheet = sprites.map(|s| &*s.npc);
+        self.map.draw_with(camera, ctx, g, sprites.map(|s| &s.tiles));
+        for npc in self.npcs.iter().filter(|npc| self.in_sight(npc)) {
+            npc.draw_with(camera, ctx, g, npc_sheet);
+        }
+        self.player.draw_with(camera, ctx, g, sprites.map(|s| &*s.player));
+        self.particles.draw(camera, ctx, g);
+    }
+
+    /// Draws each entity just after the tile that it's standing on, so that
+    /// tiles in front of it still cover it.
+    fn draw_elevated<G: Graphics>(&self,
+                                  camera:  &Camera,
+                                  ctx:     &Context,
+                                  g:       &mut G,
+                                  sprites: Option<&Sprites<G::Texture>>)
+    {
+        let player_sheet = sprites.map(|s| &*s.player);
+        let npc_sheet = sprites.map(|s| &*s.npc);
+        let tiles = sprites.map(|s| &s.tiles);
+
+        let player_tile = cube_round(*self.player.pos());
+        let mut npcs_at: Map<_, Vec<_>> = Map::default();
+        for npc in self.npcs.iter().filter(|npc| self.in_sight(npc)) {
+            let tile = cube_round(*npc.grid_pos().pos());
+            npcs_at.entry(tile).or_default().push(npc);
+        }
+
+        self.map.draw_elevated(camera, ctx, g, tiles, |pos, lifted, g| {
+            for npc in npcs_at.remove(&pos).unwrap_or_default() {
+                npc.draw_with(camera, lifted, g, npc_sheet);
+            }
+            if pos == player_tile {
+                self.player.draw_with(camera, lifted, g, player_sheet);
+            }
+        });
+
+        // Whatever's left isn't standing on a tile that was drawn.
+        for npc in npcs_at.values().flatten() {
+            npc.draw_with(camera, ctx, g, npc_sheet);
+        }
+        if self.map.height(player_tile).is_none() {
+            self.player.draw_with(camera, ctx, g, player_sheet);
+        }
+        self.particles.draw(camera, ctx, g);
     }
 }
 
 impl Drawable for Scene {
     fn draw<G: Graphics>(&self, camera: &Camera, ctx: &Context, g: &mut G) {
-        self.map.draw(camera, ctx, g);
-        self.player.draw(camera, ctx, g);
+        self.draw_with(camera, ctx, g, None);
     }
 }
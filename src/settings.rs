@@ -1,33 +1,333 @@
-use failure::Error;
+//! Loading and validation of the game's settings.
+//!
+//! Settings are built up out of layers, each merged over the last, so that
+//! each only needs to mention the settings that it changes:
+//!
+//! 1. The defaults, given by `DEFAULT_SETTINGS`.
+//! 2. The user's settings file, in their config directory.
+//! 3. The project's settings file, found by searching upwards from the
+//!    current directory.
+//! 4. Environment variables like `AHISTORICS_GRAPHICS__ZOOM=2`, with `__`
+//!    separating the section from the key.
+//! 5. Overrides given on the command line.
+//!
+//! Keys that the defaults don't have are ignored with a warning, and values
+//! of the wrong type or out of range are errors that name the offending key.
+
+use color::parse_color;
+use easing::Easing;
+use failure::{err_msg, Error, ResultExt};
 use graphics::types::Color;
-use toml;
-use std::{ffi::OsString, fs::File, io::Read, path::{Path, PathBuf}};
+use map_data::MAX_SIDE_LEN;
+use piston::input::keyboard::Key;
+use settings_file::write_setting;
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    fmt::{self, Display},
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use toml::{value::Table, Value};
+
+
+/// The default value of every setting, and so also the full schema.
+const DEFAULT_SETTINGS: &str = include_str!("default_settings.toml");
+
+/// Name of the project's settings file, which is looked for in the current
+/// directory and then each of its ancestors.
+pub const PROJECT_SETTINGS: &str = "ahistorics_settings.toml";
+
+/// Environment variables starting with this are treated as settings.
+const ENV_PREFIX: &str = "AHISTORICS_";
+
+/// Seconds between checks of whether the settings files have changed.
+const POLL_INTERVAL: f64 = 0.5;
+
+/// Name of the theme made up of just the colours in `[colors]`.
+pub const DEFAULT_THEME: &str = "default";
+
+/// Every colour that a theme can set.
+const COLOR_NAMES: [&str; 13] = [
+    "background_color",
+    "low_ground_color",
+    "high_ground_color",
+    "tile_outline_color",
+    "water_color",
+    "sand_color",
+    "grass_color",
+    "dirt_color",
+    "rock_color",
+    "player_color",
+    "player_outline_color",
+    "npc_color",
+    "npc_outline_color",
+];
 
 
 #[derive(Debug, Clone)]
 pub struct Settings {
-    pub colors: Colors,
+    pub graphics:  Graphics,
+    pub colors:    Colors,
+    pub input:     Input,
+    pub gameplay:  Gameplay,
+    pub camera:    Camera,
+    pub shading:   Shading,
+    pub minimap:   Minimap,
+    pub hud:       Hud,
+    pub sprites:   Sprites,
+    pub assets:    Assets,
+    pub audio:     Audio,
+    pub debug:     Debugging,
+    pub animation: Animation,
+    /// Problems that weren't bad enough to stop the settings from loading,
+    /// e.g. unknown keys.
+    pub warnings:  Vec<String>,
+    /// All of the layers merged together, before being validated.
+    merged:        Table,
+    /// Which layer each setting (by its dotted key) came from, if it's not
+    /// the defaults.
+    origins:       BTreeMap<String, Origin>,
+    /// Tables that the active theme's colours are read from, most specific
+    /// first, ending with `colors`.
+    theme_chain:   Vec<String>,
+}
+
+/// A layer of settings, and so where the value of a setting came from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    Default,
+    File(PathBuf),
+    /// The named environment variable.
+    Environment(String),
+    CommandLine,
+    /// Changed while the game is running, without being saved.
+    Session,
 }
 
+/// Everywhere that settings are loaded from, other than the defaults, in
+/// increasing order of precedence. Environment variables come in between
+/// the files and the overrides, and changes made during the session come
+/// last of all.
+#[derive(Debug, Clone, Default)]
+pub struct Sources {
+    /// The user's settings file, if they have one.
+    pub user:      Option<PathBuf>,
+    /// The project's settings file, if there is one.
+    pub project:   Option<PathBuf>,
+    /// Settings given on the command line.
+    pub overrides: Table,
+    /// Settings changed while the game is running, but not saved, e.g. by
+    /// cycling through the themes.
+    pub session:   Table,
+}
+
+#[derive(Debug, Clone)]
+pub struct Graphics {
+    pub width:       u32,
+    pub height:      u32,
+    pub fullscreen:  bool,
+    pub vsync:       bool,
+    pub samples:     u8,
+    /// Game updates per second.
+    pub ups:         u64,
+    pub zoom:        f64,
+    pub min_zoom:    f64,
+    pub max_zoom:    f64,
+    /// Factor that each press of a zoom key zooms in or out by.
+    pub zoom_step:   f64,
+    /// Whether tiles are drawn as raised columns, rather than flat.
+    pub elevated:    bool,
+    /// How far up the screen each level of tile height raises a tile, as a
+    /// fraction of the size of a hex.
+    pub tile_height: f64,
+    /// Whether flat maps are drawn from cached batches, rather than tile by
+    /// tile. Elevated maps are always drawn tile by tile.
+    pub batch_tiles: bool,
+}
+
+/// The colours of the active theme.
 #[derive(Debug, Clone)]
 pub struct Colors {
+    /// Name of the active theme.
+    pub theme:                String,
     pub background_color:     Color,
-    pub foreground_color:     Color,
+    pub low_ground_color:     Color,
+    pub high_ground_color:    Color,
+    pub tile_outline_color:   Color,
+    pub water_color:          Color,
+    pub sand_color:           Color,
+    pub grass_color:          Color,
+    pub dirt_color:           Color,
+    pub rock_color:           Color,
     pub player_color:         Color,
     pub player_outline_color: Color,
+    pub npc_color:            Color,
+    pub npc_outline_color:    Color,
+}
+
+/// Key bindings.
+#[derive(Debug, Clone)]
+pub struct Input {
+    pub move_forward:  Key,
+    pub move_backward: Key,
+    pub turn_left:     Key,
+    pub turn_right:    Key,
+    pub rotate_left:   Key,
+    pub rotate_right:  Key,
+    pub attack:        Key,
+    pub zoom_in:       Key,
+    pub zoom_out:      Key,
+    pub quicksave:     Key,
+    pub quickload:     Key,
+    pub options:       Key,
+    pub next_theme:    Key,
+    pub debug_overlay: Key,
+}
+
+#[derive(Debug, Clone)]
+pub struct Gameplay {
+    pub player_anim_time: f64,
+    pub camera_anim_time: f64,
+    pub npc_anim_time:    f64,
+    /// Side length, in hexes, of generated maps.
+    pub map_size:         usize,
+    pub fog_of_war:       bool,
+    /// How many hexes away the player can see.
+    pub sight_radius:     i32,
+}
+
+/// How the camera follows the player, shakes, and turns.
+#[derive(Debug, Clone)]
+pub struct Camera {
+    /// Angular frequency of the spring that the view follows the camera
+    /// with. `0` keeps up exactly.
+    pub follow_frequency: f64,
+    /// Furthest, in hexes, that the view moves when shaking the hardest.
+    pub shake_offset:     f64,
+    /// Furthest, in degrees, that the view turns when shaking the hardest.
+    pub shake_angle:      f64,
+    pub shake_frequency:  f64,
+    /// Trauma that wears off each second, out of a maximum of `1`.
+    pub trauma_decay:     f64,
+    /// Degrees per second that holding a rotate key turns the camera by.
+    pub rotate_speed:     f64,
+    /// Degrees that dragging the mouse one pixel turns the camera by.
+    pub drag_sensitivity: f64,
+    /// Whether the camera turns back to the nearest hex-aligned angle once
+    /// it's let go of.
+    pub snap_back:        bool,
+}
+
+/// How tiles are shaded.
+#[derive(Debug, Clone)]
+pub struct Shading {
+    /// Height at or below which tiles get the low ground colour.
+    pub low_height:            i32,
+    /// Height at or above which tiles get the high ground colour.
+    pub high_height:           i32,
+    /// How far tiles are blended towards their terrain colour.
+    pub terrain_weight:        f64,
+    /// Direction that light comes from, in degrees anticlockwise from the
+    /// right of the screen.
+    pub light_angle:           f64,
+    pub light_strength:        f64,
+    /// Brightness of tiles that have been seen before, but aren't in sight.
+    pub remembered_brightness: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Minimap {
+    pub shown:   bool,
+    pub corner:  Corner,
+    /// Length, in pixels, of the longer side of the minimap.
+    pub size:    f64,
+    /// Gap, in pixels, between the minimap and the edges of the window.
+    pub margin:  f64,
+    pub opacity: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Hud {
+    pub shown:           bool,
+    pub font_size:       u32,
+    /// Where the player's position, facing, and the FPS are shown.
+    pub info_corner:     Corner,
+    /// Where status messages are shown.
+    pub messages_corner: Corner,
+    /// Gap, in pixels, between the HUD and the edges of the window.
+    pub margin:          f64,
+    /// Seconds that each status message stays on screen for.
+    pub message_time:    f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sprites {
+    /// Whether entities and tiles are drawn with sprites and textures, rather
+    /// than as plain shapes.
+    pub shown: bool,
+    /// File saying which assets the entities and tile textures are drawn
+    /// with.
+    pub path:  PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct Assets {
+    /// The asset manifest, which is looked for with `find_upwards`.
+    pub manifest: PathBuf,
+}
+
+/// A corner of the window that something is drawn in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// Volumes, each within `[0, 1]`.
+#[derive(Debug, Clone)]
+pub struct Audio {
+    pub master_volume:  f64,
+    pub music_volume:   f64,
+    pub effects_volume: f64,
 }
 
-#[derive(Deserialize)]
-struct RawSettings {
-    colors: RawColors,
+#[derive(Debug, Clone)]
+pub struct Debugging {
+    pub overlay: bool,
+}
+
+/// Easing curves used by each kind of entity.
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub player: MotionEasing,
+    pub camera: MotionEasing,
+    pub npc:    MotionEasing,
 }
 
-#[derive(Deserialize)]
-struct RawColors {
-    background_color:     String,
-    foreground_color:     String,
-    player_color:         String,
-    player_outline_color: String,
+#[derive(Debug, Clone, Copy)]
+pub struct MotionEasing {
+    pub translation: Easing,
+    pub rotation:    Easing,
+}
+
+/// Notices when any settings file changes, by polling modification times.
+pub struct SettingsWatcher {
+    sources:    Sources,
+    /// Every settings file, along with when it was last modified.
+    files:      Vec<(PathBuf, Option<SystemTime>)>,
+    /// Seconds since the files were last checked.
+    since_poll: f64,
+}
+
+/// Reads typed, validated values out of a merged settings table.
+struct Reader<'a> {
+    root: &'a Value,
 }
 
 #[derive(Debug, Fail)]
@@ -42,89 +342,1197 @@ pub enum SettingsError {
     FileNotFound {
         filename: OsString,
     },
+    #[fail(display = "Settings file is not a TOML table")]
+    NotATable,
+    #[fail(display = "The setting `{}` is missing from the defaults", key)]
+    MissingDefault {
+        key: String,
+    },
+    #[fail(display = "Invalid value for `{}`: {} {}", key, value, reason)]
+    InvalidValue {
+        key:    String,
+        value:  String,
+        reason: String,
+    },
 }
 
 
 impl Settings {
-    pub fn get_from<P: AsRef<Path>>(settings_path: P) -> Result<Self, Error> {
-        let mut settings_file = File::open(settings_path)?;
-        let mut contents = String::new();
-        settings_file.read_to_string(&mut contents)?;
+    /// Every setting's value, along with where it came from, one per line.
+    pub fn describe(&self) -> String {
+        let mut leaves = Vec::new();
+        leaves_of(&self.merged, "", &mut leaves);
+
+        let lines: Vec<_> = leaves
+            .into_iter()
+            .map(|(key, value)| {
+                let origin = self.origin(&key);
+
+                (format!("{} = {}", key, value), origin)
+            })
+            .collect();
+        let width = lines.iter().map(|(line, _)| line.len()).max();
+
+        lines
+            .iter()
+            .map(|(line, origin)| format!(
+                "{:width$}  # {}\n",
+                line,
+                origin,
+                width = width.unwrap_or(0),
+            ))
+            .collect()
+    }
+
+    /// The value of the setting with the given dotted key, as it was merged
+    /// from every layer.
+    pub fn value(&self, key: &str) -> Option<&Value> {
+        let mut parts = key.split('.');
+        let mut value = self.merged.get(parts.next()?)?;
+        for part in parts {
+            value = value.get(part)?;
+        }
+
+        Some(value)
+    }
 
-        Self::unraw(&toml::from_str(&contents)?)
+    /// Which layer the setting with the given dotted key came from.
+    pub fn origin(&self, key: &str) -> &Origin {
+        self.origins.get(key).unwrap_or(&Origin::Default)
     }
 
-    pub fn get_from_recur<P>(settings_path: P) -> Result<Self, Error>
-        where P: AsRef<Path>
+    /// Names of every theme, starting with the default.
+    pub fn themes(&self) -> Vec<&str> {
+        let themes = self.merged.get("themes").and_then(Value::as_table);
+
+        Some(DEFAULT_THEME)
+            .into_iter()
+            .chain(themes.into_iter().flat_map(|t| t.keys().map(|k| &k[..])))
+            .collect()
+    }
+
+    /// Name of the theme after the active one, wrapping around.
+    pub fn next_theme(&self) -> &str {
+        let themes = self.themes();
+        let active = themes
+            .iter()
+            .position(|&t| t == self.colors.theme)
+            .unwrap_or(0);
+
+        themes[(active + 1) % themes.len()]
+    }
+
+    /// The dotted key that the active theme's colour with the given name
+    /// (e.g. `"npc_color"`) is read from.
+    pub fn color_key(&self, name: &str) -> String {
+        self.theme_chain
+            .iter()
+            .map(|table| format!("{}.{}", table, name))
+            .find(|key| self.value(key).is_some())
+            .unwrap_or_else(|| format!("colors.{}", name))
+    }
+
+    /// Names of the settings that differ between `self` and `other`, but
+    /// that only take effect when the game is restarted.
+    pub fn needing_restart(&self, other: &Self) -> Vec<&'static str> {
+        let (g, h) = (&self.graphics, &other.graphics);
+        let changes = [
+            ("graphics.width", g.width != h.width),
+            ("graphics.height", g.height != h.height),
+            ("graphics.fullscreen", g.fullscreen != h.fullscreen),
+            ("graphics.vsync", g.vsync != h.vsync),
+            ("graphics.samples", g.samples != h.samples),
+            ("graphics.ups", g.ups != h.ups),
+            (
+                "gameplay.map_size",
+                self.gameplay.map_size != other.gameplay.map_size,
+            ),
+            ("sprites.path", self.sprites.path != other.sprites.path),
+            (
+                "assets.manifest",
+                self.assets.manifest != other.assets.manifest,
+            ),
+        ];
+
+        changes
+            .iter()
+            .filter(|&&(_, changed)| changed)
+            .map(|&(key, _)| key)
+            .collect()
+    }
+
+    /// Builds the settings out of a table that already has every key.
+    fn read(merged:       Table,
+            origins:      BTreeMap<String, Origin>,
+            mut warnings: Vec<String>) -> Result<Self, Error>
     {
-        let path = settings_path.as_ref();
-        if let Ok(s) = Self::get_from(path) {
-            return Ok(s);
+        let root = Value::Table(merged);
+        let r = Reader { root: &root };
+
+        let graphics = Graphics {
+            width:       r.int("graphics.width", 1, 16_384)? as u32,
+            height:      r.int("graphics.height", 1, 16_384)? as u32,
+            fullscreen:  r.boolean("graphics.fullscreen")?,
+            vsync:       r.boolean("graphics.vsync")?,
+            samples:     r.samples("graphics.samples")?,
+            ups:         r.int("graphics.ups", 1, 1_000)? as u64,
+            zoom:        r.float("graphics.zoom", 0.01, 100.0)?,
+            min_zoom:    r.float("graphics.min_zoom", 0.01, 100.0)?,
+            max_zoom:    r.float("graphics.max_zoom", 0.01, 100.0)?,
+            zoom_step:   r.float("graphics.zoom_step", 1.0, 10.0)?,
+            elevated:    r.boolean("graphics.elevated")?,
+            tile_height: r.float("graphics.tile_height", 0.0, 1.0)?,
+            batch_tiles: r.boolean("graphics.batch_tiles")?,
+        };
+        if graphics.min_zoom > graphics.max_zoom {
+            return Err(r
+                .invalid("graphics.min_zoom", "is greater than the max_zoom")
+                .into());
+        }
+        if graphics.zoom < graphics.min_zoom
+            || graphics.zoom > graphics.max_zoom
+        {
+            let reason = "is not between min_zoom and max_zoom";
+            return Err(r.invalid("graphics.zoom", reason).into());
         }
 
-        let filename = path.file_name().ok_or(SettingsError::BadFilePath {
-            path: path.to_owned(),
-        })?;
+        let theme = r.string("colors.theme")?;
+        let theme_chain = theme_tables(&r, theme)?;
+        let colors = Colors::read(&r, theme, &theme_chain)?;
 
-        let canonical_path = path.parent().ok_or(SettingsError::FileNotFound {
-            filename: filename.to_owned(),
-        })?;
-        let canonical_path_buf = canonical_path.canonicalize()?;
-        let mut canonical_path = canonical_path_buf.as_path();
+        // Check every other theme too, so that mistakes in them show up now
+        // rather than whenever they're switched to.
+        let themes = root.get("themes").and_then(Value::as_table);
+        for (name, table) in themes.into_iter().flatten() {
+            let table = table.as_table().ok_or_else(|| {
+                r.invalid(&format!("themes.{}", name), "is not a table")
+            })?;
+            for key in table.keys() {
+                if key != "inherits" && !COLOR_NAMES.contains(&key.as_str()) {
+                    warnings.push(format!(
+                        "Unknown setting `themes.{}.{}` was ignored",
+                        name,
+                        key,
+                    ));
+                }
+            }
+
+            Colors::read(&r, name, &theme_tables(&r, name)?)?;
+        }
 
-        while let Some(p) = canonical_path.parent() {
-            if let Ok(s) = Self::get_from(p.join(filename)) {
-                return Ok(s);
+        let input = Input {
+            move_forward:  r.key("input.move_forward")?,
+            move_backward: r.key("input.move_backward")?,
+            turn_left:     r.key("input.turn_left")?,
+            turn_right:    r.key("input.turn_right")?,
+            rotate_left:   r.key("input.rotate_left")?,
+            rotate_right:  r.key("input.rotate_right")?,
+            attack:        r.key("input.attack")?,
+            zoom_in:       r.key("input.zoom_in")?,
+            zoom_out:      r.key("input.zoom_out")?,
+            quicksave:     r.key("input.quicksave")?,
+            quickload:     r.key("input.quickload")?,
+            options:       r.key("input.options")?,
+            next_theme:    r.key("input.next_theme")?,
+            debug_overlay: r.key("input.debug_overlay")?,
+        };
+        let bindings = input.bindings();
+        for (i, &(name, key)) in bindings.iter().enumerate() {
+            if let Some(&(other, _)) =
+                bindings[..i].iter().find(|&&(_, k)| k == key)
+            {
+                let reason = format!("is already bound to `input.{}`", other);
+                let key = format!("input.{}", name);
+                return Err(r.invalid(&key, reason).into());
             }
+        }
 
-            canonical_path = p;
+        let anim_time = |key| r.float(key, 0.01, 10.0);
+        let max_side_len = MAX_SIDE_LEN as i64;
+        let gameplay = Gameplay {
+            player_anim_time: anim_time("gameplay.player_anim_time")?,
+            camera_anim_time: anim_time("gameplay.camera_anim_time")?,
+            npc_anim_time:    anim_time("gameplay.npc_anim_time")?,
+            map_size:         r.int("gameplay.map_size", 1, max_side_len)?
+                as usize,
+            fog_of_war:       r.boolean("gameplay.fog_of_war")?,
+            sight_radius:     r.int("gameplay.sight_radius", 1, 64)? as i32,
+        };
+
+        let camera = Camera {
+            follow_frequency: r.float("camera.follow_frequency", 0.0, 100.0)?,
+            shake_offset:     r.float("camera.shake_offset", 0.0, 10.0)?,
+            shake_angle:      r.float("camera.shake_angle", 0.0, 180.0)?,
+            shake_frequency:  r.float("camera.shake_frequency", 0.0, 100.0)?,
+            trauma_decay:     r.float("camera.trauma_decay", 0.0, 100.0)?,
+            rotate_speed:     r.float("camera.rotate_speed", 0.0, 3_600.0)?,
+            drag_sensitivity: r.float("camera.drag_sensitivity", 0.0, 10.0)?,
+            snap_back:        r.boolean("camera.snap_back")?,
+        };
+
+        let height = |key| r.int(key, -1_000, 1_000).map(|h| h as i32);
+        let shading = Shading {
+            low_height:     height("shading.low_height")?,
+            high_height:    height("shading.high_height")?,
+            terrain_weight: r.float("shading.terrain_weight", 0.0, 1.0)?,
+            light_angle:    r.float("shading.light_angle", -360.0, 360.0)?,
+            light_strength: r.float("shading.light_strength", 0.0, 1.0)?,
+            remembered_brightness:
+                r.float("shading.remembered_brightness", 0.0, 1.0)?,
+        };
+        if shading.low_height >= shading.high_height {
+            let reason = "is not less than the high_height";
+            return Err(r.invalid("shading.low_height", reason).into());
         }
 
-        Err(SettingsError::FileNotFound {
-            filename: filename.to_owned(),
-        }.into())
-    }
+        let minimap = Minimap {
+            shown:   r.boolean("minimap.shown")?,
+            corner:  r.corner("minimap.corner")?,
+            size:    r.float("minimap.size", 16.0, 4_096.0)?,
+            margin:  r.float("minimap.margin", 0.0, 1_024.0)?,
+            opacity: r.float("minimap.opacity", 0.0, 1.0)?,
+        };
+
+        let hud = Hud {
+            shown:           r.boolean("hud.shown")?,
+            font_size:       r.int("hud.font_size", 6, 72)? as u32,
+            info_corner:     r.corner("hud.info_corner")?,
+            messages_corner: r.corner("hud.messages_corner")?,
+            margin:          r.float("hud.margin", 0.0, 1_024.0)?,
+            message_time:    r.float("hud.message_time", 0.5, 60.0)?,
+        };
+
+        let sprites = Sprites {
+            shown: r.boolean("sprites.shown")?,
+            path:  PathBuf::from(r.string("sprites.path")?),
+        };
+
+        let assets = Assets {
+            manifest: PathBuf::from(r.string("assets.manifest")?),
+        };
 
-    fn unraw(raw: &RawSettings) -> Result<Self, Error> {
-        let background_color = hex_to_color(&raw.colors.background_color)?;
-        let foreground_color = hex_to_color(&raw.colors.foreground_color)?;
-        let player_color = hex_to_color(&raw.colors.player_color)?;
-        let player_outline_color =
-            hex_to_color(&raw.colors.player_outline_color)?;
+        let audio = Audio {
+            master_volume:  r.float("audio.master_volume", 0.0, 1.0)?,
+            music_volume:   r.float("audio.music_volume", 0.0, 1.0)?,
+            effects_volume: r.float("audio.effects_volume", 0.0, 1.0)?,
+        };
 
-        let colors = Colors {
-            background_color,
-            foreground_color,
-            player_color,
-            player_outline_color,
+        let debug = Debugging {
+            overlay: r.boolean("debug.overlay")?,
+        };
+
+        let animation = Animation {
+            player: MotionEasing::read(&r, "animation.player")?,
+            camera: MotionEasing::read(&r, "animation.camera")?,
+            npc:    MotionEasing::read(&r, "animation.npc")?,
         };
 
         Ok(Settings {
+            graphics,
             colors,
+            input,
+            gameplay,
+            camera,
+            shading,
+            minimap,
+            hud,
+            sprites,
+            assets,
+            audio,
+            debug,
+            animation,
+            warnings,
+            merged: match root {
+                Value::Table(t) => t,
+                _ => Table::new(),
+            },
+            origins,
+            theme_chain,
+        })
+    }
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Origin::Default => write!(f, "default"),
+            Origin::File(ref path) => write!(f, "{}", path.display()),
+            Origin::Environment(ref name) => write!(f, "${}", name),
+            Origin::CommandLine => write!(f, "command line"),
+            Origin::Session => write!(f, "this session"),
+        }
+    }
+}
+
+impl Sources {
+    /// Finds the user's settings file, and the project's: `project` if it's
+    /// given, or else the nearest `PROJECT_SETTINGS`.
+    pub fn discover(project:   Option<PathBuf>,
+                    overrides: Table) -> Result<Self, Error>
+    {
+        let project = match project {
+            Some(path) => Some(path),
+            None => match find_upwards(PROJECT_SETTINGS) {
+                Ok(path) => Some(path),
+                Err(SettingsError::FileNotFound { .. }) => None,
+                Err(e) => return Err(e.into()),
+            },
+        };
+
+        Ok(Sources {
+            user: user_settings_path().filter(|p| p.is_file()),
+            project,
+            overrides,
+            session: Table::new(),
+        })
+    }
+
+    /// Every settings file, in order of increasing precedence.
+    pub fn files(&self) -> impl Iterator<Item=&PathBuf> {
+        self.user.iter().chain(self.project.iter())
+    }
+
+    /// Merges every layer together and validates the result.
+    pub fn load(&self) -> Result<Settings, Error> {
+        let mut merged = match DEFAULT_SETTINGS.parse()? {
+            Value::Table(t) => t,
+            _ => return Err(SettingsError::NotATable.into()),
+        };
+        let mut origins = BTreeMap::new();
+        let mut warnings = Vec::new();
+
+        let mut layers = Vec::new();
+        for path in self.files() {
+            let table = read_table(path).with_context(|_| {
+                format!("Couldn't load settings from {}", path.display())
+            })?;
+            layers.push((table, Origin::File(path.clone())));
+        }
+        for (name, (key, value)) in env_settings(&mut warnings) {
+            let mut table = Table::new();
+            set_dotted(&mut table, &key, value);
+            layers.push((table, Origin::Environment(name)));
+        }
+        layers.push((self.overrides.clone(), Origin::CommandLine));
+        layers.push((self.session.clone(), Origin::Session));
+
+        for (table, origin) in layers {
+            merge(
+                &mut merged,
+                table,
+                "",
+                &origin,
+                &mut origins,
+                &mut warnings,
+            )?;
+        }
+
+        if self.user.is_none() && self.project.is_none() {
+            warnings.push(format!(
+                "No {:?} found, so using the default settings",
+                PROJECT_SETTINGS,
+            ));
+        }
+
+        Settings::read(merged, origins, warnings)
+    }
+}
+
+impl SettingsWatcher {
+    pub fn new(sources: Sources) -> Self {
+        let files = sources
+            .files()
+            .map(|path| (path.clone(), modified(path)))
+            .collect();
+
+        SettingsWatcher {
+            sources,
+            files,
+            since_poll: 0.0,
+        }
+    }
+
+    /// Checks, at most every `POLL_INTERVAL` seconds, whether any settings
+    /// file has changed, and if so, loads all of them again. `dt` is the time
+    /// since this was last called.
+    pub fn poll(&mut self, dt: f64) -> Option<Result<Settings, Error>> {
+        self.since_poll += dt;
+        if self.since_poll < POLL_INTERVAL {
+            return None;
+        }
+        self.since_poll = 0.0;
+
+        let mut changed = false;
+        for (path, last_modified) in self.files.iter_mut() {
+            let modified = modified(path);
+            if modified != *last_modified {
+                *last_modified = modified;
+                changed = true;
+            }
+        }
+
+        if changed {
+            Some(self.sources.load())
+        } else {
+            None
+        }
+    }
+
+    /// Changes the setting with the given dotted key until the game is
+    /// closed, without saving it, and loads the settings again.
+    pub fn set_for_session(&mut self,
+                           key:   &str,
+                           value: Value) -> Result<Settings, Error>
+    {
+        let old_session = self.sources.session.clone();
+        set_dotted(&mut self.sources.session, key, value);
+
+        let loaded = self.sources.load();
+        if loaded.is_err() {
+            self.sources.session = old_session;
+        }
+
+        loaded
+    }
+
+    /// Sets the setting with the given dotted key in the project's settings
+    /// file, or in the user's if there's no project file, and loads the
+    /// settings again. If the new value doesn't load, the file is put back
+    /// the way it was.
+    pub fn write(&mut self,
+                 key:   &str,
+                 value: &Value) -> Result<Settings, Error>
+    {
+        let path = match self.sources.files().last() {
+            Some(path) => path.clone(),
+            None => user_settings_path().ok_or_else(|| {
+                err_msg("Nowhere to save settings to, since there's no \
+                         settings file and no config directory")
+            })?,
+        };
+        let old_contents = fs::read_to_string(&path).ok();
+        let is_new_file = self.files.is_empty();
+
+        write_setting(&path, key, value).with_context(|_| {
+            format!("Couldn't save settings to {}", path.display())
+        })?;
+        // Once saved, the setting shouldn't be stuck at whatever it was
+        // changed to for the session.
+        let old_session = self.sources.session.clone();
+        unset_dotted(&mut self.sources.session, key);
+        if is_new_file {
+            self.sources.user = Some(path.clone());
+            self.files.push((path.clone(), None));
+        }
+
+        let loaded = self.sources.load();
+        if loaded.is_err() {
+            // Failing to put the file back leaves it broken, but the error
+            // from loading is still the one worth reporting.
+            let _ = match old_contents {
+                Some(contents) => fs::write(&path, contents),
+                None => fs::remove_file(&path),
+            };
+            if is_new_file {
+                self.sources.user = None;
+                self.files.clear();
+            }
+            self.sources.session = old_session;
+        }
+
+        // This change has already been picked up, so `poll` shouldn't load
+        // the settings again because of it.
+        for (path, last_modified) in self.files.iter_mut() {
+            *last_modified = modified(path);
+        }
+
+        loaded
+    }
+}
+
+impl Input {
+    /// Every binding, along with the name of its key in the settings.
+    pub fn bindings(&self) -> [(&'static str, Key); 14] {
+        [
+            ("move_forward", self.move_forward),
+            ("move_backward", self.move_backward),
+            ("turn_left", self.turn_left),
+            ("turn_right", self.turn_right),
+            ("rotate_left", self.rotate_left),
+            ("rotate_right", self.rotate_right),
+            ("attack", self.attack),
+            ("zoom_in", self.zoom_in),
+            ("zoom_out", self.zoom_out),
+            ("quicksave", self.quicksave),
+            ("quickload", self.quickload),
+            ("options", self.options),
+            ("next_theme", self.next_theme),
+            ("debug_overlay", self.debug_overlay),
+        ]
+    }
+}
+
+impl Colors {
+    /// Reads the colours of the named theme, given the tables that it takes
+    /// its colours from (as from `theme_tables`).
+    fn read(r:     &Reader,
+            theme: &str,
+            chain: &[String]) -> Result<Self, SettingsError>
+    {
+        let color = |name| {
+            let key = chain
+                .iter()
+                .map(|table| format!("{}.{}", table, name))
+                .find(|key| r.get(key).is_ok())
+                .unwrap_or_else(|| format!("colors.{}", name));
+
+            r.color(&key)
+        };
+
+        Ok(Colors {
+            theme:                theme.to_owned(),
+            background_color:     color("background_color")?,
+            low_ground_color:     color("low_ground_color")?,
+            high_ground_color:    color("high_ground_color")?,
+            tile_outline_color:   color("tile_outline_color")?,
+            water_color:          color("water_color")?,
+            sand_color:           color("sand_color")?,
+            grass_color:          color("grass_color")?,
+            dirt_color:           color("dirt_color")?,
+            rock_color:           color("rock_color")?,
+            player_color:         color("player_color")?,
+            player_outline_color: color("player_outline_color")?,
+            npc_color:            color("npc_color")?,
+            npc_outline_color:    color("npc_outline_color")?,
+        })
+    }
+}
+
+impl Corner {
+    /// Top-left point of a box of the given size, tucked into this corner of
+    /// a viewport, `margin` pixels in from its edges.
+    pub fn place(self,
+                 size:     [f64; 2],
+                 margin:   f64,
+                 viewport: [f64; 2]) -> [f64; 2]
+    {
+        let right = viewport[0] - size[0] - margin;
+        let bottom = viewport[1] - size[1] - margin;
+
+        match self {
+            Corner::TopLeft => [margin, margin],
+            Corner::TopRight => [right, margin],
+            Corner::BottomLeft => [margin, bottom],
+            Corner::BottomRight => [right, bottom],
+        }
+    }
+}
+
+impl MotionEasing {
+    fn read(r: &Reader, section: &str) -> Result<Self, SettingsError> {
+        let key = |name| format!("{}.{}", section, name);
+
+        Ok(MotionEasing {
+            translation: r.easing(&key("translation_easing"))?,
+            rotation:    r.easing(&key("rotation_easing"))?,
+        })
+    }
+}
+
+impl<'a> Reader<'a> {
+    fn get(&self, key: &str) -> Result<&'a Value, SettingsError> {
+        let mut value = self.root;
+        for part in key.split('.') {
+            value = value.get(part).ok_or_else(|| {
+                SettingsError::MissingDefault { key: key.to_owned() }
+            })?;
+        }
+
+        Ok(value)
+    }
+
+    /// Error for when the value of `key` is no good, for the given reason.
+    fn invalid<R: Display>(&self, key: &str, reason: R) -> SettingsError {
+        SettingsError::InvalidValue {
+            key:    key.to_owned(),
+            value:  self.get(key).map(Value::to_string).unwrap_or_default(),
+            reason: reason.to_string(),
+        }
+    }
+
+    fn boolean(&self, key: &str) -> Result<bool, SettingsError> {
+        self.get(key)?
+            .as_bool()
+            .ok_or_else(|| self.invalid(key, "is not a boolean"))
+    }
+
+    fn int(&self,
+           key: &str,
+           min: i64,
+           max: i64) -> Result<i64, SettingsError>
+    {
+        let i = self
+            .get(key)?
+            .as_integer()
+            .ok_or_else(|| self.invalid(key, "is not an integer"))?;
+
+        if i >= min && i <= max {
+            Ok(i)
+        } else {
+            let reason = format!("is not between {} and {}", min, max);
+            Err(self.invalid(key, reason))
+        }
+    }
+
+    /// Reads a float, accepting integers too so that e.g. `zoom = 2` works.
+    fn float(&self,
+             key: &str,
+             min: f64,
+             max: f64) -> Result<f64, SettingsError>
+    {
+        let f = match *self.get(key)? {
+            Value::Float(f) => f,
+            Value::Integer(i) => i as f64,
+            _ => return Err(self.invalid(key, "is not a number")),
+        };
+
+        // Written this way around so that NaN is rejected too.
+        if f >= min && f <= max {
+            Ok(f)
+        } else {
+            let reason = format!("is not between {} and {}", min, max);
+            Err(self.invalid(key, reason))
+        }
+    }
+
+    fn string(&self, key: &str) -> Result<&'a str, SettingsError> {
+        self.get(key)?
+            .as_str()
+            .ok_or_else(|| self.invalid(key, "is not a string"))
+    }
+
+    fn samples(&self, key: &str) -> Result<u8, SettingsError> {
+        match self.int(key, 0, 16)? {
+            s @ 0 | s @ 1 | s @ 2 | s @ 4 | s @ 8 | s @ 16 => Ok(s as u8),
+            _ => Err(self.invalid(key, "is not one of 0, 1, 2, 4, 8, or 16")),
+        }
+    }
+
+    fn color(&self, key: &str) -> Result<Color, SettingsError> {
+        parse_color(self.string(key)?)
+            .map_err(|e| self.invalid(key, format!("is malformed: {}", e)))
+    }
+
+    fn easing(&self, key: &str) -> Result<Easing, SettingsError> {
+        self.string(key)?
+            .parse()
+            .map_err(|e| self.invalid(key, format!("is malformed: {}", e)))
+    }
+
+    fn key(&self, key: &str) -> Result<Key, SettingsError> {
+        key_from_name(self.string(key)?)
+            .ok_or_else(|| self.invalid(key, "is not the name of a key"))
+    }
+
+    fn corner(&self, key: &str) -> Result<Corner, SettingsError> {
+        match self.string(key)? {
+            "top_left" => Ok(Corner::TopLeft),
+            "top_right" => Ok(Corner::TopRight),
+            "bottom_left" => Ok(Corner::BottomLeft),
+            "bottom_right" => Ok(Corner::BottomRight),
+            _ => Err(self.invalid(
+                key,
+                "is not one of \"top_left\", \"top_right\", \"bottom_left\", \
+                 or \"bottom_right\"",
+            )),
+        }
+    }
+}
+
+
+/// Merges `overrides` into `base`, recursing into tables, and noting down
+/// that every value set came from `origin`. Keys that `base` doesn't have are
+/// skipped with a warning, since they're most likely typos.
+fn merge(base:      &mut Table,
+         overrides: Table,
+         prefix:    &str,
+         origin:    &Origin,
+         origins:   &mut BTreeMap<String, Origin>,
+         warnings:  &mut Vec<String>) -> Result<(), SettingsError>
+{
+    for (name, value) in overrides {
+        let key = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+
+        match (base.get_mut(&name), value) {
+            // Themes can be made up, so anything goes in them, and they're
+            // checked once everything is merged.
+            (None, value) if key.starts_with("themes.") => {
+                let mut leaves = Vec::new();
+                match value {
+                    Value::Table(ref t) => leaves_of(t, &key, &mut leaves),
+                    _ => leaves.push((key.clone(), String::new())),
+                }
+                for (leaf, _) in leaves {
+                    origins.insert(leaf, origin.clone());
+                }
+
+                base.insert(name, value);
+            },
+            (None, _) => warnings.push(format!(
+                "Unknown setting `{}` from {} was ignored",
+                key,
+                origin,
+            )),
+            (Some(Value::Table(base)), Value::Table(overrides)) =>
+                merge(base, overrides, &key, origin, origins, warnings)?,
+            (Some(Value::Table(_)), value) =>
+                return Err(SettingsError::InvalidValue {
+                    key,
+                    value:  value.to_string(),
+                    reason: "is not a table".to_owned(),
+                }),
+            (Some(base), value) => {
+                *base = value;
+                origins.insert(key, origin.clone());
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Collects the dotted key and value of every non-table value in `table`.
+fn leaves_of(table: &Table, prefix: &str, leaves: &mut Vec<(String, String)>) {
+    for (name, value) in table.iter() {
+        let key = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+
+        match *value {
+            Value::Table(ref t) => leaves_of(t, &key, leaves),
+            ref v => leaves.push((key, v.to_string())),
+        }
+    }
+}
+
+/// The tables that the named theme takes its colours from, most specific
+/// first: the theme's own, then those of the themes that it inherits from in
+/// turn, and finally `colors`.
+fn theme_tables(r:     &Reader,
+                theme: &str) -> Result<Vec<String>, SettingsError>
+{
+    let mut chain = Vec::new();
+    // The setting that named the theme currently being looked at.
+    let mut named_by = "colors.theme".to_owned();
+    let mut theme = theme.to_owned();
+
+    while theme != DEFAULT_THEME {
+        let table = format!("themes.{}", theme);
+        if r.get(&table).is_err() {
+            return Err(r.invalid(&named_by, "is not the name of a theme"));
+        }
+        if chain.contains(&table) {
+            return Err(r.invalid(&named_by, "makes themes inherit in a loop"));
+        }
+
+        named_by = format!("{}.inherits", table);
+        theme = match r.get(&named_by) {
+            Ok(_) => r.string(&named_by)?.to_owned(),
+            Err(_) => DEFAULT_THEME.to_owned(),
+        };
+        chain.push(table);
+    }
+    chain.push("colors".to_owned());
+
+    Ok(chain)
+}
+
+fn read_table(path: &Path) -> Result<Table, Error> {
+    let mut settings_file = File::open(path)?;
+    let mut contents = String::new();
+    settings_file.read_to_string(&mut contents)?;
+
+    match contents.parse()? {
+        Value::Table(t) => Ok(t),
+        _ => Err(SettingsError::NotATable.into()),
+    }
+}
+
+/// Where the user's settings file goes, following the XDG base directory
+/// spec, and falling back to `%APPDATA%` on Windows.
+fn user_settings_path() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|h| Path::new(&h).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .map(|dir| dir.join("ahistorics").join("settings.toml"))
+}
+
+/// Settings given by environment variables, as the name of the variable
+/// along with the dotted key and the value that it sets, sorted by name.
+fn env_settings(warnings: &mut Vec<String>) -> Vec<(String, (String, Value))> {
+    let mut settings: Vec<_> = env::vars_os()
+        .filter_map(|(name, value)| {
+            Some((name.into_string().ok()?, value.into_string().ok()?))
+        })
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+        .filter_map(|(name, value)| {
+            let parts: Vec<_> = name[ENV_PREFIX.len()..]
+                .split("__")
+                .map(str::to_lowercase)
+                .collect();
+            if parts.iter().any(String::is_empty) {
+                warnings.push(format!(
+                    "${} doesn't name a setting; it should look like \
+                     {}SECTION__KEY",
+                    name,
+                    ENV_PREFIX,
+                ));
+                return None;
+            }
+
+            let key = parts.join(".");
+            Some((name, (key, parse_value(&value))))
         })
+        .collect();
+    settings.sort_by(|a, b| a.0.cmp(&b.0));
+
+    settings
+}
+
+/// Parses a value given outside of a settings file (e.g. on the command
+/// line) as TOML, treating anything that isn't valid TOML as a bare string,
+/// so that e.g. `red` works without any quoting.
+pub fn parse_value(raw: &str) -> Value {
+    format!("value = {}", raw)
+        .parse::<Value>()
+        .ok()
+        .and_then(|mut v| v.as_table_mut().and_then(|t| t.remove("value")))
+        .unwrap_or_else(|| Value::String(raw.to_owned()))
+}
+
+/// Sets the value at a dotted key like `"graphics.zoom"`, creating tables
+/// along the way as needed.
+pub fn set_dotted(table: &mut Table, key: &str, value: Value) {
+    let dot = match key.find('.') {
+        Some(dot) => dot,
+        None => {
+            table.insert(key.to_owned(), value);
+            return;
+        },
+    };
+
+    let entry = table
+        .entry(key[..dot].to_owned())
+        .or_insert_with(|| Value::Table(Table::new()));
+    if !entry.is_table() {
+        *entry = Value::Table(Table::new());
     }
+    if let Value::Table(ref mut inner) = *entry {
+        set_dotted(inner, &key[dot + 1..], value);
+    }
+}
+
+/// Removes the value at a dotted key, if there is one, leaving any tables
+/// along the way.
+fn unset_dotted(table: &mut Table, key: &str) {
+    match key.find('.') {
+        Some(dot) => {
+            if let Some(Value::Table(inner)) = table.get_mut(&key[..dot]) {
+                unset_dotted(inner, &key[dot + 1..]);
+            }
+        },
+        None => {
+            table.remove(key);
+        },
+    }
+}
+
+/// Looks for a file with the same name as `path` in `path`'s directory, and
+/// then in each of that directory's ancestors, returning the first found.
+pub fn find_upwards<P>(path: P) -> Result<PathBuf, SettingsError>
+    where P: AsRef<Path>
+{
+    let path = path.as_ref();
+    if path.is_file() {
+        return Ok(path.to_owned());
+    }
+
+    let filename = path.file_name().ok_or(SettingsError::BadFilePath {
+        path: path.to_owned(),
+    })?;
+    let not_found = || SettingsError::FileNotFound {
+        filename: filename.to_owned(),
+    };
+
+    let dir = path.parent().ok_or_else(not_found)?;
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let canonical_dir = dir.canonicalize().map_err(|_| not_found())?;
+
+    canonical_dir
+        .ancestors()
+        .skip(1)
+        .map(|d| d.join(filename))
+        .find(|p| p.is_file())
+        .ok_or_else(not_found)
 }
 
-pub fn hex_to_color(hex_str: &str) -> Result<Color, Error> {
-    let parsed_int = u32::from_str_radix(&hex_str[1..], 16)?;
-    if parsed_int > 0xFF_FF_FF {
-        let r = parsed_int >> 24 & 0xFF;
-        let g = parsed_int >> 16 & 0xFF;
-        let b = parsed_int >> 8  & 0xFF;
-        let a = parsed_int       & 0xFF;
-
-        Ok([r as f32 / 255.0,
-            g as f32 / 255.0,
-            b as f32 / 255.0,
-            a as f32 / 255.0])
-    } else {
-        let r = parsed_int >> 16 & 0xFF;
-        let g = parsed_int >> 8  & 0xFF;
-        let b = parsed_int       & 0xFF;
-
-        Ok([r as f32 / 255.0,
-            g as f32 / 255.0,
-            b as f32 / 255.0,
-            1.0])
+/// When the given file was last modified, if it can be told.
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Looks up a key by the name of its variant in piston's `Key`, ignoring
+/// case.
+pub fn key_from_name(name: &str) -> Option<Key> {
+    let key = match name.to_lowercase().as_str() {
+        "a" => Key::A,
+        "b" => Key::B,
+        "c" => Key::C,
+        "d" => Key::D,
+        "e" => Key::E,
+        "f" => Key::F,
+        "g" => Key::G,
+        "h" => Key::H,
+        "i" => Key::I,
+        "j" => Key::J,
+        "k" => Key::K,
+        "l" => Key::L,
+        "m" => Key::M,
+        "n" => Key::N,
+        "o" => Key::O,
+        "p" => Key::P,
+        "q" => Key::Q,
+        "r" => Key::R,
+        "s" => Key::S,
+        "t" => Key::T,
+        "u" => Key::U,
+        "v" => Key::V,
+        "w" => Key::W,
+        "x" => Key::X,
+        "y" => Key::Y,
+        "z" => Key::Z,
+        "d0" => Key::D0,
+        "d1" => Key::D1,
+        "d2" => Key::D2,
+        "d3" => Key::D3,
+        "d4" => Key::D4,
+        "d5" => Key::D5,
+        "d6" => Key::D6,
+        "d7" => Key::D7,
+        "d8" => Key::D8,
+        "d9" => Key::D9,
+        "f1" => Key::F1,
+        "f2" => Key::F2,
+        "f3" => Key::F3,
+        "f4" => Key::F4,
+        "f5" => Key::F5,
+        "f6" => Key::F6,
+        "f7" => Key::F7,
+        "f8" => Key::F8,
+        "f9" => Key::F9,
+        "f10" => Key::F10,
+        "f11" => Key::F11,
+        "f12" => Key::F12,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "space" => Key::Space,
+        "return" => Key::Return,
+        "tab" => Key::Tab,
+        "backspace" => Key::Backspace,
+        "lshift" => Key::LShift,
+        "rshift" => Key::RShift,
+        "lctrl" => Key::LCtrl,
+        "rctrl" => Key::RCtrl,
+        "lalt" => Key::LAlt,
+        "ralt" => Key::RAlt,
+        "minus" => Key::Minus,
+        "equals" => Key::Equals,
+        "comma" => Key::Comma,
+        "period" => Key::Period,
+        "slash" => Key::Slash,
+        "backslash" => Key::Backslash,
+        "leftbracket" => Key::LeftBracket,
+        "rightbracket" => Key::RightBracket,
+        "semicolon" => Key::Semicolon,
+        "quote" => Key::Quote,
+        "backquote" => Key::Backquote,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        "insert" => Key::Insert,
+        "delete" => Key::Delete,
+        "numpadplus" => Key::NumPadPlus,
+        "numpadminus" => Key::NumPadMinus,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The defaults with each of `layers` (as the contents of a settings
+    /// file) merged over them in turn, without looking at the environment.
+    fn load(layers: &[&str]) -> Result<Settings, Error> {
+        let mut merged = match DEFAULT_SETTINGS.parse()? {
+            Value::Table(t) => t,
+            _ => return Err(SettingsError::NotATable.into()),
+        };
+        let mut origins = BTreeMap::new();
+        let mut warnings = Vec::new();
+
+        for layer in layers {
+            let table = match layer.parse()? {
+                Value::Table(t) => t,
+                _ => return Err(SettingsError::NotATable.into()),
+            };
+            let origin = Origin::File(PathBuf::from("test.toml"));
+            merge(
+                &mut merged,
+                table,
+                "",
+                &origin,
+                &mut origins,
+                &mut warnings,
+            )?;
+        }
+
+        Settings::read(merged, origins, warnings)
+    }
+
+    #[test]
+    fn partial_file_keeps_other_defaults() {
+        let settings = load(&["[graphics]\nzoom = 2\n"]).unwrap();
+
+        assert_eq!(settings.graphics.zoom, 2.0);
+        assert_eq!(settings.graphics.width, 1366);
+        assert_eq!(
+            *settings.origin("graphics.zoom"),
+            Origin::File(PathBuf::from("test.toml")),
+        );
+        assert_eq!(*settings.origin("graphics.width"), Origin::Default);
+        assert!(settings.warnings.is_empty());
+    }
+
+    #[test]
+    fn unknown_key_is_a_warning() {
+        let settings = load(&["[graphics]\nzoon = 2.0\n"]).unwrap();
+
+        assert_eq!(settings.graphics.zoom, 1.0);
+        assert_eq!(settings.warnings.len(), 1);
+        assert!(settings.warnings[0].contains("`graphics.zoon`"));
+    }
+
+    #[test]
+    fn out_of_range_value_is_an_error() {
+        let error = load(&["[graphics]\nwidth = 0\n"]).unwrap_err();
+
+        match error.downcast::<SettingsError>() {
+            Ok(SettingsError::InvalidValue { key, value, .. }) => {
+                assert_eq!(key, "graphics.width");
+                assert_eq!(value, "0");
+            },
+            other => panic!("expected an invalid value, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn theme_inherits_through_two_levels() {
+        let settings = load(&[r#"
+            [colors]
+            theme = "child"
+
+            [themes.parent]
+            npc_color = "red"
+            player_color = "red"
+
+            [themes.child]
+            inherits = "parent"
+            player_color = "blue"
+        "#]).unwrap();
+        let color = |s| parse_color(s).unwrap();
+
+        assert_eq!(settings.colors.player_color, color("blue"));
+        assert_eq!(settings.colors.npc_color, color("red"));
+        assert_eq!(settings.colors.background_color, color("#4f7069"));
+    }
+
+    #[test]
+    fn theme_inheriting_in_a_loop_is_an_error() {
+        let error = load(&[r#"
+            [colors]
+            theme = "a"
+
+            [themes.a]
+            inherits = "b"
+
+            [themes.b]
+            inherits = "a"
+        "#]).unwrap_err();
+
+        match error.downcast::<SettingsError>() {
+            Ok(SettingsError::InvalidValue { key, reason, .. }) => {
+                assert_eq!(key, "themes.b.inherits");
+                assert!(reason.contains("loop"));
+            },
+            other => panic!("expected an invalid value, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn environment_comes_between_files_and_command_line() {
+        let path = env::temp_dir().join(format!(
+            "ahistorics-settings-test-{}.toml",
+            std::process::id(),
+        ));
+        fs::write(&path, "[graphics]\nwidth = 800\nheight = 500\nzoom = 2\n")
+            .unwrap();
+        env::set_var("AHISTORICS_GRAPHICS__HEIGHT", "600");
+        env::set_var("AHISTORICS_GRAPHICS__ZOOM", "3");
+        env::set_var("AHISTORICS_GRAPHICS__", "1");
+
+        let mut overrides = Table::new();
+        set_dotted(&mut overrides, "graphics.zoom", parse_value("1.5"));
+        let sources = Sources {
+            user: None,
+            project: Some(path.clone()),
+            overrides,
+            session: Table::new(),
+        };
+        let settings = sources.load();
+
+        env::remove_var("AHISTORICS_GRAPHICS__HEIGHT");
+        env::remove_var("AHISTORICS_GRAPHICS__ZOOM");
+        env::remove_var("AHISTORICS_GRAPHICS__");
+        fs::remove_file(&path).unwrap();
+        let settings = settings.unwrap();
+
+        assert_eq!(settings.graphics.width, 800);
+        assert_eq!(*settings.origin("graphics.width"), Origin::File(path));
+        assert_eq!(settings.graphics.height, 600);
+        assert_eq!(
+            *settings.origin("graphics.height"),
+            Origin::Environment("AHISTORICS_GRAPHICS__HEIGHT".to_owned()),
+        );
+        assert_eq!(settings.graphics.zoom, 1.5);
+        assert_eq!(*settings.origin("graphics.zoom"), Origin::CommandLine);
+        assert!(settings
+            .warnings
+            .iter()
+            .any(|w| w.contains("$AHISTORICS_GRAPHICS__ ")));
     }
 }
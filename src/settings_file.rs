@@ -0,0 +1,161 @@
+//! Editing of settings files in place.
+//!
+//! Rather than serializing the settings from scratch, which would throw away
+//! the user's comments and ordering, settings files are edited line by line:
+//! the line holding the setting has just its value swapped out, and settings
+//! that aren't in the file yet are added to the end of their table. Only
+//! values that fit on one line are understood, which is all of them for the
+//! settings that the game itself writes.
+
+use failure::Error;
+use std::{fs, io, path::Path};
+use toml::Value;
+
+
+/// Sets `key` (a dotted key like `"graphics.zoom"`) to `value` in the given
+/// file, creating the file if it doesn't exist yet.
+pub fn write_setting(path:  &Path,
+                     key:   &str,
+                     value: &Value) -> Result<(), Error>
+{
+    let document = match fs::read_to_string(path) {
+        Ok(document) => document,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, set_value(&document, key, value))?;
+
+    Ok(())
+}
+
+/// Returns `document` with `key` (a dotted key like `"graphics.zoom"`) set to
+/// `value`, leaving everything else as it was.
+pub fn set_value(document: &str, key: &str, value: &Value) -> String {
+    let mut lines: Vec<String> = document.lines().map(str::to_owned).collect();
+    let (table, name) = match key.rfind('.') {
+        Some(dot) => (&key[..dot], &key[dot + 1..]),
+        None => ("", key),
+    };
+
+    // The header of the table that each line is in, as a dotted key.
+    let mut current = String::new();
+    // Where to add the setting if it isn't there already: just after the
+    // last key/value pair in its table.
+    let mut insert_at = None;
+
+    for (i, line) in lines.iter_mut().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            current = parse_header(trimmed).unwrap_or_default();
+            if current == table {
+                insert_at = Some(i + 1);
+            }
+            continue;
+        }
+
+        let eq = match find_unquoted(trimmed, '=') {
+            Some(eq) if !trimmed.starts_with('#') => eq,
+            _ => continue,
+        };
+
+        let line_key = normalize_key(&trimmed[..eq]);
+        let full_key = if current.is_empty() {
+            line_key
+        } else {
+            format!("{}.{}", current, line_key)
+        };
+
+        if full_key == key {
+            *line = replace_value(line, value);
+            return join(&lines);
+        }
+        if current == table {
+            insert_at = Some(i + 1);
+        }
+    }
+
+    let new_line = format!("{} = {}", name, value);
+    match insert_at {
+        Some(i) => lines.insert(i, new_line),
+        // Keys outside of any table have to come before the first table.
+        None if table.is_empty() => lines.insert(0, new_line),
+        None => {
+            if lines.last().map_or(false, |l| !l.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push(format!("[{}]", table));
+            lines.push(new_line);
+        },
+    }
+
+    join(&lines)
+}
+
+
+fn join(lines: &[String]) -> String {
+    let mut document = lines.join("\n");
+    document.push('\n');
+
+    document
+}
+
+/// Parses a table header like `[animation.player]` into a dotted key.
+/// Headers of arrays of tables (`[[...]]`) aren't tables that settings can
+/// be put in, so they come out as `None`.
+fn parse_header(line: &str) -> Option<String> {
+    if line.starts_with("[[") {
+        return None;
+    }
+
+    let end = find_unquoted(line, ']')?;
+
+    Some(normalize_key(&line[1..end]))
+}
+
+/// Strips any whitespace around the parts of a dotted key, and any quotes
+/// around parts that don't need them.
+fn normalize_key(key: &str) -> String {
+    key.split('.')
+        .map(|part| part.trim().trim_matches('"'))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Swaps out the value of a key/value line, keeping its indentation, the key
+/// as it was written, and any trailing comment.
+fn replace_value(line: &str, value: &Value) -> String {
+    let eq = find_unquoted(line, '=').unwrap_or(line.len());
+    let after_eq = &line[eq + 1..];
+    let comment = find_unquoted(after_eq, '#').map(|hash| {
+        // Keep the spacing before the comment too.
+        let value_end = after_eq[..hash].trim_end().len();
+        &after_eq[value_end..]
+    });
+
+    format!("{}= {}{}", &line[..eq], value, comment.unwrap_or(""))
+}
+
+/// Finds the first `target` that isn't inside of a string.
+fn find_unquoted(s: &str, target: char) -> Option<usize> {
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (i, c) in s.char_indices() {
+        match quote {
+            // Only basic strings (`"..."`) have escapes.
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == target => return Some(i),
+            None => (),
+        }
+    }
+
+    None
+}
@@ -0,0 +1,297 @@
+//! Textured sprites: sprite sheets cut up into named frames, animations made
+//! out of those frames for entities, and textures for the tops of tiles.
+
+use assets::{read_file, Assets, Handle};
+use failure::{Error, ResultExt};
+use fnv::FnvHashMap as Map;
+use geometry::{HEXAGON_POLY, SQRT_3};
+use graphics::{
+    draw_state::DrawState,
+    math::{transform_pos, Matrix2d, Vec2d},
+    types::{Color, SourceRectangle},
+    Graphics,
+    Image,
+    ImageSize,
+};
+use map_data::Terrain;
+use std::path::Path;
+use toml;
+use transitioned_grid_pos::Motion;
+
+
+/// Width of entity sprites, as a fraction of the size of a hex.
+pub const SPRITE_WIDTH: f64 = 0.75;
+
+/// Indices into `HEXAGON_POLY` of the corners of the four triangles that
+/// fill in a hexagon.
+pub const HEXAGON_FAN: [usize; 12] = [0, 1, 2, 0, 2, 3, 0, 3, 4, 0, 4, 5];
+
+/// The animation that every sprite sheet has, and that stands in for those
+/// that it doesn't.
+const IDLE: &str = "idle";
+
+
+
+/// Every sprite and texture in the game, with textures of type `T`.
+pub struct Sprites<T> {
+    pub player: Handle<SpriteSheet<T>>,
+    pub npc:    Handle<SpriteSheet<T>>,
+    /// Textures for the tops of tiles. Tiles without one are drawn plain.
+    pub tiles:  TileTextures<T>,
+}
+
+pub type TileTextures<T> = Map<Terrain, Handle<T>>;
+
+/// One image, cut up into frames that are strung together into animations.
+pub struct SpriteSheet<T> {
+    texture:    Handle<T>,
+    /// By name, which is one of `IDLE`, "walk", or "turn".
+    animations: Map<String, Animation>,
+}
+
+#[derive(Clone, Debug)]
+struct Animation {
+    /// Parts of the image that each frame is made of, in pixels.
+    frames:     Vec<SourceRectangle>,
+    /// Seconds that each frame is shown for.
+    frame_time: f64,
+    /// Whether this stops on the last frame, rather than starting over.
+    once:       bool,
+}
+
+/// Which animation an entity is playing, and how far through it.
+#[derive(Clone, Copy, Debug)]
+pub struct AnimationState {
+    motion:  Motion,
+    /// Seconds since the animation started.
+    elapsed: f64,
+}
+
+#[derive(Debug, Fail)]
+pub enum SpriteError {
+    #[fail(display = "There's no frame named {:?}", name)]
+    MissingFrame {
+        name: String,
+    },
+    #[fail(display = "There's no {:?} animation", name)]
+    MissingAnimation {
+        name: String,
+    },
+    #[fail(display = "The {:?} animation has no frames", name)]
+    NoFrames {
+        name: String,
+    },
+    #[fail(display = "The {:?} animation's frame_time isn't positive", name)]
+    BadFrameTime {
+        name: String,
+    },
+}
+
+/// The file that `sprites.path` points to.
+#[derive(Deserialize)]
+struct SpritesFile {
+    /// Ids of sprite sheets in the asset manifest.
+    player: String,
+    npc:    String,
+    #[serde(default)]
+    tiles:  Vec<TileTextureFile>,
+}
+
+#[derive(Deserialize)]
+struct TileTextureFile {
+    terrain: Terrain,
+    /// Id of the texture in the asset manifest.
+    texture: String,
+}
+
+#[derive(Deserialize)]
+struct SheetFile {
+    /// Id of the texture in the asset manifest.
+    texture:    String,
+    /// As `[x, y, width, height]` in pixels of the image.
+    frames:     Map<String, SourceRectangle>,
+    animations: Map<String, AnimationFile>,
+}
+
+#[derive(Deserialize)]
+struct AnimationFile {
+    /// Names of frames.
+    frames:     Vec<String>,
+    frame_time: f64,
+    #[serde(default)]
+    once:       bool,
+}
+
+
+impl<T> Sprites<T> {
+    /// Loads the sprites that the file at `path` names, out of `assets`.
+    pub fn load(path: &Path, assets: &mut Assets<T>) -> Result<Self, Error> {
+        let file: SpritesFile = toml::from_str(&read_file(path)?)
+            .with_context(|_| format!("Couldn't load {}", path.display()))?;
+
+        let mut tiles = Map::default();
+        for tile in file.tiles {
+            tiles.insert(tile.terrain, assets.texture(&tile.texture)?);
+        }
+
+        Ok(Sprites {
+            player: assets.sheet(&file.player)?,
+            npc:    assets.sheet(&file.npc)?,
+            tiles,
+        })
+    }
+}
+
+impl<T> SpriteSheet<T> {
+    /// Loads the sheet described by the file at `path`, getting the texture
+    /// that it names from `texture`.
+    pub fn load<F>(path: &Path, texture: F) -> Result<Self, Error>
+        where F: FnOnce(&str) -> Result<Handle<T>, Error>
+    {
+        let context = || format!("Couldn't load {}", path.display());
+        let file: SheetFile = toml::from_str(&read_file(path)?)
+            .with_context(|_| context())?;
+        let animations = animations(&file.frames, file.animations)
+            .with_context(|_| context())?;
+
+        Ok(SpriteSheet {
+            texture: texture(&file.texture).with_context(|_| context())?,
+            animations,
+        })
+    }
+
+    /// The animation played for the given motion.
+    fn animation(&self, motion: Motion) -> &Animation {
+        let name = match motion {
+            Motion::Idle => IDLE,
+            Motion::Walking => "walk",
+            Motion::Turning => "turn",
+        };
+
+        self.animations
+            .get(name)
+            .unwrap_or_else(|| &self.animations[IDLE])
+    }
+}
+
+impl<T: ImageSize> SpriteSheet<T> {
+    /// Draws the frame that `state` is up to, `width` pixels wide and
+    /// centered on the origin, tinted with `color`.
+    pub fn draw<G>(&self,
+                   state:      &AnimationState,
+                   width:      f64,
+                   color:      Color,
+                   draw_state: &DrawState,
+                   transform:  Matrix2d,
+                   g:          &mut G)
+        where G: Graphics<Texture=T>
+    {
+        let frame = self.animation(state.motion).frame_at(state.elapsed);
+        let height = width * frame[3] / frame[2].max(1.0);
+
+        Image::new_color(color)
+            .src_rect(frame)
+            .rect([-width / 2.0, -height / 2.0, width, height])
+            .draw(&*self.texture, draw_state, transform, g);
+    }
+}
+
+impl Animation {
+    fn frame_at(&self, elapsed: f64) -> SourceRectangle {
+        let count = self.frames.len();
+        let i = (elapsed / self.frame_time).max(0.0) as usize;
+        let i = if self.once { i.min(count - 1) } else { i % count };
+
+        self.frames[i]
+    }
+}
+
+impl AnimationState {
+    /// Moves the animation along, starting over with a different one if the
+    /// motion has changed.
+    pub fn step(&mut self, motion: Motion, dt: f64) {
+        if motion == self.motion {
+            self.elapsed += dt;
+        } else {
+            self.motion = motion;
+            self.elapsed = 0.0;
+        }
+    }
+}
+
+impl Default for AnimationState {
+    fn default() -> Self {
+        AnimationState {
+            motion:  Motion::Idle,
+            elapsed: 0.0,
+        }
+    }
+}
+
+
+/// Texture coordinates that go with the corners picked out by `HEXAGON_FAN`,
+/// such that the hexagon is cut out of the middle of the texture.
+pub fn hexagon_uvs() -> [[f32; 2]; 12] {
+    let mut uvs = [[0.0; 2]; 12];
+    for (uv, &k) in uvs.iter_mut().zip(HEXAGON_FAN.iter()) {
+        let [x, y] = HEXAGON_POLY[k];
+        *uv = [((x + 1.0) / 2.0) as f32, (y / SQRT_3 + 0.5) as f32];
+    }
+
+    uvs
+}
+
+/// Draws a texture clipped to the shape of the hexagon with the given
+/// corners, which are in the same order as `HEXAGON_POLY`, tinted with
+/// `color`.
+pub fn draw_hexagon<G: Graphics>(texture:    &G::Texture,
+                                 color:      Color,
+                                 corners:    &[Vec2d],
+                                 draw_state: &DrawState,
+                                 transform:  Matrix2d,
+                                 g:          &mut G)
+{
+    let mut vertices = [[0.0; 2]; 12];
+    for (vertex, &k) in vertices.iter_mut().zip(HEXAGON_FAN.iter()) {
+        let [x, y] = transform_pos(transform, corners[k]);
+        *vertex = [x as f32, y as f32];
+    }
+
+    g.tri_list_uv(draw_state, &color, texture, |f| {
+        f(&vertices, &hexagon_uvs())
+    });
+}
+
+/// Puts together each animation out of the frames that it names.
+fn animations(frames: &Map<String, SourceRectangle>,
+              files:  Map<String, AnimationFile>)
+    -> Result<Map<String, Animation>, SpriteError>
+{
+    let mut animations = Map::default();
+    for (name, file) in files {
+        let frames = file
+            .frames
+            .iter()
+            .map(|frame| frames.get(frame).cloned().ok_or_else(|| {
+                SpriteError::MissingFrame { name: frame.clone() }
+            }))
+            .collect::<Result<Vec<_>, _>>()?;
+        if frames.is_empty() {
+            return Err(SpriteError::NoFrames { name });
+        }
+        if file.frame_time.is_nan() || file.frame_time <= 0.0 {
+            return Err(SpriteError::BadFrameTime { name });
+        }
+
+        animations.insert(name, Animation {
+            frames,
+            frame_time: file.frame_time,
+            once:       file.once,
+        });
+    }
+    if !animations.contains_key(IDLE) {
+        return Err(SpriteError::MissingAnimation { name: IDLE.to_owned() });
+    }
+
+    Ok(animations)
+}
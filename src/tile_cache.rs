@@ -0,0 +1,376 @@
+//! Geometry of the tiles of flat maps, worked out a chunk at a time and kept
+//! until the map changes, so that drawing it only takes lighting each batch
+//! of tiles and handing the lot over to be drawn.
+//!
+//! Chunks are kept in hex grid units, unlit, with tiles that look the same
+//! grouped together, so moving, turning, zooming or shaking the camera
+//! never calls for building them again. Each group is lit as a whole, and
+//! every vertex is taken onto the screen by the same transformation as it's
+//! handed over to the back end.
+//!
+//! Only flat maps are batched: elevated maps are always drawn tile by tile.
+
+use camera::Camera;
+use fnv::FnvHashMap as Map;
+use geometry::SQRT_3;
+use graphics::{
+    math::{sub, Vec2d},
+    types::Color,
+    Context,
+    Graphics,
+    BACK_END_MAX_VERTEX_COUNT,
+};
+use map_data::{Terrain, TileStyle, Visibility, OUTLINE_WIDTH};
+use matrix::{m, rot, Matrix};
+use positioned::Positioned;
+use sprite::{hexagon_uvs, TileTextures, HEXAGON_FAN};
+use std::ops::Range;
+
+
+/// Side length, in hexes, of each chunk. Chunks are only left out whole, so
+/// smaller chunks waste fewer draw calls on the colours of tiles that are
+/// just off the screen.
+pub const CHUNK_SIZE: usize = 4;
+
+/// Each hexagon is filled in with four triangles.
+const FILL_VERTICES: usize = 12;
+
+/// Most vertices of textured hexagons that the back end can take at a time,
+/// such that each part has a whole number of hexagons.
+const TEXTURED_PART: usize =
+    BACK_END_MAX_VERTEX_COUNT / FILL_VERTICES * FILL_VERTICES;
+
+
+#[derive(Clone, Default)]
+pub struct TileCache {
+    /// Chunks by their column and row, in units of `CHUNK_SIZE` hexes.
+    chunks: Map<[usize; 2], Chunk>,
+}
+
+/// The top of a tile to be drawn: its corners, in hex grid units, and how
+/// it looks before it's lit.
+pub type Hexagon = ([Vec2d; 6], Top);
+
+/// How the top of a tile looks before it's lit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Top {
+    pub color:      Color,
+    /// Which way, and how steeply, the top faces, in hex grid units. Once
+    /// turned the same way as the camera, it's lit as for
+    /// `TileStyle::brightness`.
+    pub facing:     Vec2d,
+    pub visibility: Visibility,
+    /// Terrain whose texture, if any, the top is filled in with.
+    pub terrain:    Terrain,
+}
+
+/// Index into the palette of the colour of a fill, and the terrain whose
+/// texture it's filled in with.
+type FillKey = (usize, Terrain);
+
+/// The tiles of a chunk, in hex grid units.
+#[derive(Clone, Default)]
+struct Chunk {
+    /// Triangles making up the tops of the tiles, grouped by how the tops
+    /// look.
+    fills:    Vec<(Top, Vec<[f32; 2]>)>,
+    /// Triangles making up the edges around the tops of the tiles, grouped
+    /// by visibility. Each vertex is a point on an edge, followed by the
+    /// unit vector across the edge that it's pushed out along, by half the
+    /// width of the outline.
+    outlines: Vec<(Visibility, Vec<[f32; 4]>)>,
+}
+
+/// Every colour that a frame's batches use.
+#[derive(Clone, Default)]
+struct Palette {
+    colors: Vec<Color>,
+    /// Colours that look the same on a display with 8 bits per channel
+    /// share an index.
+    index:  Map<[u8; 4], usize>,
+}
+
+
+impl TileCache {
+    /// Forgets every chunk.
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+    }
+
+    /// Forgets the chunk that the hex at the given rectangular coordinates
+    /// is in.
+    pub fn invalidate(&mut self, x: usize, y: usize) {
+        self.chunks.remove(&[x / CHUNK_SIZE, y / CHUNK_SIZE]);
+    }
+
+    /// Draws every chunk on screen with hexes in the given ranges of
+    /// rectangular coordinates, building those that aren't cached by calling
+    /// `build` with the ranges that the chunk covers. Chunks out of range
+    /// are forgotten, so that only those near the screen take up memory.
+    ///
+    /// Triangles of the same colour are drawn together, whichever chunk
+    /// they're from, so there are only as many draw calls as there are
+    /// colours on screen. Tiles with one of `textures` are filled in with
+    /// it, which takes a draw call for each colour and terrain instead.
+    pub fn draw<G, B>(&mut self,
+                      xs:        Range<usize>,
+                      ys:        Range<usize>,
+                      camera:    &Camera,
+                      ctx:       &Context,
+                      g:         &mut G,
+                      textures:  Option<&TileTextures<G::Texture>>,
+                      style:     &TileStyle,
+                      mut build: B)
+        where G: Graphics,
+              B: FnMut(Range<usize>, Range<usize>) -> Vec<Hexagon>
+    {
+        let to_chunks = |r: Range<usize>| {
+            r.start / CHUNK_SIZE..(r.end + CHUNK_SIZE - 1) / CHUNK_SIZE
+        };
+        let (columns, rows) = (to_chunks(xs), to_chunks(ys));
+        self.chunks.retain(|&[column, row], _| {
+            columns.contains(&column) && rows.contains(&row)
+        });
+
+        let to_screen = camera.ground_transform();
+        let viewport = camera.viewport();
+        let mut on_screen = Vec::new();
+        for row in rows {
+            for column in columns.clone() {
+                let start = [column * CHUNK_SIZE, row * CHUNK_SIZE];
+                let sides = chunk_bounds(start)
+                    .iter()
+                    .map(|&corner| to_screen.pos_mul(corner))
+                    .fold([false; 4], |seen, [x, y]| [
+                        seen[0] || x >= 0.0,
+                        seen[1] || x <= viewport[0],
+                        seen[2] || y >= 0.0,
+                        seen[3] || y <= viewport[1],
+                    ]);
+                if sides.contains(&false) {
+                    continue;
+                }
+
+                self.chunks.entry([column, row]).or_insert_with(|| {
+                    Chunk::new(build(
+                        start[0]..start[0] + CHUNK_SIZE,
+                        start[1]..start[1] + CHUNK_SIZE,
+                    ))
+                });
+                on_screen.push([column, row]);
+            }
+        }
+
+        // Tiles are lit according to which way they face on the screen, so
+        // each group is lit for the way that the camera is turned now.
+        let rotation = rot(camera.angle().radians());
+        let mut palette = Palette::default();
+        let mut fills: Map<FillKey, Vec<&[[f32; 2]]>> = Map::default();
+        let mut outlines: Vec<Vec<&[[f32; 4]]>> = Vec::new();
+        for key in on_screen.iter() {
+            let chunk = &self.chunks[key];
+            for (top, vertices) in chunk.fills.iter() {
+                let color = style.top_color(top, &rotation);
+                let key = (palette.index_of(color), top.terrain);
+                fills.entry(key).or_default().push(vertices);
+            }
+
+            for &(visibility, ref vertices) in chunk.outlines.iter() {
+                // Transparent outlines are as good as none.
+                let color = style.outline_color(visibility);
+                if color[3] <= 0.0 {
+                    continue;
+                }
+
+                let i = palette.index_of(color);
+                if outlines.len() <= i {
+                    outlines.resize(i + 1, Vec::new());
+                }
+                outlines[i].push(vertices);
+            }
+        }
+
+        // Outlines are the same number of pixels wide however far in the
+        // camera is zoomed, and go on top.
+        let to_ndc = to_screen * m(ctx.transform);
+        let half_width = OUTLINE_WIDTH / 2.0 / camera.scale_factor();
+        let colors = &palette.colors;
+        draw_fills(&fills, colors, textures, &to_ndc, ctx, g);
+        for (parts, color) in outlines.iter().zip(colors.iter()) {
+            if parts.is_empty() {
+                continue;
+            }
+
+            let offset = |&[x, y, across_x, across_y]: &[f32; 4]| {
+                let along = |v: f32, across: f32| {
+                    f64::from(v) + f64::from(across) * half_width
+                };
+
+                let v = [along(x, across_x), along(y, across_y)];
+
+                to_f32(to_ndc.pos_mul(v))
+            };
+            g.tri_list(&ctx.draw_state, color, |f| {
+                stream(parts, BACK_END_MAX_VERTEX_COUNT, &offset, |v| f(v));
+            });
+        }
+    }
+}
+
+impl Chunk {
+    fn new(hexagons: Vec<Hexagon>) -> Self {
+        let mut chunk = Chunk::default();
+
+        for (corners, top) in hexagons {
+            group(&mut chunk.fills, top)
+                .extend(HEXAGON_FAN.iter().map(|&k| to_f32(corners[k])));
+
+            let edges = group(&mut chunk.outlines, top.visibility);
+            for (k, &from) in corners.iter().enumerate() {
+                let to = corners[(k + 1) % corners.len()];
+                edges.extend(edge_quad(from, to).iter());
+            }
+        }
+
+        chunk
+    }
+}
+
+impl Palette {
+    fn index_of(&mut self, color: Color) -> usize {
+        let channel = |c: f32| (c.max(0.0).min(1.0) * 255.0).round() as u8;
+        let key = [
+            channel(color[0]),
+            channel(color[1]),
+            channel(color[2]),
+            channel(color[3]),
+        ];
+
+        let colors = &mut self.colors;
+        *self.index.entry(key).or_insert_with(|| {
+            colors.push(color);
+
+            colors.len() - 1
+        })
+    }
+}
+
+
+/// The group with the given key, which is added on the end if there isn't
+/// one yet.
+fn group<K: PartialEq, V>(groups: &mut Vec<(K, Vec<V>)>,
+                          key:    K) -> &mut Vec<V>
+{
+    let i = match groups.iter().position(|(k, _)| *k == key) {
+        Some(i) => i,
+        None => {
+            groups.push((key, Vec::new()));
+
+            groups.len() - 1
+        },
+    };
+
+    &mut groups[i].1
+}
+
+/// Corners, in hex grid units, of a rectangle around every hex of the chunk
+/// whose first hex has the given rectangular coordinates.
+fn chunk_bounds(start: [usize; 2]) -> [Vec2d; 4] {
+    let [x, y] = [start[0] as f64, start[1] as f64];
+    let size = CHUNK_SIZE as f64;
+    // Hexes are a unit from their centers to their corners, and every other
+    // column is shifted half a hex down.
+    let (left, right) = (1.5 * x - 1.0, 1.5 * (x + size) + 1.0);
+    let (top, bottom) = (SQRT_3 * y - 1.0, SQRT_3 * (y + size + 0.5) + 1.0);
+
+    [[left, top], [right, top], [right, bottom], [left, bottom]]
+}
+
+/// The two triangles of a line between the given points, laid out as in
+/// `Chunk::outlines`.
+fn edge_quad(from: Vec2d, to: Vec2d) -> [[f32; 4]; 6] {
+    let along = sub(to, from);
+    let len = along[0].hypot(along[1]).max(::std::f64::EPSILON);
+    let across = [-along[1] / len, along[0] / len];
+    let vertex = |[x, y]: Vec2d, side: f64| [
+        x as f32,
+        y as f32,
+        (side * across[0]) as f32,
+        (side * across[1]) as f32,
+    ];
+    let corners = [
+        vertex(from, 1.0),
+        vertex(to, 1.0),
+        vertex(to, -1.0),
+        vertex(from, -1.0),
+    ];
+
+    [
+        corners[0], corners[1], corners[2],
+        corners[0], corners[2], corners[3],
+    ]
+}
+
+/// Draws each batch of fills in its colour, with the fills of tiles that
+/// have one of `textures` filled in with it.
+fn draw_fills<G: Graphics>(batches:  &Map<FillKey, Vec<&[[f32; 2]]>>,
+                           colors:   &[Color],
+                           textures: Option<&TileTextures<G::Texture>>,
+                           to_ndc:   &Matrix,
+                           ctx:      &Context,
+                           g:        &mut G)
+{
+    let transform = |&[x, y]: &[f32; 2]| {
+        to_f32(to_ndc.pos_mul([f64::from(x), f64::from(y)]))
+    };
+
+    let mut uvs = Vec::new();
+    for (&(i, terrain), parts) in batches.iter() {
+        let color = &colors[i];
+        match textures.and_then(|t| t.get(&terrain)) {
+            Some(texture) => {
+                if uvs.is_empty() {
+                    uvs = hexagon_uvs()
+                        .iter()
+                        .cloned()
+                        .cycle()
+                        .take(TEXTURED_PART)
+                        .collect();
+                }
+                g.tri_list_uv(&ctx.draw_state, color, texture, |f| {
+                    stream(parts, TEXTURED_PART, &transform, |v| {
+                        f(v, &uvs[..v.len()])
+                    });
+                });
+            },
+            None => g.tri_list(&ctx.draw_state, color, |f| {
+                stream(parts, BACK_END_MAX_VERTEX_COUNT, &transform, |v| f(v));
+            }),
+        }
+    }
+}
+
+/// Hands every vertex of `parts` over to `f`, no more than `limit` at a
+/// time, once `transform` has taken it onto the screen.
+fn stream<V, T, F>(parts: &[&[V]], limit: usize, transform: &T, mut f: F)
+    where T: Fn(&V) -> [f32; 2],
+          F: FnMut(&[[f32; 2]])
+{
+    let mut buffer = [[0.0; 2]; BACK_END_MAX_VERTEX_COUNT];
+    let mut len = 0;
+    for vertex in parts.iter().flat_map(|part| part.iter()) {
+        buffer[len] = transform(vertex);
+        len += 1;
+        if len == limit {
+            f(&buffer[..len]);
+            len = 0;
+        }
+    }
+    if len > 0 {
+        f(&buffer[..len]);
+    }
+}
+
+fn to_f32(v: Vec2d) -> [f32; 2] {
+    [v[0] as f32, v[1] as f32]
+}
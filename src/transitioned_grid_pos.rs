@@ -1,5 +1,10 @@
-use geometry::{Angle, bezier2, cube_lerp, CubePoint};
-use std::ops::{AddAssign, SubAssign};
+use easing::Easing;
+use geometry::{Angle, cube_lerp, CubePoint};
+use std::{
+    collections::VecDeque,
+    ops::{AddAssign, SubAssign},
+    vec::Drain,
+};
 
 
 #[derive(Debug, Clone)]
@@ -24,6 +29,48 @@ pub struct TransitionedGridPos {
     /// Current progress of transition from `angle` to `target_angle`. `<= 0`
     /// is "just started", `>= 1` is "complete, no animation in progress".
     angle_state:  f64,
+    /// Easing curve that positional transitions follow.
+    pos_easing:   Easing,
+    /// Easing curve that rotational transitions follow.
+    angle_easing: Easing,
+    /// Waypoint currently being transitioned towards, if any.
+    waypoint:     Option<Waypoint>,
+    /// Waypoints to transition towards after the current one, in order.
+    queue:        VecDeque<Waypoint>,
+    /// Events that have happened since they were last drained.
+    events:       Vec<MovementEvent>,
+}
+
+/// A stop along a queued route. Either or both of the position and facing
+/// may be changed by a single waypoint, in which case they transition
+/// simultaneously.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Waypoint {
+    pub pos:    Option<CubePoint<i32>>,
+    pub facing: Option<Angle>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MovementEvent {
+    /// The given waypoint was reached.
+    Reached(Waypoint),
+    /// The last waypoint in the queue was reached.
+    QueueFinished,
+    /// Waypoints were cancelled before they were reached.
+    Cancelled {
+        /// How many waypoints were dropped.
+        dropped: usize,
+    },
+}
+
+/// What a position is doing right now, e.g. for picking an animation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    Idle,
+    /// Moving between tiles, whether or not it's turning at the same time.
+    Walking,
+    /// Turning on the spot.
+    Turning,
 }
 
 
@@ -39,9 +86,27 @@ impl TransitionedGridPos {
             target_angle: Angle::new(0.0),
             prev_angle:   Angle::new(0.0),
             angle_state:  0.0,
+            pos_easing:   Easing::default(),
+            angle_easing: Easing::default(),
+            waypoint:     None,
+            queue:        VecDeque::new(),
+            events:       Vec::new(),
         }
     }
 
+    /// Sets the easing curves used for translation and rotation,
+    /// respectively.
+    pub fn set_easing(&mut self, pos_easing: Easing, angle_easing: Easing) {
+        self.pos_easing = pos_easing;
+        self.angle_easing = angle_easing;
+    }
+
+    /// Sets the time that each animation phase takes, starting from the
+    /// next phase.
+    pub fn set_anim_time(&mut self, anim_time: f64) {
+        self.anim_time = anim_time;
+    }
+
     pub fn pos(&self) -> &CubePoint<f64> {
         &self.pos
     }
@@ -58,12 +123,131 @@ impl TransitionedGridPos {
         self.target_angle
     }
 
+    /// Progress, from `0` to `1`, of the current positional transition.
+    pub fn pos_state(&self) -> f64 {
+        self.pos_state
+    }
+
+    /// Progress, from `0` to `1`, of the current rotational transition.
+    pub fn angle_state(&self) -> f64 {
+        self.angle_state
+    }
+
+    /// Whether a positional transition is still underway. Transitions snap
+    /// to their target when they finish, so this is exact.
+    pub fn is_moving(&self) -> bool {
+        self.pos != self.target_pos.cast()
+    }
+
+    pub fn motion(&self) -> Motion {
+        if self.is_moving() {
+            Motion::Walking
+        } else if self.angle != self.target_angle {
+            Motion::Turning
+        } else {
+            Motion::Idle
+        }
+    }
+
+    /// Whether there is nothing left to do: no transitions underway and no
+    /// waypoints queued up.
+    pub fn is_idle(&self) -> bool {
+        !self.is_moving()
+            && self.angle == self.target_angle
+            && self.waypoint.is_none()
+            && self.queue.is_empty()
+    }
+
+    /// Waypoints that have yet to be reached, including the one currently
+    /// being transitioned towards.
+    pub fn waypoints(&self) -> impl Iterator<Item=&Waypoint> {
+        self.waypoint.iter().chain(self.queue.iter())
+    }
+
+    /// Adds a waypoint to the end of the queue.
+    pub fn push_waypoint(&mut self, waypoint: Waypoint) {
+        self.queue.push_back(waypoint);
+        self.advance_queue();
+    }
+
+    /// Adds several waypoints to the end of the queue, in order.
+    pub fn extend_waypoints<I>(&mut self, waypoints: I)
+        where I: IntoIterator<Item=Waypoint>
+    {
+        self.queue.extend(waypoints);
+        self.advance_queue();
+    }
+
+    /// Drops all waypoints that haven't been started on yet. The transition
+    /// towards the current waypoint is allowed to finish, so that this
+    /// doesn't get stranded in between hexes.
+    pub fn cancel_waypoints(&mut self) {
+        let dropped = self.queue.len();
+        if dropped > 0 {
+            self.queue.clear();
+            self.events.push(MovementEvent::Cancelled { dropped });
+        }
+    }
+
+    /// Estimate of the seconds left before every queued waypoint is reached,
+    /// assuming that each takes one full animation phase.
+    pub fn estimated_duration(&self) -> f64 {
+        let pos_left = if self.is_moving() {
+            (1.0 - self.pos_state).max(0.0)
+        } else {
+            0.0
+        };
+        let angle_left = if self.angle != self.target_angle {
+            (1.0 - self.angle_state).max(0.0)
+        } else {
+            0.0
+        };
+
+        self.anim_time *
+            (pos_left.max(angle_left) + self.queue.len() as f64)
+    }
+
+    /// Takes all of the events that have happened since the last call.
+    pub fn drain_events(&mut self) -> Drain<'_, MovementEvent> {
+        self.events.drain(..)
+    }
+
+    /// Sets the position to transition towards. This is independent of the
+    /// waypoint queue, which will carry on from wherever this leaves off.
     pub fn set_target_pos(&mut self, target: CubePoint<i32>) {
         self.pos_state = 0.0;
         self.prev_pos = self.pos;
         self.target_pos = target;
     }
 
+    pub fn set_target_angle(&mut self, target: Angle) {
+        if target != self.target_angle {
+            self.angle_state = 0.0;
+            self.prev_angle = self.angle;
+            self.target_angle = target;
+        }
+    }
+
+    /// Instantly puts this at the given position and angle, abandoning any
+    /// transitions that are in progress and any queued waypoints.
+    pub fn teleport(&mut self, pos: CubePoint<i32>, angle: Angle) {
+        let dropped = self.waypoints().count();
+        if dropped > 0 {
+            self.waypoint = None;
+            self.queue.clear();
+            self.events.push(MovementEvent::Cancelled { dropped });
+        }
+
+        self.pos = pos.cast();
+        self.target_pos = pos;
+        self.prev_pos = self.pos;
+        self.pos_state = 1.0;
+        self.angle = angle;
+        self.target_angle = angle;
+        self.prev_angle = angle;
+        self.angle_state = 1.0;
+    }
+
     pub fn inc_target_angle<A>(&mut self, increment: A)
         where Angle: AddAssign<A>
     {
@@ -80,6 +264,14 @@ impl TransitionedGridPos {
         self.target_angle -= decrement;
     }
 
+    /// Turns by `radians` straight away, on top of any rotation that's in
+    /// progress.
+    pub fn rotate_now(&mut self, radians: f64) {
+        self.angle += radians;
+        self.prev_angle += radians;
+        self.target_angle += radians;
+    }
+
     pub fn step(&mut self, dt: f64) {
         let target_pos_cast = self.target_pos.cast();
 
@@ -92,18 +284,20 @@ impl TransitionedGridPos {
             } else {
                 self.pos_state += dt / self.anim_time;
 
-                let new_pos_progress = bezier2(
-                    0.0,
-                    0.75,
-                    1.0,
-                    self.pos_state.min(1.0)
-                );
-
-                self.pos = cube_lerp(
-                    self.prev_pos,
-                    self.target_pos,
-                    new_pos_progress
-                );
+                if self.pos_state >= 1.0 {
+                    // Snapped rather than lerped, so that it lands exactly
+                    // on the target and stops moving.
+                    self.pos = target_pos_cast;
+                } else {
+                    let new_pos_progress =
+                        self.pos_easing.apply(self.pos_state);
+
+                    self.pos = cube_lerp(
+                        self.prev_pos,
+                        self.target_pos,
+                        new_pos_progress
+                    );
+                }
             }
         }
 
@@ -116,18 +310,150 @@ impl TransitionedGridPos {
             } else {
                 self.angle_state += dt / self.anim_time;
 
-                let new_angle_progress = bezier2(
-                    0.0,
-                    0.75,
-                    1.0,
-                    self.angle_state.min(1.0)
-                );
-
-                self.angle = self.prev_angle.lerp(
-                    &self.target_angle,
-                    new_angle_progress
-                );
+                if self.angle_state >= 1.0 {
+                    self.angle = self.target_angle;
+                } else {
+                    let new_angle_progress =
+                        self.angle_easing.apply(self.angle_state);
+
+                    self.angle = self.prev_angle.lerp(
+                        &self.target_angle,
+                        new_angle_progress
+                    );
+                }
+            }
+        }
+
+        self.advance_queue();
+    }
+
+    /// Checks whether the current waypoint has been reached, and if so, moves
+    /// on to the next one.
+    fn advance_queue(&mut self) {
+        if let Some(waypoint) = self.waypoint {
+            // Transitions may have been redirected since this waypoint was
+            // started on, so just wait for them to settle.
+            let pos_reached = waypoint.pos.is_none() || !self.is_moving();
+            let facing_reached = waypoint.facing.is_none()
+                || self.angle == self.target_angle;
+
+            if !(pos_reached && facing_reached) {
+                return;
             }
+
+            self.waypoint = None;
+            self.events.push(MovementEvent::Reached(waypoint));
+            if self.queue.is_empty() {
+                self.events.push(MovementEvent::QueueFinished);
+            }
+        }
+
+        if let Some(next) = self.queue.pop_front() {
+            if let Some(pos) = next.pos {
+                self.set_target_pos(pos);
+            }
+            if let Some(facing) = next.facing {
+                self.set_target_angle(facing);
+            }
+
+            self.waypoint = Some(next);
+        }
+    }
+}
+
+impl Waypoint {
+    /// Waypoint that moves to the given position, keeping the same facing.
+    pub fn to(pos: CubePoint<i32>) -> Self {
+        Waypoint {
+            pos:    Some(pos),
+            facing: None,
+        }
+    }
+
+    /// Waypoint that turns to face the given angle, staying in place.
+    pub fn face(facing: Angle) -> Self {
+        Waypoint {
+            pos:    None,
+            facing: Some(facing),
         }
     }
+
+    /// Also turns to face the given angle on the way to this waypoint.
+    pub fn facing(self, facing: Angle) -> Self {
+        Waypoint {
+            facing: Some(facing),
+            ..self
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Steps `pos` in quarters of a phase until it has nothing left to do,
+    /// returning the time that took.
+    fn step_until_idle(pos: &mut TransitionedGridPos) -> f64 {
+        let mut time = 0.0;
+        while !pos.is_idle() {
+            assert!(time < 10.0, "never finished");
+            pos.step(0.25);
+            time += 0.25;
+        }
+
+        time
+    }
+
+    #[test]
+    fn waypoints_are_reached_in_order() {
+        let origin = CubePoint::new(0.0, 0.0, 0.0);
+        let mut pos = TransitionedGridPos::new(1.0, origin);
+        let first = Waypoint::to(CubePoint::new(1, -1, 0));
+        let second = Waypoint::to(CubePoint::new(2, -1, -1))
+            .facing(Angle::new(1.0));
+        pos.extend_waypoints(vec![first, second]);
+
+        let estimate = pos.estimated_duration();
+        let time = step_until_idle(&mut pos);
+
+        assert_eq!(time, estimate);
+        assert_eq!(
+            pos.drain_events().collect::<Vec<_>>(),
+            vec![
+                MovementEvent::Reached(first),
+                MovementEvent::Reached(second),
+                MovementEvent::QueueFinished,
+            ],
+        );
+        assert_eq!(*pos.pos(), CubePoint::new(2.0, -1.0, -1.0));
+        assert_eq!(pos.angle(), Angle::new(1.0));
+        assert_eq!(pos.motion(), Motion::Idle);
+    }
+
+    #[test]
+    fn cancelling_finishes_the_current_waypoint() {
+        let origin = CubePoint::new(0.0, 0.0, 0.0);
+        let mut pos = TransitionedGridPos::new(1.0, origin);
+        let first = Waypoint::to(CubePoint::new(1, -1, 0));
+        pos.extend_waypoints(vec![
+            first,
+            Waypoint::to(CubePoint::new(2, -1, -1)),
+            Waypoint::face(Angle::new(2.0)),
+        ]);
+        pos.step(0.5);
+        pos.cancel_waypoints();
+        step_until_idle(&mut pos);
+
+        assert_eq!(
+            pos.drain_events().collect::<Vec<_>>(),
+            vec![
+                MovementEvent::Cancelled { dropped: 2 },
+                MovementEvent::Reached(first),
+                MovementEvent::QueueFinished,
+            ],
+        );
+        assert_eq!(*pos.pos(), CubePoint::new(1.0, -1.0, 0.0));
+        assert_eq!(pos.angle(), Angle::new(0.0));
+    }
 }
@@ -0,0 +1,448 @@
+//! Tweens and timelines for animating arbitrary properties.
+//!
+//! A `Timeline<P>` describes how some properties of a `P` change over time.
+//! The simplest timelines tween a single property from one value to another;
+//! these are then arranged in sequence or in parallel, delayed, looped, and
+//! so on. Timelines are stateless and are evaluated at an arbitrary point in
+//! time, so an `Animator` is what keeps track of how far along one is.
+
+use easing::Easing;
+use geometry::{cube_lerp, lerp, Angle, CubePoint};
+use graphics::{rectangle::Rectangle, types::Color};
+use std::{f64::INFINITY, rc::Rc};
+use temporal::Temporal;
+
+
+/// Values that can be smoothly interpolated between.
+pub trait Tweenable: Copy {
+    /// Interpolates between `from` and `to`, where `t = 0` yields `from` and
+    /// `t = 1` yields `to`. `t` may lie outside of `[0, 1]` for overshooting
+    /// easings.
+    fn tween(from: Self, to: Self, t: f64) -> Self;
+}
+
+/// Something that changes a property of a `P` over a fixed duration.
+pub trait Track<P> {
+    fn duration(&self) -> f64;
+
+    /// Sets the property to what it should be at time `t`, in seconds since
+    /// the start of this track.
+    fn apply(&self, target: &mut P, t: f64);
+}
+
+/// Tweens the property of a `P` that `prop` points to, from `from` to `to`.
+pub struct Tween<P, T> {
+    pub from:     T,
+    pub to:       T,
+    pub duration: f64,
+    pub easing:   Easing,
+    pub prop:     fn(&mut P) -> &mut T,
+}
+
+#[derive(Clone)]
+pub enum Timeline<P> {
+    Track(Rc<dyn Track<P>>),
+    /// Does nothing for the given number of seconds.
+    Delay(f64),
+    /// Plays each timeline after the previous one has finished.
+    Sequence(Vec<Timeline<P>>),
+    /// Plays all of the timelines at once, lasting as long as the longest.
+    Parallel(Vec<Timeline<P>>),
+    /// Plays `body` over again, `times` times or forever.
+    Loop {
+        body:  Box<Timeline<P>>,
+        times: Option<u32>,
+    },
+    /// Plays `body` forwards and then backwards again, `times` times or
+    /// forever.
+    Yoyo {
+        body:  Box<Timeline<P>>,
+        times: Option<u32>,
+    },
+}
+
+/// Plays a timeline back.
+#[derive(Clone)]
+pub struct Animator<P> {
+    timeline: Timeline<P>,
+    elapsed:  f64,
+}
+
+/// Visual properties of an entity that can be animated independently of its
+/// position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Appearance {
+    /// Multiplied componentwise with the entity's colours.
+    pub tint:    Color,
+    /// Multiplied with the alpha of the entity's colours.
+    pub opacity: f64,
+    /// Multiplied with the entity's size.
+    pub scale:   f64,
+}
+
+
+impl Tweenable for f64 {
+    fn tween(from: Self, to: Self, t: f64) -> Self {
+        lerp(from, to, t)
+    }
+}
+
+impl Tweenable for f32 {
+    fn tween(from: Self, to: Self, t: f64) -> Self {
+        lerp(f64::from(from), f64::from(to), t) as f32
+    }
+}
+
+impl Tweenable for Color {
+    fn tween(from: Self, to: Self, t: f64) -> Self {
+        [
+            f32::tween(from[0], to[0], t),
+            f32::tween(from[1], to[1], t),
+            f32::tween(from[2], to[2], t),
+            f32::tween(from[3], to[3], t),
+        ]
+    }
+}
+
+impl Tweenable for Angle {
+    fn tween(from: Self, to: Self, t: f64) -> Self {
+        from.lerp(&to, t)
+    }
+}
+
+impl Tweenable for CubePoint<f64> {
+    fn tween(from: Self, to: Self, t: f64) -> Self {
+        cube_lerp(from, to, t)
+    }
+}
+
+impl<P, T: Tweenable> Track<P> for Tween<P, T> {
+    fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    fn apply(&self, target: &mut P, t: f64) {
+        let progress = if self.duration > 0.0 {
+            t / self.duration
+        } else {
+            1.0
+        };
+
+        *(self.prop)(target) =
+            T::tween(self.from, self.to, self.easing.apply(progress));
+    }
+}
+
+impl<P: 'static> Timeline<P> {
+    /// A timeline that tweens a single property.
+    pub fn tween<T>(prop:     fn(&mut P) -> &mut T,
+                    from:     T,
+                    to:       T,
+                    duration: f64,
+                    easing:   Easing) -> Self
+        where T: Tweenable + 'static
+    {
+        Timeline::Track(Rc::new(Tween { from, to, duration, easing, prop }))
+    }
+
+    /// Plays `next` after this.
+    pub fn then(self, next: Self) -> Self {
+        match self {
+            Timeline::Sequence(mut timelines) => {
+                timelines.push(next);
+                Timeline::Sequence(timelines)
+            },
+            _ => Timeline::Sequence(vec![self, next]),
+        }
+    }
+
+    /// Plays `other` at the same time as this.
+    pub fn with(self, other: Self) -> Self {
+        match self {
+            Timeline::Parallel(mut timelines) => {
+                timelines.push(other);
+                Timeline::Parallel(timelines)
+            },
+            _ => Timeline::Parallel(vec![self, other]),
+        }
+    }
+
+    /// Repeats this `times` times, or forever if `times` is `None`.
+    pub fn looped(self, times: Option<u32>) -> Self {
+        Timeline::Loop {
+            body: Box::new(self),
+            times,
+        }
+    }
+
+    /// Plays this forwards then backwards, `times` times, or forever if
+    /// `times` is `None`.
+    pub fn yoyo(self, times: Option<u32>) -> Self {
+        Timeline::Yoyo {
+            body: Box::new(self),
+            times,
+        }
+    }
+}
+
+impl<P> Timeline<P> {
+    /// Total duration, in seconds. Timelines that repeat forever last an
+    /// infinite amount of time.
+    pub fn duration(&self) -> f64 {
+        match *self {
+            Timeline::Track(ref track) => track.duration(),
+            Timeline::Delay(d) => d,
+            Timeline::Sequence(ref ts) => ts.iter().map(Self::duration).sum(),
+            Timeline::Parallel(ref ts) =>
+                ts.iter().map(Self::duration).fold(0.0, f64::max),
+            Timeline::Loop { ref body, times } =>
+                times.map_or(INFINITY, |n| f64::from(n) * body.duration()),
+            Timeline::Yoyo { ref body, times } => times
+                .map_or(INFINITY, |n| 2.0 * f64::from(n) * body.duration()),
+        }
+    }
+
+    /// Sets the properties of `target` to what they should be at time `t`,
+    /// in seconds since the start of this timeline. Times past the end of the
+    /// timeline leave everything as it is at the end.
+    pub fn apply(&self, target: &mut P, t: f64) {
+        let t = t.max(0.0);
+
+        match *self {
+            Timeline::Track(ref track) =>
+                track.apply(target, t.min(track.duration())),
+            Timeline::Delay(_) => (),
+            Timeline::Sequence(ref timelines) => {
+                // Timelines that haven't started yet are left alone, so that
+                // they don't clobber the ones that came before them.
+                let mut start = 0.0;
+                for timeline in timelines.iter() {
+                    if t < start {
+                        break;
+                    }
+
+                    timeline.apply(target, t - start);
+                    start += timeline.duration();
+                }
+            },
+            Timeline::Parallel(ref timelines) =>
+                for timeline in timelines.iter() {
+                    timeline.apply(target, t);
+                },
+            // Played no times at all, so there's nothing to show.
+            Timeline::Loop { times: Some(0), .. }
+            | Timeline::Yoyo { times: Some(0), .. } => (),
+            Timeline::Loop { ref body, times } => {
+                let d = body.duration();
+                let finished =
+                    times.map_or(false, |n| t >= f64::from(n) * d);
+
+                if finished || d <= 0.0 {
+                    body.apply(target, d);
+                } else {
+                    body.apply(target, t % d);
+                }
+            },
+            Timeline::Yoyo { ref body, times } => {
+                let d = body.duration();
+                let finished =
+                    times.map_or(false, |n| t >= 2.0 * f64::from(n) * d);
+
+                if finished || d <= 0.0 {
+                    body.apply(target, 0.0);
+                } else {
+                    let local = t % (2.0 * d);
+                    if local < d {
+                        body.apply(target, local);
+                    } else {
+                        body.apply(target, 2.0 * d - local);
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl<P> Animator<P> {
+    pub fn new(timeline: Timeline<P>) -> Self {
+        Animator {
+            timeline,
+            elapsed: 0.0,
+        }
+    }
+
+    /// Sets the properties of `target` to their values at the current point
+    /// in the timeline.
+    pub fn apply(&self, target: &mut P) {
+        self.timeline.apply(target, self.elapsed);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed >= self.timeline.duration()
+    }
+}
+
+impl<P> Temporal for Animator<P> {
+    fn step(&mut self, dt: f64) {
+        self.elapsed += dt;
+    }
+}
+
+impl Appearance {
+    /// Briefly tints towards `color` and then back again, e.g. when taking
+    /// damage.
+    pub fn flash(color: Color, duration: f64) -> Timeline<Self> {
+        Timeline::tween(
+            |a: &mut Self| &mut a.tint,
+            [1.0; 4],
+            color,
+            duration / 2.0,
+            Easing::CubicOut,
+        ).yoyo(Some(1))
+    }
+
+    /// Briefly grows to `scale` times the usual size and then shrinks back,
+    /// e.g. when attacking.
+    pub fn pulse(scale: f64, duration: f64) -> Timeline<Self> {
+        Timeline::tween(
+            |a: &mut Self| &mut a.scale,
+            1.0,
+            scale,
+            duration / 2.0,
+            Easing::CubicOut,
+        ).yoyo(Some(1))
+    }
+
+    /// Applies this appearance to a colour.
+    pub fn color(&self, color: Color) -> Color {
+        [
+            color[0] * self.tint[0],
+            color[1] * self.tint[1],
+            color[2] * self.tint[2],
+            color[3] * self.tint[3] * self.opacity as f32,
+        ]
+    }
+
+    /// Applies this appearance to both the fill and the border of a
+    /// rectangle.
+    pub fn rect(&self, rect: &Rectangle) -> Rectangle {
+        let mut rect = rect.color(self.color(rect.color));
+        if let Some(ref mut border) = rect.border {
+            border.color = self.color(border.color);
+        }
+
+        rect
+    }
+}
+
+impl Default for Appearance {
+    fn default() -> Self {
+        Appearance {
+            tint:    [1.0; 4],
+            opacity: 1.0,
+            scale:   1.0,
+        }
+    }
+}
+
+
+/// Steps all of the given effects, applies them on top of `base`, and
+/// forgets about any that have finished.
+pub fn step_effects<P: Clone>(effects: &mut Vec<Animator<P>>,
+                              base:    &P,
+                              dt:      f64) -> P
+{
+    let mut animated = base.clone();
+    for effect in effects.iter_mut() {
+        effect.step(dt);
+        effect.apply(&mut animated);
+    }
+    effects.retain(|e| !e.is_finished());
+
+    animated
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Point {
+        x: f64,
+        y: f64,
+    }
+
+    fn x(p: &mut Point) -> &mut f64 {
+        &mut p.x
+    }
+
+    fn y(p: &mut Point) -> &mut f64 {
+        &mut p.y
+    }
+
+    /// The point that `timeline` leaves the origin at, at time `t`.
+    fn at(timeline: &Timeline<Point>, t: f64) -> Point {
+        let mut p = Point { x: 0.0, y: 0.0 };
+        timeline.apply(&mut p, t);
+        p
+    }
+
+    #[test]
+    fn sequence_hands_off() {
+        let timeline = Timeline::tween(x, 0.0, 1.0, 1.0, Easing::Linear)
+            .then(Timeline::Delay(1.0))
+            .then(Timeline::tween(x, 10.0, 20.0, 1.0, Easing::Linear));
+
+        assert_eq!(timeline.duration(), 3.0);
+        assert_eq!(at(&timeline, 0.5).x, 0.5);
+        assert_eq!(at(&timeline, 1.5).x, 1.0);
+        assert_eq!(at(&timeline, 2.5).x, 15.0);
+        assert_eq!(at(&timeline, 5.0).x, 20.0);
+    }
+
+    #[test]
+    fn parallel_lasts_as_long_as_the_longest() {
+        let timeline = Timeline::tween(x, 0.0, 1.0, 1.0, Easing::Linear)
+            .with(Timeline::tween(y, 0.0, 4.0, 2.0, Easing::Linear));
+
+        assert_eq!(timeline.duration(), 2.0);
+        assert_eq!(at(&timeline, 0.5), Point { x: 0.5, y: 1.0 });
+        assert_eq!(at(&timeline, 1.5), Point { x: 1.0, y: 3.0 });
+        assert_eq!(at(&timeline, 5.0), Point { x: 1.0, y: 4.0 });
+    }
+
+    #[test]
+    fn loop_wraps_around() {
+        let timeline = Timeline::tween(x, 0.0, 1.0, 1.0, Easing::Linear)
+            .looped(Some(3));
+
+        assert_eq!(timeline.duration(), 3.0);
+        assert_eq!(at(&timeline, 0.25).x, 0.25);
+        assert_eq!(at(&timeline, 2.25).x, 0.25);
+        assert_eq!(at(&timeline, 5.0).x, 1.0);
+    }
+
+    #[test]
+    fn loop_played_no_times_does_nothing() {
+        let timeline = Timeline::tween(x, 5.0, 6.0, 1.0, Easing::Linear)
+            .looped(Some(0));
+
+        assert_eq!(timeline.duration(), 0.0);
+        assert_eq!(at(&timeline, 0.0).x, 0.0);
+        assert_eq!(at(&timeline, 1.0).x, 0.0);
+    }
+
+    #[test]
+    fn yoyo_reverses() {
+        let timeline = Timeline::tween(x, 0.0, 1.0, 1.0, Easing::Linear)
+            .yoyo(Some(2));
+
+        assert_eq!(timeline.duration(), 4.0);
+        assert_eq!(at(&timeline, 0.25).x, 0.25);
+        assert_eq!(at(&timeline, 1.75).x, 0.25);
+        assert_eq!(at(&timeline, 2.5).x, 0.5);
+        assert_eq!(at(&timeline, 3.75).x, 0.25);
+        assert_eq!(at(&timeline, 5.0).x, 0.0);
+    }
+}
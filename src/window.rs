@@ -5,22 +5,22 @@ use piston::{
     window::{Api, Window, WindowSettings},
 };
 use sdl2_window::{OpenGL, Sdl2Window};
+use settings::Graphics;
 
-pub const WINDOW_WIDTH: u32 = 1_366;
-pub const WINDOW_HEIGHT: u32 = 768;
-
-pub const HALF_WINDOW_WIDTH: f64 = WINDOW_WIDTH as f64 / 2.0;
-pub const HALF_WINDOW_HEIGHT: f64 = WINDOW_HEIGHT as f64 / 2.0;
+pub const TITLE: &str = "ahistorics";
 
 pub const OPENGL: OpenGL = OpenGL::V4_5;
 
 /// Sets up the window.
-pub fn init() -> Result<Sdl2Window, Error> {
+pub fn init(graphics: &Graphics) -> Result<Sdl2Window, Error> {
     let window_settings =
-        WindowSettings::new("ahistorics", [WINDOW_WIDTH, WINDOW_HEIGHT])
-            .exit_on_esc(true)
-            .vsync(true)
-            .samples(4)
+        WindowSettings::new(TITLE, [graphics.width, graphics.height])
+            // Escape closes the options menu first, if it's open, so
+            // quitting on it is handled by the main loop instead.
+            .exit_on_esc(false)
+            .fullscreen(graphics.fullscreen)
+            .vsync(graphics.vsync)
+            .samples(graphics.samples)
             .graphics_api(Api::opengl(
                 OPENGL.get_major_minor().0 as u32,
                 OPENGL.get_major_minor().1 as u32,
@@ -32,9 +32,9 @@ pub fn init() -> Result<Sdl2Window, Error> {
 }
 
 /// Sets up events for the window.
-pub fn events() -> Events {
+pub fn events(graphics: &Graphics) -> Events {
     let event_settings = EventSettings {
-        ups: 60,
+        ups: graphics.ups,
         ..EventSettings::new()
     };
 